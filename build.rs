@@ -0,0 +1,807 @@
+/*
+   Generates the register map consumed by src/registers.rs from the CMSIS-SVD descriptions in
+   svd/, the device file with the core peripherals of cortex-m4-core.svd laid over it. Only the
+   parts of the SVD format the drivers need are understood: peripherals, clusters and registers
+   (with derivedFrom and dim arrays) and fields given as bitOffset/bitWidth, lsb/msb or
+   bitRange.
+
+   The interrupts of all peripherals become the Interrupt enum, the device part of the vector
+   table and a linker script fragment pointing every handler nobody defined at DefaultHandler.
+
+   The crate has no dependencies, so the XML reader below is a small hand written one.
+*/
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+const DEVICE_SVD_PATH: &str = "svd/STM32F439x.svd";
+const CORE_SVD_PATH: &str = "svd/cortex-m4-core.svd";
+
+// Fields the vendor file splits into bits (PLLM0..PLLM5), merged into the ones the drivers use
+const SPLIT_FIELDS: &[(&str, &str, &[&str])] = &[
+    ("RCC", "PLLCFGR", &["PLLM", "PLLN", "PLLP", "PLLQ"]),
+    ("RCC", "CFGR", &["SW", "SWS"]),
+    ("RCC", "PLLI2SCFGR", &["PLLI2SN", "PLLI2SR"]),
+];
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed={DEVICE_SVD_PATH}");
+    println!("cargo:rerun-if-changed={CORE_SVD_PATH}");
+    println!("cargo:rerun-if-changed=ld");
+
+    let mut peripherals = svd::overlay(read_svd(DEVICE_SVD_PATH), read_svd(CORE_SVD_PATH));
+    svd::merge_split_fields(&mut peripherals, SPLIT_FIELDS)
+        .unwrap_or_else(|error| panic!("{DEVICE_SVD_PATH}: {error}"));
+
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR is set by cargo");
+    let out_dir = Path::new(&out_dir);
+    fs::write(out_dir.join("registers.rs"), generate(&peripherals))
+        .expect("cannot write generated registers");
+
+    let interrupts =
+        svd::interrupts(&peripherals).unwrap_or_else(|error| panic!("{DEVICE_SVD_PATH}: {error}"));
+    fs::write(
+        out_dir.join("interrupts.rs"),
+        generate_interrupts(&interrupts),
+    )
+    .expect("cannot write generated interrupts");
+    fs::write(out_dir.join("vectors.rs"), generate_vectors(&interrupts))
+        .expect("cannot write generated vector table");
+    fs::write(
+        out_dir.join("interrupts.x"),
+        generate_linker_fragment(&interrupts),
+    )
+    .expect("cannot write generated linker fragment");
+    // The linker scripts INCLUDE sections.ld and interrupts.x
+    println!("cargo:rustc-link-search={}", out_dir.display());
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR is set by cargo");
+    println!("cargo:rustc-link-search={manifest_dir}/ld");
+    // Examples are applications started by the bootloader, host builds link with the default linker
+    if env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("none") {
+        println!("cargo:rustc-link-arg-examples=-TSTM32F439ZITX_FLASH.ld");
+        println!("cargo:rustc-link-arg-bin=bootloader=-TSTM32F439ZITX_BOOTLOADER.ld");
+    }
+}
+
+fn read_svd(path: &str) -> Vec<svd::Peripheral> {
+    let source = fs::read_to_string(path).unwrap_or_else(|_| panic!("{path} is missing"));
+    let device = xml::parse(&source).unwrap_or_else(|error| panic!("{path}: {error}"));
+    svd::read_peripherals(&device).unwrap_or_else(|error| panic!("{path}: {error}"))
+}
+
+fn generate(peripherals: &[svd::Peripheral]) -> String {
+    let mut output = String::new();
+    writeln!(
+        output,
+        "// Generated by build.rs from {DEVICE_SVD_PATH} and {CORE_SVD_PATH}, do not edit."
+    )
+    .unwrap();
+    for peripheral in peripherals {
+        let module = peripheral.name.to_lowercase();
+        writeln!(output).unwrap();
+        if let Some(description) = &peripheral.description {
+            writeln!(output, "/// {description}").unwrap();
+        }
+        writeln!(output, "pub mod {module} {{").unwrap();
+        if let Some(parent) = &peripheral.derived_from {
+            // Not every instance of a shared layout is referenced by the drivers
+            writeln!(output, "    #[allow(unused_imports)]").unwrap();
+            writeln!(output, "    pub use super::{}::*;", parent.to_lowercase()).unwrap();
+            writeln!(output).unwrap();
+        } else if !peripheral.registers.is_empty() {
+            writeln!(output, "    use crate::register::Register;").unwrap();
+            writeln!(output).unwrap();
+        }
+        writeln!(
+            output,
+            "    pub const BASE_ADDRESS: u32 = {};",
+            hex(peripheral.base_address)
+        )
+        .unwrap();
+        for register in &peripheral.registers {
+            writeln!(output).unwrap();
+            writeln!(output, "    /// {}", register.description).unwrap();
+            writeln!(
+                output,
+                "    pub const {}: Register = Register::new({:#04X}, {});",
+                constant_name(&register.name),
+                register.offset,
+                hex(register.reset_value)
+            )
+            .unwrap();
+        }
+        for register in peripheral.registers.iter().filter(|r| !r.fields.is_empty()) {
+            writeln!(output).unwrap();
+            writeln!(output, "    pub mod {} {{", register.name.to_lowercase()).unwrap();
+            writeln!(output, "        use crate::register::Field;").unwrap();
+            for field in &register.fields {
+                writeln!(output).unwrap();
+                writeln!(output, "        /// {}", field.description).unwrap();
+                writeln!(
+                    output,
+                    "        pub const {}: Field = Field::new({}, {});",
+                    constant_name(&field.name),
+                    field.offset,
+                    field.width
+                )
+                .unwrap();
+            }
+            writeln!(output, "    }}").unwrap();
+        }
+        writeln!(output, "}}").unwrap();
+    }
+    generate_descriptions(peripherals, &mut output);
+    output
+}
+
+// Names of every register and field by address, used to decode recorded register accesses
+fn generate_descriptions(peripherals: &[svd::Peripheral], output: &mut String) {
+    writeln!(output).unwrap();
+    writeln!(
+        output,
+        "pub const REGISTER_DESCRIPTIONS: &[crate::register::RegisterDescription] = &["
+    )
+    .unwrap();
+    for peripheral in peripherals {
+        let layout = match &peripheral.derived_from {
+            Some(parent) => peripherals
+                .iter()
+                .find(|candidate| &candidate.name == parent)
+                .unwrap_or_else(|| panic!("{}: unknown parent {parent}", peripheral.name)),
+            None => peripheral,
+        };
+        let module = peripheral.name.to_lowercase();
+        for register in &layout.registers {
+            writeln!(output, "    crate::register::RegisterDescription {{").unwrap();
+            writeln!(output, "        peripheral: \"{}\",", peripheral.name).unwrap();
+            writeln!(output, "        register: \"{}\",", register.name).unwrap();
+            writeln!(
+                output,
+                "        address: {},",
+                hex(peripheral.base_address + register.offset)
+            )
+            .unwrap();
+            writeln!(output, "        fields: &[").unwrap();
+            for field in &register.fields {
+                writeln!(
+                    output,
+                    "            (\"{}\", {module}::{}::{}),",
+                    field.name,
+                    register.name.to_lowercase(),
+                    constant_name(&field.name)
+                )
+                .unwrap();
+            }
+            writeln!(output, "        ],").unwrap();
+            writeln!(output, "    }},").unwrap();
+        }
+    }
+    writeln!(output, "];").unwrap();
+}
+
+fn generate_interrupts(interrupts: &[&svd::Interrupt]) -> String {
+    let mut output = String::new();
+    writeln!(
+        output,
+        "// Generated by build.rs from {DEVICE_SVD_PATH}, do not edit."
+    )
+    .unwrap();
+    writeln!(output).unwrap();
+    writeln!(output, "#[repr(u32)]").unwrap();
+    writeln!(output, "#[derive(Clone, Copy, Debug, PartialEq, Eq)]").unwrap();
+    writeln!(output, "pub enum Interrupt {{").unwrap();
+    for interrupt in interrupts {
+        writeln!(output, "    /// {}", interrupt.description).unwrap();
+        writeln!(
+            output,
+            "    {} = {},",
+            variant_name(&interrupt.name),
+            interrupt.value
+        )
+        .unwrap();
+    }
+    writeln!(output, "}}").unwrap();
+    output
+}
+
+// Device part of the vector table, handlers are linked by the name of their Interrupt variant
+fn generate_vectors(interrupts: &[&svd::Interrupt]) -> String {
+    let mut output = String::new();
+    writeln!(
+        output,
+        "// Generated by build.rs from {DEVICE_SVD_PATH}, do not edit."
+    )
+    .unwrap();
+    writeln!(output).unwrap();
+    writeln!(output, "#[allow(non_snake_case)]").unwrap();
+    writeln!(output, "extern \"C\" {{").unwrap();
+    for interrupt in interrupts {
+        writeln!(output, "    fn {}();", variant_name(&interrupt.name)).unwrap();
+    }
+    writeln!(output, "}}").unwrap();
+    writeln!(output).unwrap();
+    writeln!(output, "#[no_mangle]").unwrap();
+    writeln!(output, "#[link_section = \".vector_table.interrupts\"]").unwrap();
+    writeln!(
+        output,
+        "pub static INTERRUPTS: [Vector; {}] = [",
+        interrupts.len()
+    )
+    .unwrap();
+    for interrupt in interrupts {
+        writeln!(
+            output,
+            "    Vector {{ handler: {} }},",
+            variant_name(&interrupt.name)
+        )
+        .unwrap();
+    }
+    writeln!(output, "];").unwrap();
+    output
+}
+
+fn generate_linker_fragment(interrupts: &[&svd::Interrupt]) -> String {
+    let mut output = String::new();
+    writeln!(
+        output,
+        "/* Generated by build.rs from {DEVICE_SVD_PATH}, do not edit. */"
+    )
+    .unwrap();
+    for interrupt in interrupts {
+        writeln!(
+            output,
+            "PROVIDE({} = DefaultHandler);",
+            variant_name(&interrupt.name)
+        )
+        .unwrap();
+    }
+    output
+}
+
+/*
+   EXTI15_10 -> Exti15_10, DMA1_Stream0 -> Dma1Stream0. An underscore is only kept between
+   two digits, where dropping it would merge the numbers.
+*/
+fn variant_name(name: &str) -> String {
+    let mut variant = String::new();
+    for part in name.split('_').filter(|part| !part.is_empty()) {
+        let previous_digit = variant.ends_with(|c: char| c.is_ascii_digit());
+        if previous_digit && part.starts_with(|c: char| c.is_ascii_digit()) {
+            variant.push('_');
+        }
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            variant.push(first.to_ascii_uppercase());
+            variant.extend(chars.map(|c| c.to_ascii_lowercase()));
+        }
+    }
+    variant
+}
+
+fn constant_name(name: &str) -> String {
+    name.to_uppercase()
+}
+
+fn hex(value: u32) -> String {
+    format!("0x{:04X}_{:04X}", value >> 16, value & 0xFFFF)
+}
+
+mod svd {
+    use crate::xml::Element;
+
+    pub struct Peripheral {
+        pub name: String,
+        pub description: Option<String>,
+        pub base_address: u32,
+        pub derived_from: Option<String>,
+        pub registers: Vec<Register>,
+        pub interrupts: Vec<Interrupt>,
+    }
+
+    pub struct Interrupt {
+        pub name: String,
+        pub description: String,
+        pub value: u32,
+    }
+
+    #[derive(Clone)]
+    pub struct Register {
+        pub name: String,
+        pub description: String,
+        pub offset: u32,
+        pub reset_value: u32,
+        pub fields: Vec<Field>,
+    }
+
+    #[derive(Clone)]
+    pub struct Field {
+        pub name: String,
+        pub description: String,
+        pub offset: u32,
+        pub width: u32,
+    }
+
+    pub fn read_peripherals(device: &Element) -> Result<Vec<Peripheral>, String> {
+        let default_reset = match device.child_text("resetValue") {
+            Some(value) => parse_integer(value)?,
+            None => 0,
+        };
+        let peripherals = device
+            .child("peripherals")
+            .ok_or("device has no peripherals")?;
+        peripherals
+            .children("peripheral")
+            .map(|peripheral| read_peripheral(peripheral, default_reset))
+            .collect()
+    }
+
+    fn read_peripheral(element: &Element, default_reset: u32) -> Result<Peripheral, String> {
+        let name = required_text(element, "name")?.to_string();
+        let derived_from = element.attribute("derivedFrom").map(str::to_string);
+        let default_reset = match element.child_text("resetValue") {
+            Some(value) => parse_integer(value)?,
+            None => default_reset,
+        };
+        let mut registers = Vec::new();
+        if let Some(block) = element.child("registers") {
+            read_registers(block, "", 0, default_reset, &mut registers)
+                .map_err(|error| format!("{name}: {error}"))?;
+        }
+        let mut interrupts = Vec::new();
+        for interrupt in element.children("interrupt") {
+            let interrupt_name = required_text(interrupt, "name")?;
+            interrupts.push(Interrupt {
+                name: interrupt_name.to_string(),
+                description: interrupt
+                    .child_text("description")
+                    .map(normalize)
+                    .unwrap_or_else(|| interrupt_name.to_string()),
+                value: parse_integer(required_text(interrupt, "value")?)?,
+            });
+        }
+        Ok(Peripheral {
+            base_address: parse_integer(required_text(element, "baseAddress")?)?,
+            description: element.child_text("description").map(normalize),
+            derived_from,
+            registers,
+            interrupts,
+            name,
+        })
+    }
+
+    /*
+       A core peripheral replaces the device peripheral of the same name but keeps its
+       interrupts, vendor files list the FPU interrupt on their FPU peripheral.
+    */
+    pub fn overlay(mut device: Vec<Peripheral>, core: Vec<Peripheral>) -> Vec<Peripheral> {
+        for mut peripheral in core {
+            match device
+                .iter_mut()
+                .find(|candidate| candidate.name == peripheral.name)
+            {
+                Some(replaced) => {
+                    peripheral.interrupts.append(&mut replaced.interrupts);
+                    *replaced = peripheral;
+                }
+                None => device.push(peripheral),
+            }
+        }
+        device
+    }
+
+    pub fn merge_split_fields(
+        peripherals: &mut [Peripheral],
+        split_fields: &[(&str, &str, &[&str])],
+    ) -> Result<(), String> {
+        for (peripheral_name, register_name, field_names) in split_fields {
+            let register = peripherals
+                .iter_mut()
+                .filter(|peripheral| peripheral.name == *peripheral_name)
+                .flat_map(|peripheral| peripheral.registers.iter_mut())
+                .find(|register| register.name == *register_name);
+            let Some(register) = register else {
+                continue;
+            };
+            for field_name in *field_names {
+                merge_field(&mut register.fields, field_name)
+                    .map_err(|error| format!("{peripheral_name}.{register_name}: {error}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    // PLLM0..PLLM5 -> PLLM, the bits are adjacent and numbered from the lowest one
+    fn merge_field(fields: &mut Vec<Field>, name: &str) -> Result<(), String> {
+        let positions: Vec<usize> = fields
+            .iter()
+            .enumerate()
+            .filter(|(_, field)| {
+                field.name.strip_prefix(name).is_some_and(|number| {
+                    !number.is_empty() && number.bytes().all(|digit| digit.is_ascii_digit())
+                })
+            })
+            .map(|(position, _)| position)
+            .collect();
+        let Some(&first) = positions.first() else {
+            return Ok(());
+        };
+        let mut bits: Vec<(u32, &Field)> = Vec::new();
+        for &position in &positions {
+            let field = &fields[position];
+            bits.push((parse_integer(&field.name[name.len()..])?, field));
+        }
+        bits.sort_by_key(|(number, _)| *number);
+        let offset = bits[0].1.offset;
+        for (bit, (number, field)) in bits.iter().enumerate() {
+            if *number != bit as u32 || field.width != 1 || field.offset != offset + bit as u32 {
+                return Err(format!("{} is not bit {bit} of {name}", field.name));
+            }
+        }
+        let merged = Field {
+            name: name.to_string(),
+            description: bits[0].1.description.clone(),
+            offset,
+            width: bits.len() as u32,
+        };
+        for &position in positions.iter().rev() {
+            fields.remove(position);
+        }
+        fields.insert(first, merged);
+        Ok(())
+    }
+
+    /*
+       Interrupts of all peripherals ordered by their position in the vector table. The table
+       has no holes, a missing position is an incomplete SVD.
+    */
+    pub fn interrupts(peripherals: &[Peripheral]) -> Result<Vec<&Interrupt>, String> {
+        let mut interrupts: Vec<&Interrupt> = peripherals
+            .iter()
+            .flat_map(|peripheral| peripheral.interrupts.iter())
+            .collect();
+        interrupts.sort_by_key(|interrupt| interrupt.value);
+        interrupts.dedup_by_key(|interrupt| interrupt.value);
+        for (position, interrupt) in interrupts.iter().enumerate() {
+            if interrupt.value != position as u32 {
+                return Err(format!("no interrupt at position {position}"));
+            }
+        }
+        Ok(interrupts)
+    }
+
+    /*
+       Registers and clusters in document order. The registers of a cluster are named
+       CLUSTER_REGISTER and placed at the offset of the cluster, nested clusters add up.
+    */
+    fn read_registers(
+        block: &Element,
+        prefix: &str,
+        base: u32,
+        default_reset: u32,
+        registers: &mut Vec<Register>,
+    ) -> Result<(), String> {
+        for element in block.elements() {
+            match element.name.as_str() {
+                "register" => read_register(element, prefix, base, default_reset, registers)?,
+                "cluster" => read_cluster(block, element, prefix, base, default_reset, registers)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // A derived cluster takes the registers of a sibling cluster at its own offset
+    fn read_cluster(
+        block: &Element,
+        element: &Element,
+        prefix: &str,
+        base: u32,
+        default_reset: u32,
+        registers: &mut Vec<Register>,
+    ) -> Result<(), String> {
+        let name = required_text(element, "name")?;
+        let offset = base + parse_integer(required_text(element, "addressOffset")?)?;
+        let default_reset = match element.child_text("resetValue") {
+            Some(value) => parse_integer(value)?,
+            None => default_reset,
+        };
+        let layout = match element.attribute("derivedFrom") {
+            Some(parent) => block
+                .children("cluster")
+                .find(|cluster| cluster.child_text("name") == Some(parent))
+                .ok_or_else(|| format!("{name}: unknown parent cluster {parent}"))?,
+            None => element,
+        };
+        for (cluster, offset) in instances(element, name, offset)? {
+            read_registers(
+                layout,
+                &format!("{prefix}{cluster}_"),
+                offset,
+                default_reset,
+                registers,
+            )
+            .map_err(|error| format!("{cluster}: {error}"))?;
+        }
+        Ok(())
+    }
+
+    // A derived register takes what it does not describe itself from an earlier register
+    fn read_register(
+        element: &Element,
+        prefix: &str,
+        base: u32,
+        default_reset: u32,
+        registers: &mut Vec<Register>,
+    ) -> Result<(), String> {
+        let name = required_text(element, "name")?;
+        let offset = base + parse_integer(required_text(element, "addressOffset")?)?;
+        let parent = match element.attribute("derivedFrom") {
+            Some(parent) => {
+                // Either a register of the same block or a path ending in one
+                let parent = parent.rsplit('.').next().unwrap_or(parent);
+                let prefixed = format!("{prefix}{parent}");
+                let found = registers
+                    .iter()
+                    .find(|register| register.name == prefixed || register.name == parent);
+                Some(
+                    found
+                        .ok_or_else(|| format!("{name}: unknown parent register {parent}"))?
+                        .clone(),
+                )
+            }
+            None => None,
+        };
+        let reset_value = match (element.child_text("resetValue"), &parent) {
+            (Some(value), _) => parse_integer(value)?,
+            (None, Some(parent)) => parent.reset_value,
+            (None, None) => default_reset,
+        };
+        let description = match (element.child_text("description"), &parent) {
+            (Some(description), _) => normalize(description),
+            (None, Some(parent)) => parent.description.clone(),
+            (None, None) => name.to_string(),
+        };
+        let mut fields = Vec::new();
+        if let Some(block) = element.child("fields") {
+            for field in block.children("field") {
+                fields.push(read_field(field).map_err(|error| format!("{name}: {error}"))?);
+            }
+        } else if let Some(parent) = parent {
+            fields = parent.fields;
+        }
+
+        for (name, offset) in instances(element, name, offset)? {
+            registers.push(Register {
+                name: format!("{prefix}{name}"),
+                description: description.clone(),
+                offset,
+                reset_value,
+                fields: fields.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    // <dim> describes an array of identical registers or clusters, %s is replaced by the index
+    fn instances(element: &Element, name: &str, offset: u32) -> Result<Vec<(String, u32)>, String> {
+        let Some(dim) = element.child_text("dim") else {
+            return Ok(vec![(name.to_string(), offset)]);
+        };
+        let dim = parse_integer(dim)?;
+        let increment = parse_integer(required_text(element, "dimIncrement")?)?;
+        let indices = dim_indices(element.child_text("dimIndex"), dim)?;
+        // NAME[%s] is an array in the SVD, the generated constants are NAME0, NAME1, ...
+        let name = name.replace("[%s]", "%s");
+        Ok(indices
+            .iter()
+            .enumerate()
+            .map(|(position, index)| {
+                (
+                    name.replace("%s", index),
+                    offset + position as u32 * increment,
+                )
+            })
+            .collect())
+    }
+
+    fn dim_indices(dim_index: Option<&str>, dim: u32) -> Result<Vec<String>, String> {
+        let Some(dim_index) = dim_index else {
+            return Ok((0..dim).map(|index| index.to_string()).collect());
+        };
+        if let Some((first, last)) = dim_index.split_once('-') {
+            let first = parse_integer(first)?;
+            let last = parse_integer(last)?;
+            return Ok((first..=last).map(|index| index.to_string()).collect());
+        }
+        Ok(dim_index
+            .split(',')
+            .map(|index| index.trim().to_string())
+            .collect())
+    }
+
+    fn read_field(element: &Element) -> Result<Field, String> {
+        let name = required_text(element, "name")?.to_string();
+        let (offset, width) = if let Some(offset) = element.child_text("bitOffset") {
+            let width = match element.child_text("bitWidth") {
+                Some(width) => parse_integer(width)?,
+                None => 1,
+            };
+            (parse_integer(offset)?, width)
+        } else if let Some(lsb) = element.child_text("lsb") {
+            let lsb = parse_integer(lsb)?;
+            let msb = parse_integer(required_text(element, "msb")?)?;
+            (lsb, msb - lsb + 1)
+        } else if let Some(range) = element.child_text("bitRange") {
+            let (msb, lsb) = range
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .split_once(':')
+                .ok_or_else(|| format!("{name}: malformed bitRange {range}"))?;
+            let lsb = parse_integer(lsb)?;
+            (lsb, parse_integer(msb)? - lsb + 1)
+        } else {
+            return Err(format!("{name}: field has no bit position"));
+        };
+        if width == 0 || offset + width > 32 {
+            return Err(format!("{name}: field does not fit in 32 bits"));
+        }
+        Ok(Field {
+            description: element
+                .child_text("description")
+                .map(normalize)
+                .unwrap_or_else(|| name.clone()),
+            name,
+            offset,
+            width,
+        })
+    }
+
+    fn required_text<'a>(element: &'a Element, name: &str) -> Result<&'a str, String> {
+        element
+            .child_text(name)
+            .ok_or_else(|| format!("<{}> without <{name}>", element.name))
+    }
+
+    fn normalize(text: &str) -> String {
+        text.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    fn parse_integer(text: &str) -> Result<u32, String> {
+        let text = text.trim();
+        let parsed = if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X"))
+        {
+            u32::from_str_radix(hex, 16)
+        } else if let Some(binary) = text.strip_prefix('#') {
+            u32::from_str_radix(binary, 2)
+        } else {
+            text.parse()
+        };
+        parsed.map_err(|_| format!("invalid number {text}"))
+    }
+}
+
+mod xml {
+    pub struct Element {
+        pub name: String,
+        attributes: Vec<(String, String)>,
+        children: Vec<Element>,
+        text: String,
+    }
+
+    impl Element {
+        pub fn attribute(&self, name: &str) -> Option<&str> {
+            self.attributes
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        }
+
+        pub fn child(&self, name: &str) -> Option<&Element> {
+            self.children.iter().find(|child| child.name == name)
+        }
+
+        pub fn elements(&self) -> impl Iterator<Item = &Element> {
+            self.children.iter()
+        }
+
+        pub fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+            self.children.iter().filter(move |child| child.name == name)
+        }
+
+        pub fn child_text(&self, name: &str) -> Option<&str> {
+            self.child(name).map(|child| child.text.trim())
+        }
+    }
+
+    // Returns the root element, the prolog, comments and processing instructions are skipped
+    pub fn parse(source: &str) -> Result<Element, String> {
+        let mut stack: Vec<Element> = Vec::new();
+        let mut rest = source;
+        loop {
+            let Some(start) = rest.find('<') else {
+                return Err("unexpected end of document".to_string());
+            };
+            if let Some(element) = stack.last_mut() {
+                element.text.push_str(&decode(&rest[..start]));
+            }
+            rest = &rest[start..];
+            if let Some(comment) = rest.strip_prefix("<!--") {
+                let end = comment.find("-->").ok_or("unterminated comment")?;
+                rest = &comment[end + 3..];
+                continue;
+            }
+            if rest.starts_with("<?") || rest.starts_with("<!") {
+                let end = rest.find('>').ok_or("unterminated declaration")?;
+                rest = &rest[end + 1..];
+                continue;
+            }
+            let end = rest.find('>').ok_or("unterminated tag")?;
+            let tag = &rest[1..end];
+            rest = &rest[end + 1..];
+
+            if let Some(name) = tag.strip_prefix('/') {
+                let element = stack.pop().ok_or("closing tag without opening tag")?;
+                if element.name != name.trim() {
+                    return Err(format!("<{}> closed by </{}>", element.name, name.trim()));
+                }
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None => return Ok(element),
+                }
+                continue;
+            }
+
+            let self_closing = tag.ends_with('/');
+            let element = parse_tag(tag.trim_end_matches('/'))?;
+            if self_closing {
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None => return Ok(element),
+                }
+            } else {
+                stack.push(element);
+            }
+        }
+    }
+
+    fn parse_tag(tag: &str) -> Result<Element, String> {
+        let tag = tag.trim();
+        let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+        let mut element = Element {
+            name: tag[..name_end].to_string(),
+            attributes: Vec::new(),
+            children: Vec::new(),
+            text: String::new(),
+        };
+        let mut rest = tag[name_end..].trim_start();
+        while !rest.is_empty() {
+            let (key, value) = rest
+                .split_once('=')
+                .ok_or_else(|| format!("malformed attribute in <{}>", element.name))?;
+            let value = value.trim_start();
+            let quote = value
+                .chars()
+                .next()
+                .filter(|quote| *quote == '"' || *quote == '\'')
+                .ok_or_else(|| format!("unquoted attribute in <{}>", element.name))?;
+            let end = value[1..]
+                .find(quote)
+                .ok_or_else(|| format!("unterminated attribute in <{}>", element.name))?;
+            element
+                .attributes
+                .push((key.trim().to_string(), decode(&value[1..end + 1])));
+            rest = value[end + 2..].trim_start();
+        }
+        Ok(element)
+    }
+
+    fn decode(text: &str) -> String {
+        text.replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&")
+    }
+}
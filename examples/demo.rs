@@ -0,0 +1,183 @@
+#![no_std]
+#![no_main]
+
+use avocado::asm::no_operation;
+use avocado::clock::{register_clock_listener, take_hse_failure, ClockConfig};
+use avocado::independent_watchdog::IndependentWatchdogConf;
+use avocado::nucleo_f439zi::{
+    Board, Led, Leds, UserButton, VIRTUAL_COM_PORT_BAUD_RATE, VIRTUAL_COM_PORT_DMA_CHANNEL,
+};
+use avocado::panic::{report_and_reset, take_previous_panic};
+use avocado::rcc::{Peripheral, PllClockSource};
+use avocado::shared::Shared;
+use avocado::stack::enable_stack_guard;
+use avocado::stm32f439zitx::{Interrupt, Peripherals};
+use avocado::timer::{BasicTimerConf, BasicTimerPeriod};
+use avocado::usart::{UsartBaudRate, UsartConf, UsartDmaDriver, UsartSingleByteDriver};
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+// Peripherals used by the interrupt handlers, moved in by main
+static LEDS: Shared<Leds> = Shared::new();
+static BUTTON: Shared<UserButton> = Shared::new();
+static LED_BLINK_TIMER: Shared<BasicTimerConf> = Shared::new();
+static WATCHDOG: Shared<IndependentWatchdogConf> = Shared::new();
+static WATCHDOG_FEED_TIMER: Shared<BasicTimerConf> = Shared::new();
+// Owned here for the divider listener, the USART drivers borrow it
+static VIRTUAL_COM_PORT: Shared<UsartConf> = Shared::new();
+// Set by the user button, the main loop switches between the two clock configurations
+static THROTTLE_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+// Recompute the dividers whenever the clock tree changes
+static VIRTUAL_COM_PORT_DIVIDER: UsartBaudRate = UsartBaudRate::new(
+    &VIRTUAL_COM_PORT,
+    Peripheral::Usart3,
+    VIRTUAL_COM_PORT_BAUD_RATE,
+);
+static LED_BLINK_PERIOD: BasicTimerPeriod =
+    BasicTimerPeriod::new(&LED_BLINK_TIMER, Peripheral::Tim7, 500_000);
+// Well below the IWDG timeout
+static WATCHDOG_FEED_PERIOD: BasicTimerPeriod =
+    BasicTimerPeriod::new(&WATCHDOG_FEED_TIMER, Peripheral::Tim6, 780);
+
+// setup_clock and the battery setting, USB needs the PLL48CK of the first one only
+const FULL_SPEED: ClockConfig = ClockConfig::new()
+    .use_hse(8_000_000, true)
+    .sysclk(168_000_000)
+    .pclk1(42_000_000)
+    .pclk2(84_000_000)
+    .require_pll48();
+const THROTTLED: ClockConfig = ClockConfig::new()
+    .use_hse(8_000_000, true)
+    .sysclk(48_000_000)
+    .pclk1(12_000_000)
+    .pclk2(24_000_000);
+
+avocado::entry!(main);
+
+fn main() -> ! {
+    let board = Board::init(Peripherals::take().unwrap());
+    enable_stack_guard(&board.mpu);
+    let rcc = board.rcc;
+    let pwr = board.pwr;
+    let flash = board.flash;
+    let nvic = board.nvic;
+    // Handed to the interrupt handlers before any of their interrupts gets enabled
+    let leds = LEDS.init(board.leds).unwrap();
+    let button = BUTTON.init(board.user_button).unwrap();
+    let tim6 = WATCHDOG_FEED_TIMER.init(board.tim6).unwrap();
+    let tim7 = LED_BLINK_TIMER.init(board.tim7).unwrap();
+    let iwdg = WATCHDOG.init(board.iwdg).unwrap();
+    let virtual_com_port = VIRTUAL_COM_PORT.init(board.virtual_com_port).unwrap();
+    rcc.enable_peripheral(Peripheral::Tim7);
+    rcc.enable_peripheral(Peripheral::Tim6);
+    leds.on(Led::Blue);
+
+    // Enable interrupts index 40, 54, 55
+    nvic.enable_interrupts(&[
+        UserButton::INTERRUPT.into(),
+        Interrupt::Tim7.into(),
+        Interrupt::Tim6Dac.into(),
+    ]);
+
+    button.enable_interrupt();
+
+    // Registering sets the dividers for the running clocks
+    register_clock_listener(&VIRTUAL_COM_PORT_DIVIDER).unwrap();
+    register_clock_listener(&LED_BLINK_PERIOD).unwrap();
+    register_clock_listener(&WATCHDOG_FEED_PERIOD).unwrap();
+
+    // TIM7 handler index 55
+    tim7.update_interrupt_enable();
+    tim7.enable_timer();
+
+    // TIM6 handler index 54
+    tim6.update_interrupt_enable();
+    tim6.enable_timer();
+
+    iwdg.start_watchdog();
+
+    const HELLO: &str = "Hello World\r\n";
+    const DMA_HELLO: &str = "DMA Works Fine";
+    let mut single_byte_driver = UsartSingleByteDriver::new(virtual_com_port.by_ref());
+    single_byte_driver.send_bytes(HELLO.as_bytes());
+    let _ = write!(
+        single_byte_driver,
+        "Reset cause {:?}\r\n",
+        board.reset_cause
+    );
+    if let Some(record) = take_previous_panic() {
+        let _ = write!(single_byte_driver, "Previous boot {record}\r\n");
+    }
+    if board.clocks.source() == PllClockSource::HSI {
+        let _ = write!(
+            single_byte_driver,
+            "HSE did not start, running from HSI\r\n"
+        );
+    }
+    let mut dma_driver: UsartDmaDriver<1024, _> = UsartDmaDriver::new(
+        single_byte_driver.release(),
+        board.dma1.stream3,
+        VIRTUAL_COM_PORT_DMA_CHANNEL,
+    );
+    let mut throttled = false;
+    loop {
+        // The listeners already moved USART3 and the timers to the HSI clocks
+        if take_hse_failure() {
+            leds.off(Led::Blue);
+            dma_driver
+                .print_line("HSE failed, running from HSI")
+                .unwrap();
+        }
+        if THROTTLE_REQUESTED.swap(false, Ordering::AcqRel) {
+            throttled = !throttled;
+            let config = if throttled { THROTTLED } else { FULL_SPEED };
+            // The divider must not change under a frame
+            dma_driver.flush().unwrap();
+            while !dma_driver.is_transmission_completed() {
+                unsafe {
+                    no_operation();
+                }
+            }
+            let message = match config.freeze(&rcc, &pwr, &flash) {
+                Ok(_) if throttled => "SYSCLK 48 MHz",
+                Ok(_) => "SYSCLK 168 MHz",
+                Err(_) => "Clock change failed",
+            };
+            dma_driver.print_line(message).unwrap();
+        }
+        dma_driver.print_line(DMA_HELLO).unwrap();
+    }
+}
+
+unsafe fn button_handler() {
+    if let (Some(leds), Some(button)) = (LEDS.get(), BUTTON.get()) {
+        leds.toggle(Led::Green);
+        THROTTLE_REQUESTED.store(true, Ordering::Release);
+        button.clear_pending();
+    }
+}
+
+unsafe fn led_blink() {
+    if let (Some(leds), Some(timer)) = (LEDS.get(), LED_BLINK_TIMER.get()) {
+        leds.toggle(Led::Red);
+        timer.clear_status_flag();
+    }
+}
+
+unsafe fn feed_watchdog() {
+    if let (Some(watchdog), Some(timer)) = (WATCHDOG.get(), WATCHDOG_FEED_TIMER.get()) {
+        watchdog.feed_watchdog();
+        timer.clear_status_flag();
+    }
+}
+
+avocado::interrupt!(Exti15_10, button_handler);
+avocado::interrupt!(Tim7, led_blink);
+avocado::interrupt!(Tim6Dac, feed_watchdog);
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    report_and_reset(info)
+}
@@ -1,6 +1,19 @@
+#[cfg(target_arch = "arm")]
 use core::arch::asm;
 
+/// # Safety
+/// Has no side effects, unsafe only because it is inline assembly.
+#[cfg(target_arch = "arm")]
 #[inline(always)]
 pub unsafe fn no_operation() {
     asm!("NOP")
 }
+
+// Host builds run drivers against SimulatedBus
+/// # Safety
+/// Has no side effects, unsafe only because it is inline assembly.
+#[cfg(not(target_arch = "arm"))]
+#[inline(always)]
+pub unsafe fn no_operation() {
+    core::hint::spin_loop()
+}
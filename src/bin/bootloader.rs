@@ -0,0 +1,118 @@
+#![no_std]
+#![no_main]
+
+use avocado::boot::{application_vectors, is_valid_application, jump_to_application};
+use avocado::clock::{reset_clock, setup_clock};
+use avocado::flash::InternalFlash;
+use avocado::nucleo_f439zi::{configure_virtual_com_port, USER_BUTTON_PIN};
+use avocado::panic::report_and_reset;
+use avocado::rcc::Peripheral::{GpioC, GpioD, Usart3};
+use avocado::stm32f439zitx::Peripherals;
+use avocado::systick::SysTickConf;
+use avocado::update::{apply_update, pending_update, receive_update, UpdateError};
+use avocado::usart::UsartSingleByteDriver;
+use avocado::ymodem::{SerialPort, YmodemError};
+use core::fmt::Write;
+use core::panic::PanicInfo;
+
+/*
+   Bootloader, linked with ld/STM32F439ZITX_BOOTLOADER.ld into the first flash sectors.
+
+   A pending update received before the last reset is copied over the application first.
+   Then the application at APPLICATION_ADDRESS is started unless the user button (PC13) is held
+   down during reset or there is no valid application image. In that case the bootloader stays
+   in update mode and receives a new image over USART3 with YMODEM, see update.rs, and resets
+   once it is staged.
+   Before the jump USART3, GPIOC, GPIOD and the clock tree go back to their reset
+   state, the application starts as if it came out of reset. The reset flags in RCC_CSR are
+   left alone for the application to read.
+*/
+avocado::entry!(main);
+
+// USART3 with timeouts counted in SysTick milliseconds
+struct VirtualComPort<'a> {
+    usart: &'a UsartSingleByteDriver,
+    systick: &'a SysTickConf,
+}
+
+impl SerialPort for VirtualComPort<'_> {
+    fn write_byte(&mut self, byte: u8) {
+        self.usart.send_bytes(&[byte]);
+    }
+
+    fn read_byte(&mut self, timeout: u32) -> Option<u8> {
+        let mut remaining = timeout;
+        loop {
+            if let Some(byte) = self.usart.receive_byte() {
+                return Some(byte);
+            }
+            if self.systick.has_ticked() {
+                if remaining == 0 {
+                    return None;
+                }
+                remaining -= 1;
+            }
+        }
+    }
+}
+
+fn main() -> ! {
+    let peripherals = Peripherals::take().unwrap();
+    let rcc = peripherals.rcc;
+    rcc.enable_peripheral(GpioC);
+    let update_requested = peripherals.port_c.is_pin_high(USER_BUTTON_PIN);
+
+    let clocks = setup_clock(&rcc, &peripherals.pwr, &peripherals.flash);
+    configure_virtual_com_port(&rcc, &peripherals.port_d, &peripherals.usart3, &clocks);
+    let mut usart = UsartSingleByteDriver::new(peripherals.usart3);
+    let mut flash = InternalFlash::new(peripherals.flash);
+
+    if let Some(record) = pending_update(&flash) {
+        let _ = write!(usart, "Applying update of {} bytes\r\n", record.size);
+        if let Err(error) = apply_update(&mut flash, &record) {
+            let _ = write!(usart, "Update failed: {error:?}\r\n");
+        }
+    }
+
+    let (initial_stack_pointer, reset_handler) = application_vectors();
+    if update_requested || !is_valid_application(initial_stack_pointer, reset_handler) {
+        let _ = write!(
+            usart,
+            "Bootloader update mode, send the image with YMODEM\r\n"
+        );
+        let systick = peripherals.systick;
+        systick.start_millisecond_ticks(clocks.hclk());
+        loop {
+            let mut port = VirtualComPort {
+                usart: &usart,
+                systick: &systick,
+            };
+            match receive_update(&mut port, &mut flash) {
+                Ok(record) => {
+                    let _ = write!(usart, "\r\nReceived {} bytes, resetting\r\n", record.size);
+                    peripherals.scb.system_reset();
+                }
+                // The sender was not started yet, keep asking
+                Err(UpdateError::Transfer(YmodemError::Timeout)) => {}
+                Err(error) => {
+                    let _ = write!(usart, "\r\nUpdate failed: {error:?}\r\n");
+                }
+            }
+        }
+    }
+
+    let _ = write!(usart, "Starting application at {reset_handler:#010x}\r\n");
+    // send_bytes returns once the last frame left the shift register
+    usart.release();
+    for peripheral in [Usart3, GpioC, GpioD] {
+        rcc.reset_peripheral(peripheral);
+        rcc.disable_peripheral(peripheral);
+    }
+    reset_clock(&rcc);
+    unsafe { jump_to_application(&peripherals.scb) }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    report_and_reset(info)
+}
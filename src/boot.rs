@@ -0,0 +1,82 @@
+use crate::ccmram::{is_in_ccmram, CCMRAM_SIZE, CCMRAM_START};
+
+/*
+   Flash layout shared by the bootloader and the application, RM0090 p77.
+
+   0x0800_0000  sectors 0 to 3    64 KB   bootloader, ld/STM32F439ZITX_BOOTLOADER.ld
+   0x0801_0000  sector 4          64 KB   pending update record, see update.rs
+   0x0802_0000  sectors 5 to 11  896 KB   application, ld/STM32F439ZITX_FLASH.ld
+   0x0810_0000  sectors 12 to 16 128 KB   unused
+   0x0812_0000  sectors 17 to 23 896 KB   staging area for a received application image
+
+   The bootloader starts the application by loading MSP and the reset handler from the first
+   two words of its vector table, after pointing VTOR at it.
+*/
+pub const BOOTLOADER_ADDRESS: u32 = 0x0800_0000;
+pub const APPLICATION_ADDRESS: u32 = 0x0802_0000;
+pub const APPLICATION_SIZE: u32 = 896 * 1024;
+pub const UPDATE_RECORD_ADDRESS: u32 = 0x0801_0000;
+pub const STAGING_ADDRESS: u32 = 0x0812_0000;
+pub const STAGING_SIZE: u32 = APPLICATION_SIZE;
+
+const RAM_START: u32 = 0x2000_0000;
+const RAM_SIZE: u32 = 192 * 1024;
+
+/*
+   Erased flash reads 0xFFFF_FFFF and fails both checks. The initial stack pointer may be the
+   end of RAM or CCM, the reset handler is a Thumb address inside the application area.
+*/
+pub fn is_valid_application(initial_stack_pointer: u32, reset_handler: u32) -> bool {
+    let stack_in_ram = (RAM_START..=RAM_START + RAM_SIZE).contains(&initial_stack_pointer)
+        || is_in_ccmram(initial_stack_pointer)
+        || initial_stack_pointer == CCMRAM_START + CCMRAM_SIZE;
+    let handler_in_image = (APPLICATION_ADDRESS..APPLICATION_ADDRESS + APPLICATION_SIZE)
+        .contains(&(reset_handler & !1));
+    stack_in_ram
+        && initial_stack_pointer.is_multiple_of(4)
+        && handler_in_image
+        && reset_handler & 1 == 1
+}
+
+#[cfg(target_os = "none")]
+mod jump {
+    use super::APPLICATION_ADDRESS;
+    use crate::register_bus::RegisterBus;
+    use crate::scb::ScbConf;
+    use core::arch::asm;
+    use core::ptr::read_volatile;
+
+    // Initial stack pointer and reset handler of the application
+    pub fn application_vectors() -> (u32, u32) {
+        unsafe {
+            (
+                read_volatile(APPLICATION_ADDRESS as *const u32),
+                read_volatile((APPLICATION_ADDRESS + 4) as *const u32),
+            )
+        }
+    }
+
+    /// Starts the application, the bootloader stack is abandoned.
+    ///
+    /// # Safety
+    /// The image has to pass is_valid_application, and every peripheral the bootloader used has
+    /// to be back in its reset state since the application starts as after a reset.
+    pub unsafe fn jump_to_application<B: RegisterBus>(scb: &ScbConf<B>) -> ! {
+        let (initial_stack_pointer, reset_handler) = application_vectors();
+        scb.set_vector_table_offset(APPLICATION_ADDRESS);
+        // CONTROL back to privileged thread mode on MSP without FP context, as after reset
+        asm!(
+            "msr control, {zero}",
+            "isb",
+            "msr msp, {stack_pointer}",
+            "bx {reset_handler}",
+            zero = in(reg) 0,
+            stack_pointer = in(reg) initial_stack_pointer,
+            reset_handler = in(reg) reset_handler,
+            options(noreturn, nostack),
+        );
+    }
+}
+
+#[cfg(target_os = "none")]
+pub use jump::{application_vectors, jump_to_application};
@@ -0,0 +1,52 @@
+/*
+   Core coupled memory, RM0090 p68.
+
+   The 64 KB at 0x1000_0000 are connected to the D-bus of the core only. Accesses have no wait
+   states and never compete with DMA, which makes CCM a good place for stacks, task-local state
+   and hot data. The DMA controllers cannot reach it at all, a transfer from or to CCM does not
+   raise an error, it just moves nothing useful.
+
+   ccmram! places a static in .ccmram.data, the reset handler copies its initial value from
+   flash. ccmram_zeroed! places it in .ccmram.bss, which is only zeroed, the initializer must
+   be all zeros and does not take space in flash.
+*/
+pub const CCMRAM_START: u32 = 0x1000_0000;
+pub const CCMRAM_SIZE: u32 = 64 * 1024;
+
+pub const fn is_in_ccmram(address: u32) -> bool {
+    address >= CCMRAM_START && address - CCMRAM_START < CCMRAM_SIZE
+}
+
+// True when no byte of the buffer is in CCM
+pub const fn is_dma_accessible(address: u32, length: usize) -> bool {
+    let end = address as u64 + length as u64;
+    end <= CCMRAM_START as u64 || address >= CCMRAM_START + CCMRAM_SIZE
+}
+
+#[macro_export]
+macro_rules! ccmram {
+    ($(#[$attribute:meta])* $visibility:vis static mut $name:ident: $type:ty = $value:expr;) => {
+        $(#[$attribute])*
+        #[link_section = concat!(".ccmram.data.", stringify!($name))]
+        $visibility static mut $name: $type = $value;
+    };
+    ($(#[$attribute:meta])* $visibility:vis static $name:ident: $type:ty = $value:expr;) => {
+        $(#[$attribute])*
+        #[link_section = concat!(".ccmram.data.", stringify!($name))]
+        $visibility static $name: $type = $value;
+    };
+}
+
+#[macro_export]
+macro_rules! ccmram_zeroed {
+    ($(#[$attribute:meta])* $visibility:vis static mut $name:ident: $type:ty = $value:expr;) => {
+        $(#[$attribute])*
+        #[link_section = concat!(".ccmram.bss.", stringify!($name))]
+        $visibility static mut $name: $type = $value;
+    };
+    ($(#[$attribute:meta])* $visibility:vis static $name:ident: $type:ty = $value:expr;) => {
+        $(#[$attribute])*
+        #[link_section = concat!(".ccmram.bss.", stringify!($name))]
+        $visibility static $name: $type = $value;
+    };
+}
@@ -0,0 +1,897 @@
+use crate::asm::no_operation;
+use crate::flash::FlashConf;
+use crate::interrupt;
+use crate::pwr::PwrConf;
+use crate::rcc::SystemClock::HSI;
+use crate::rcc::{
+    Bus, I2sPll, MainPll, Peripheral, PllClockSource, RccConf, RccError, SaiPll, SystemClock,
+};
+use crate::register_bus::RegisterBus;
+use crate::scb::ScbConf;
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+const HSI_FREQUENCY: u32 = 16_000_000;
+const USB_FREQUENCY: u32 = 48_000_000;
+
+/*
+   DS9484 p95 and p117, voltage scale 1 without and with over-drive
+   HSE crystal 4 to 26 MHz, external clock (bypass) 1 to 50 MHz
+*/
+const MAX_SYSTEM_CLOCK: u32 = 168_000_000;
+const MAX_PCLK1: u32 = 42_000_000;
+const MAX_PCLK2: u32 = 84_000_000;
+const MAX_SYSTEM_CLOCK_OVER_DRIVE: u32 = 180_000_000;
+const MAX_PCLK1_OVER_DRIVE: u32 = 45_000_000;
+const MAX_PCLK2_OVER_DRIVE: u32 = 90_000_000;
+const HSE_CRYSTAL_RANGE: (u32, u32) = (4_000_000, 26_000_000);
+const HSE_BYPASS_RANGE: (u32, u32) = (1_000_000, 50_000_000);
+
+// RM0090 p226
+const VCO_INPUT_RANGE: (u32, u32) = (1_000_000, 2_000_000);
+const VCO_OUTPUT_RANGE: (u32, u32) = (100_000_000, 432_000_000);
+
+// RM0090 p80, one wait state per 30 MHz of HCLK at 2.7 V to 3.6 V
+const FLASH_WAIT_STATE_STEP: u32 = 30_000_000;
+
+const AHB_PRESCALERS: [u16; 9] = [1, 2, 4, 8, 16, 64, 128, 256, 512];
+const APB_PRESCALERS: [u8; 5] = [1, 2, 4, 8, 16];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClockError {
+    HseFrequencyOutOfRange,
+    SystemClockTooHigh,
+    SystemClockUnreachable,
+    UsbClockUnreachable,
+    AhbClockUnreachable,
+    Apb1ClockTooHigh,
+    Apb1ClockUnreachable,
+    Apb2ClockTooHigh,
+    Apb2ClockUnreachable,
+    // HSE did not start and the targets can not be reached from HSI either
+    HseStartupTimeout,
+    I2sPllVcoOutOfRange,
+    SaiPllVcoOutOfRange,
+    I2sPllLockTimeout,
+    SaiPllLockTimeout,
+    TooManyClockListeners,
+}
+
+/*
+   Frequencies in Hz of a configured clock tree. Timers on an APB bus run at twice PCLK unless
+   the bus is not divided, RM0090 p152.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Clocks {
+    sysclk: u32,
+    hclk: u32,
+    pclk1: u32,
+    pclk2: u32,
+    apb1_prescaler: u8,
+    apb2_prescaler: u8,
+    pll48: Option<u32>,
+    i2s: Option<u32>,
+    plli2s_sai: Option<u32>,
+    pllsai_sai: Option<u32>,
+    lcd_tft: Option<u32>,
+    source: PllClockSource,
+}
+
+// SYSCLK = HSI 16MHz, AHB and APB not divided
+const RESET_CLOCKS: Clocks = Clocks {
+    sysclk: HSI_FREQUENCY,
+    hclk: HSI_FREQUENCY,
+    pclk1: HSI_FREQUENCY,
+    pclk2: HSI_FREQUENCY,
+    apb1_prescaler: 1,
+    apb2_prescaler: 1,
+    pll48: None,
+    i2s: None,
+    plli2s_sai: None,
+    pllsai_sai: None,
+    lcd_tft: None,
+    source: PllClockSource::HSI,
+};
+
+impl Clocks {
+    pub const fn sysclk(&self) -> u32 {
+        self.sysclk
+    }
+
+    pub const fn hclk(&self) -> u32 {
+        self.hclk
+    }
+
+    pub const fn pclk1(&self) -> u32 {
+        self.pclk1
+    }
+
+    pub const fn pclk2(&self) -> u32 {
+        self.pclk2
+    }
+
+    pub const fn timer_clock1(&self) -> u32 {
+        if self.apb1_prescaler == 1 {
+            self.pclk1
+        } else {
+            2 * self.pclk1
+        }
+    }
+
+    pub const fn timer_clock2(&self) -> u32 {
+        if self.apb2_prescaler == 1 {
+            self.pclk2
+        } else {
+            2 * self.pclk2
+        }
+    }
+
+    // Clock of the peripherals on a bus, HCLK for the AHB buses
+    pub const fn bus_clock(&self, bus: Bus) -> u32 {
+        match bus {
+            Bus::APB1 => self.pclk1,
+            Bus::APB2 => self.pclk2,
+            Bus::AHB1 | Bus::AHB2 | Bus::AHB3 => self.hclk,
+        }
+    }
+
+    pub const fn timer_clock(&self, bus: Bus) -> u32 {
+        match bus {
+            Bus::APB1 => self.timer_clock1(),
+            Bus::APB2 => self.timer_clock2(),
+            Bus::AHB1 | Bus::AHB2 | Bus::AHB3 => self.hclk,
+        }
+    }
+
+    // PLL48CK for USB OTG FS, SDIO and RNG, only running with the main PLL
+    pub const fn pll48(&self) -> Option<u32> {
+        self.pll48
+    }
+
+    // PLLI2S R output, the clock of the I2S peripherals
+    pub const fn i2s(&self) -> Option<u32> {
+        self.i2s
+    }
+
+    // SAI1 clock from PLLI2S Q output after PLLI2SDIVQ
+    pub const fn plli2s_sai(&self) -> Option<u32> {
+        self.plli2s_sai
+    }
+
+    // SAI1 clock from PLLSAI Q output after PLLSAIDIVQ
+    pub const fn pllsai_sai(&self) -> Option<u32> {
+        self.pllsai_sai
+    }
+
+    // LCD-TFT pixel clock, PLLSAI R output after PLLSAIDIVR
+    pub const fn lcd_tft(&self) -> Option<u32> {
+        self.lcd_tft
+    }
+
+    // Oscillator feeding SYSCLK directly or through the main PLL, HSI after an HSE fallback
+    pub const fn source(&self) -> PllClockSource {
+        self.source
+    }
+
+    // SYSCLK taken over by HSI with the prescalers still in CFGR, all PLLs are off
+    fn on_hsi<R: RegisterBus>(rcc: &RccConf<R>) -> Clocks {
+        let (apb2_prescaler, apb1_prescaler) = rcc.get_apb_prescaler();
+        let hclk = HSI_FREQUENCY / rcc.get_ahb_prescaler() as u32;
+        Clocks {
+            sysclk: HSI_FREQUENCY,
+            hclk,
+            pclk1: hclk / apb1_prescaler as u32,
+            pclk2: hclk / apb2_prescaler as u32,
+            apb1_prescaler,
+            apb2_prescaler,
+            pll48: None,
+            i2s: None,
+            plli2s_sai: None,
+            pllsai_sai: None,
+            lcd_tft: None,
+            source: PllClockSource::HSI,
+        }
+    }
+}
+
+/*
+   Frequencies of the running clock tree, stored by ClockConfig::freeze and after an HSE
+   failure. A store runs with interrupts masked, so no handler ever sees it half done and waits
+   for it forever. A reader preempted by a store copies again when the sequence changed
+   meanwhile.
+   The clock security NMI is not masked and may interrupt a store, it writes a failover copy
+   of its own instead. Readers prefer it until a store that started after the NMI clears it,
+   nothing preempts the NMI so it never waits for a reader. HardFault must not call
+   current_clocks.
+*/
+struct RunningClocks {
+    // Odd while a store is in progress
+    sequence: AtomicU32,
+    clocks: UnsafeCell<Option<Clocks>>,
+    // Advanced by two for every NMI store, odd while it is in progress
+    failover_sequence: AtomicU32,
+    // failover_sequence the last store started with, the failover copy is older than it
+    failover_cleared: AtomicU32,
+    failover: UnsafeCell<Option<Clocks>>,
+}
+
+unsafe impl Sync for RunningClocks {}
+
+static RUNNING_CLOCKS: RunningClocks = RunningClocks {
+    sequence: AtomicU32::new(0),
+    clocks: UnsafeCell::new(None),
+    failover_sequence: AtomicU32::new(0),
+    failover_cleared: AtomicU32::new(0),
+    failover: UnsafeCell::new(None),
+};
+
+// Set by the NMI for the PendSV handler, set by the PendSV handler for take_hse_failure
+static HSE_FAILURE_PENDING: AtomicBool = AtomicBool::new(false);
+static HSE_FAILURE_REPORTED: AtomicBool = AtomicBool::new(false);
+
+impl RunningClocks {
+    fn store(&self, clocks: &Clocks) {
+        interrupt::free(|| {
+            // An NMI storing from here on wins over this store
+            let failover = self.failover_sequence.load(Ordering::Acquire);
+            self.sequence.fetch_add(1, Ordering::Acquire);
+            unsafe {
+                ptr::write_volatile(self.clocks.get(), Some(*clocks));
+            }
+            self.sequence.fetch_add(1, Ordering::Release);
+            self.failover_cleared.store(failover, Ordering::Release);
+        });
+    }
+
+    // Only from the clock security NMI
+    fn store_failover(&self, clocks: &Clocks) {
+        self.failover_sequence.fetch_add(1, Ordering::Acquire);
+        unsafe {
+            ptr::write_volatile(self.failover.get(), Some(*clocks));
+        }
+        self.failover_sequence.fetch_add(1, Ordering::Release);
+    }
+
+    fn load(&self) -> Option<Clocks> {
+        loop {
+            let failover = self.failover_sequence.load(Ordering::Acquire);
+            if failover != self.failover_cleared.load(Ordering::Acquire) {
+                let clocks = unsafe { ptr::read_volatile(self.failover.get()) };
+                if failover.is_multiple_of(2)
+                    && self.failover_sequence.load(Ordering::Acquire) == failover
+                {
+                    return clocks;
+                }
+                continue;
+            }
+            let sequence = self.sequence.load(Ordering::Acquire);
+            let clocks = unsafe { ptr::read_volatile(self.clocks.get()) };
+            if sequence.is_multiple_of(2) && self.sequence.load(Ordering::Acquire) == sequence {
+                return clocks;
+            }
+        }
+    }
+}
+
+// None until ClockConfig::freeze ran
+pub fn current_clocks() -> Option<Clocks> {
+    RUNNING_CLOCKS.load()
+}
+
+/*
+   Drivers whose dividers follow the bus clocks, e.g. UsartBaudRate and BasicTimerPeriod.
+   A registered listener gets the running clocks right away and every clock tree after that,
+   from ClockConfig::freeze once SYSCLK switched and from the PendSV handler after an HSE
+   failure. It runs in the context of their caller and should only write the dividers of its own peripheral.
+*/
+pub trait ClockListener {
+    fn clocks_changed(&self, clocks: &Clocks);
+}
+
+const MAX_CLOCK_LISTENERS: usize = 8;
+
+type ListenerSlot = Option<&'static (dyn ClockListener + Sync)>;
+
+// Slots below count are written once and never change, apply_clocks reads them without locking
+struct ClockListeners {
+    count: AtomicUsize,
+    slots: UnsafeCell<[ListenerSlot; MAX_CLOCK_LISTENERS]>,
+}
+
+unsafe impl Sync for ClockListeners {}
+
+static CLOCK_LISTENERS: ClockListeners = ClockListeners {
+    count: AtomicUsize::new(0),
+    slots: UnsafeCell::new([None; MAX_CLOCK_LISTENERS]),
+};
+
+pub fn register_clock_listener(
+    listener: &'static (dyn ClockListener + Sync),
+) -> Result<(), ClockError> {
+    interrupt::free(|| {
+        let count = CLOCK_LISTENERS.count.load(Ordering::Relaxed);
+        if count == MAX_CLOCK_LISTENERS {
+            return Err(ClockError::TooManyClockListeners);
+        }
+        unsafe {
+            (*CLOCK_LISTENERS.slots.get())[count] = Some(listener);
+        }
+        CLOCK_LISTENERS.count.store(count + 1, Ordering::Release);
+        Ok(())
+    })?;
+    if let Some(clocks) = current_clocks() {
+        listener.clocks_changed(&clocks);
+    }
+    Ok(())
+}
+
+// Stores the frequencies for current_clocks and tells the listeners
+fn apply_clocks(clocks: &Clocks) {
+    RUNNING_CLOCKS.store(clocks);
+    let count = CLOCK_LISTENERS.count.load(Ordering::Acquire);
+    let slots = CLOCK_LISTENERS.slots.get() as *const ListenerSlot;
+    for index in 0..count {
+        if let Some(listener) = unsafe { *slots.add(index) } {
+            listener.clocks_changed(clocks);
+        }
+    }
+}
+
+/*
+   The part of the clock security NMI that does not depend on the vector table. The hardware
+   already runs SYSCLK from HSI with HSE and the main PLL off, current_clocks reports the HSI
+   frequencies from here on. Only CSSF is cleared, with a single bit-band store, the NMI may
+   have interrupted a read-modify-write of another RCC register or a clock listener. The rest
+   is pended to PendSV, which runs once the interrupted code and handlers are done.
+*/
+pub fn record_hse_failure<R: RegisterBus, S: RegisterBus>(rcc: &RccConf<R>, scb: &ScbConf<S>) {
+    rcc.clear_hse_failure();
+    RUNNING_CLOCKS.store_failover(&Clocks::on_hsi(rcc));
+    HSE_FAILURE_PENDING.store(true, Ordering::Release);
+    scb.set_pend_sv();
+}
+
+/*
+   The part of the PendSV handler that does not depend on the vector table, Some once after
+   record_hse_failure. The PLLs and HSE are switched off for good so a later freeze starts from
+   a known state, flash wait states stay, too many are only slower. Registered clock listeners
+   get the HSI frequencies, anything else clocked from the old ones has to be set up again by
+   the application when take_hse_failure reports the failure.
+*/
+pub fn recover_from_hse_failure<R: RegisterBus>(rcc: &RccConf<R>) -> Option<Clocks> {
+    if !HSE_FAILURE_PENDING.swap(false, Ordering::AcqRel) {
+        return None;
+    }
+    rcc.enable_hsi();
+    rcc.set_system_clock(HSI);
+    rcc.disable_main_pll();
+    rcc.disable_i2s_pll();
+    rcc.disable_sai_pll();
+    rcc.disable_hse();
+    let clocks = Clocks::on_hsi(rcc);
+    apply_clocks(&clocks);
+    HSE_FAILURE_REPORTED.store(true, Ordering::Release);
+    Some(clocks)
+}
+
+// True once after an HSE failure was recovered from, current_clocks has the HSI frequencies
+pub fn take_hse_failure() -> bool {
+    HSE_FAILURE_REPORTED.swap(false, Ordering::AcqRel)
+}
+
+// Register values computed by ClockConfig::plan, applied by ClockConfig::freeze
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClockPlan {
+    pub system_clock: SystemClock,
+    pub pll: Option<MainPll>,
+    // PLLM shared by the main PLL, PLLI2S and PLLSAI, None while all of them are off
+    pub pll_m: Option<u8>,
+    pub i2s_pll: Option<I2sPll>,
+    pub sai_pll: Option<SaiPll>,
+    pub ahb_prescaler: u16,
+    pub apb1_prescaler: u8,
+    pub apb2_prescaler: u8,
+    pub flash_latency: u8,
+    // SYSCLK above 168 MHz
+    pub over_drive: bool,
+    pub clocks: Clocks,
+}
+
+#[derive(Clone, Copy)]
+struct Hse {
+    frequency: u32,
+    bypass: bool,
+}
+
+/*
+   Clock tree from target frequencies, e.g.
+
+   ClockConfig::new()
+       .use_hse(8_000_000, true)
+       .sysclk(168_000_000)
+       .require_pll48()
+       .freeze(&rcc, &pwr, &flash)
+
+   Frequencies that are not given default to SYSCLK for HCLK and to the fastest allowed clock
+   for PCLK1 and PCLK2. Every requested frequency has to be met exactly.
+*/
+#[derive(Clone, Copy, Default)]
+pub struct ClockConfig {
+    hse: Option<Hse>,
+    sysclk: Option<u32>,
+    hclk: Option<u32>,
+    pclk1: Option<u32>,
+    pclk2: Option<u32>,
+    pll48: bool,
+    i2s_pll: Option<I2sPll>,
+    sai_pll: Option<SaiPll>,
+}
+
+impl ClockConfig {
+    // HSI at 16 MHz without PLL, the clock tree after reset
+    pub const fn new() -> Self {
+        ClockConfig {
+            hse: None,
+            sysclk: None,
+            hclk: None,
+            pclk1: None,
+            pclk2: None,
+            pll48: false,
+            i2s_pll: None,
+            sai_pll: None,
+        }
+    }
+
+    // ST-LINK MCO on the Nucleo board is an 8 MHz external clock, oscillator_bypassed = true
+    pub const fn use_hse(mut self, frequency: u32, oscillator_bypassed: bool) -> Self {
+        self.hse = Some(Hse {
+            frequency,
+            bypass: oscillator_bypassed,
+        });
+        self
+    }
+
+    pub const fn sysclk(mut self, frequency: u32) -> Self {
+        self.sysclk = Some(frequency);
+        self
+    }
+
+    pub const fn hclk(mut self, frequency: u32) -> Self {
+        self.hclk = Some(frequency);
+        self
+    }
+
+    pub const fn pclk1(mut self, frequency: u32) -> Self {
+        self.pclk1 = Some(frequency);
+        self
+    }
+
+    pub const fn pclk2(mut self, frequency: u32) -> Self {
+        self.pclk2 = Some(frequency);
+        self
+    }
+
+    // Exactly 48 MHz on PLL48CK, USB OTG FS needs it
+    pub const fn require_pll48(mut self) -> Self {
+        self.pll48 = true;
+        self
+    }
+
+    /*
+       PLLI2S and PLLSAI are given as dividers, the input is the one of the main PLL. Without
+       the main PLL PLLM is chosen for a VCO input close to 2 MHz.
+    */
+    pub const fn i2s_pll(mut self, pll: I2sPll) -> Self {
+        self.i2s_pll = Some(pll);
+        self
+    }
+
+    pub const fn sai_pll(mut self, pll: SaiPll) -> Self {
+        self.sai_pll = Some(pll);
+        self
+    }
+
+    pub fn plan(&self) -> Result<ClockPlan, ClockError> {
+        let (source, source_frequency) = match self.hse {
+            Some(hse) => {
+                let (min, max) = if hse.bypass {
+                    HSE_BYPASS_RANGE
+                } else {
+                    HSE_CRYSTAL_RANGE
+                };
+                if !(min..=max).contains(&hse.frequency) {
+                    return Err(ClockError::HseFrequencyOutOfRange);
+                }
+                (PllClockSource::HSE, hse.frequency)
+            }
+            None => (PllClockSource::HSI, HSI_FREQUENCY),
+        };
+        let sysclk = self.sysclk.unwrap_or(source_frequency);
+        if sysclk > MAX_SYSTEM_CLOCK_OVER_DRIVE {
+            return Err(ClockError::SystemClockTooHigh);
+        }
+        let over_drive = sysclk > MAX_SYSTEM_CLOCK;
+        let (max_pclk1, max_pclk2) = if over_drive {
+            (MAX_PCLK1_OVER_DRIVE, MAX_PCLK2_OVER_DRIVE)
+        } else {
+            (MAX_PCLK1, MAX_PCLK2)
+        };
+        let (system_clock, pll) = if sysclk == source_frequency && !self.pll48 {
+            let system_clock = match source {
+                PllClockSource::HSI => SystemClock::HSI,
+                PllClockSource::HSE => SystemClock::HSE,
+            };
+            (system_clock, None)
+        } else {
+            let pll = find_main_pll(source, source_frequency, sysclk, self.pll48)?;
+            (SystemClock::PLL, Some(pll))
+        };
+
+        let pll_m = match pll {
+            Some(pll) => Some(pll.m()),
+            None if self.i2s_pll.is_some() || self.sai_pll.is_some() => {
+                Some(pll_input_divider(source_frequency))
+            }
+            None => None,
+        };
+        let vco = |n: u16| {
+            let m = pll_m.unwrap_or(1) as u64;
+            let vco = source_frequency as u64 * n as u64 / m;
+            let input = source_frequency / m as u32;
+            let in_range = (VCO_INPUT_RANGE.0..=VCO_INPUT_RANGE.1).contains(&input)
+                && (VCO_OUTPUT_RANGE.0 as u64..=VCO_OUTPUT_RANGE.1 as u64).contains(&vco);
+            in_range.then_some(vco)
+        };
+        let (i2s, plli2s_sai) = match self.i2s_pll {
+            Some(i2s_pll) => {
+                let vco = vco(i2s_pll.n()).ok_or(ClockError::I2sPllVcoOutOfRange)?;
+                (
+                    Some((vco / i2s_pll.r() as u64) as u32),
+                    Some((vco / i2s_pll.q() as u64 / i2s_pll.div_q() as u64) as u32),
+                )
+            }
+            None => (None, None),
+        };
+        let (pllsai_sai, lcd_tft) = match self.sai_pll {
+            Some(sai_pll) => {
+                let vco = vco(sai_pll.n()).ok_or(ClockError::SaiPllVcoOutOfRange)?;
+                (
+                    Some((vco / sai_pll.q() as u64 / sai_pll.div_q() as u64) as u32),
+                    Some((vco / sai_pll.r() as u64 / sai_pll.div_r() as u64) as u32),
+                )
+            }
+            None => (None, None),
+        };
+
+        let ahb_prescaler = match self.hclk {
+            Some(hclk) => AHB_PRESCALERS
+                .into_iter()
+                .find(|&divider| divides(sysclk, divider as u32, hclk))
+                .ok_or(ClockError::AhbClockUnreachable)?,
+            None => 1,
+        };
+        let hclk = sysclk / ahb_prescaler as u32;
+        let apb1_prescaler = apb_prescaler(hclk, self.pclk1, max_pclk1).map_err(|too_high| {
+            if too_high {
+                ClockError::Apb1ClockTooHigh
+            } else {
+                ClockError::Apb1ClockUnreachable
+            }
+        })?;
+        let apb2_prescaler = apb_prescaler(hclk, self.pclk2, max_pclk2).map_err(|too_high| {
+            if too_high {
+                ClockError::Apb2ClockTooHigh
+            } else {
+                ClockError::Apb2ClockUnreachable
+            }
+        })?;
+
+        Ok(ClockPlan {
+            system_clock,
+            pll,
+            pll_m,
+            i2s_pll: self.i2s_pll,
+            sai_pll: self.sai_pll,
+            ahb_prescaler,
+            apb1_prescaler,
+            apb2_prescaler,
+            flash_latency: ((hclk - 1) / FLASH_WAIT_STATE_STEP) as u8,
+            over_drive,
+            clocks: Clocks {
+                sysclk,
+                hclk,
+                pclk1: hclk / apb1_prescaler as u32,
+                pclk2: hclk / apb2_prescaler as u32,
+                apb1_prescaler,
+                apb2_prescaler,
+                pll48: pll.map(|pll| sysclk * pll.p() as u32 / pll.q() as u32),
+                i2s,
+                plli2s_sai,
+                pllsai_sai,
+                lcd_tft,
+                source,
+            },
+        })
+    }
+
+    /*
+       Increasing the CPU frequency RM0090 p82
+       APB dividers at their highest while switching, flash wait states raised before SW and
+       lowered after it. HPRE is set before SW when it divides more, after SW otherwise.
+       A running main PLL is left by switching to HSI first, it can not be reconfigured while
+       enabled. SYSCLK above 168 MHz needs the over-drive mode, it is entered and left while
+       SYSCLK is not the PLL.
+       freeze can run again at any time to change SYSCLK and the bus prescalers, e.g. to slow
+       down on battery. Registered clock listeners recompute their dividers afterwards,
+       transfers in flight should be finished before.
+       When HSE does not start the same targets are planned from HSI instead, Clocks::source
+       tells the caller. With HSE running the clock security system is enabled last, an HSE
+       failure afterwards moves the clock tree to HSI from the NMI and PendSV.
+    */
+    pub fn freeze<R: RegisterBus, P: RegisterBus, F: RegisterBus>(
+        &self,
+        rcc: &RccConf<R>,
+        pwr: &PwrConf<P>,
+        flash: &FlashConf<F>,
+    ) -> Result<Clocks, ClockError> {
+        let mut plan = self.plan()?;
+        rcc.enable_peripheral(Peripheral::Pwr);
+        pwr.set_regulator_voltage_scaling_output(1);
+        rcc.set_highest_apb_dividers();
+        rcc.disable_clock_security_system();
+        match self.hse {
+            Some(hse) => {
+                if let Err(RccError::HseStartupTimeout) = rcc.enable_hse(hse.bypass) {
+                    let fallback = ClockConfig { hse: None, ..*self };
+                    plan = fallback.plan().map_err(|_| ClockError::HseStartupTimeout)?;
+                    rcc.enable_hsi();
+                }
+            }
+            None => rcc.enable_hsi(),
+        }
+        // Neither the PLLs nor the over-drive mode may change under a PLL SYSCLK
+        let over_drive = pwr.is_over_drive_enabled();
+        let leave_pll = plan.pll_m.is_some() || over_drive != plan.over_drive;
+        // A reserved SWS value could be anything, HSI is safe either way
+        let on_pll = !matches!(
+            rcc.get_system_clock_status(),
+            Ok(SystemClock::HSI | SystemClock::HSE)
+        );
+        if leave_pll && on_pll {
+            rcc.enable_hsi();
+            rcc.set_system_clock(HSI);
+        }
+        if over_drive && !plan.over_drive {
+            pwr.disable_over_drive();
+        }
+        // PLLM can only change while all three PLLs are off
+        if let Some(m) = plan.pll_m {
+            if rcc.is_main_pll_ready() {
+                rcc.disable_main_pll();
+            }
+            if rcc.is_i2s_pll_ready() {
+                rcc.disable_i2s_pll();
+            }
+            if rcc.is_sai_pll_ready() {
+                rcc.disable_sai_pll();
+            }
+            match plan.pll {
+                Some(pll) => {
+                    rcc.configure_main_pll(&pll);
+                    rcc.enable_main_pll();
+                }
+                None => rcc.configure_pll_input(plan.clocks.source, m),
+            }
+        }
+        // After the PLL locked, before the flash latency and the switch to PLL
+        if plan.over_drive && !over_drive {
+            pwr.enable_over_drive();
+        }
+        /*
+           HCLK stays at or below the old or the new HCLK throughout, so the wait states in
+           place always cover it: a larger AHB divider is set before the switch, a smaller one
+           after it.
+        */
+        let divide_first = plan.ahb_prescaler > rcc.get_ahb_prescaler();
+        let raise_latency = plan.flash_latency >= flash.latency();
+        if raise_latency {
+            flash.configure_access_control(plan.flash_latency, true, true, true);
+        }
+        if divide_first {
+            rcc.set_ahb_prescaler(plan.ahb_prescaler);
+        }
+        rcc.set_system_clock(plan.system_clock);
+        rcc.set_apb_prescaler(plan.apb2_prescaler, plan.apb1_prescaler);
+        if !divide_first {
+            rcc.set_ahb_prescaler(plan.ahb_prescaler);
+        }
+        if !raise_latency {
+            flash.configure_access_control(plan.flash_latency, true, true, true);
+        }
+        if plan.system_clock != SystemClock::PLL {
+            rcc.disable_main_pll();
+        }
+        let pll_from_hsi = plan.pll_m.is_some() && plan.clocks.source == PllClockSource::HSI;
+        if plan.system_clock != HSI && !pll_from_hsi {
+            rcc.disable_hsi();
+        }
+        while !pwr.is_regulator_voltage_scaling_output_ready() {
+            unsafe {
+                no_operation();
+            }
+        }
+        // SYSCLK does not depend on them, a PLL that does not lock leaves the rest running
+        let mut clocks = plan.clocks;
+        let mut result = Ok(());
+        if let Some(i2s_pll) = plan.i2s_pll {
+            rcc.configure_i2s_pll(&i2s_pll);
+            if rcc.enable_i2s_pll().is_err() {
+                clocks.i2s = None;
+                clocks.plli2s_sai = None;
+                result = Err(ClockError::I2sPllLockTimeout);
+            }
+        }
+        if let Some(sai_pll) = plan.sai_pll {
+            rcc.configure_sai_pll(&sai_pll);
+            if rcc.enable_sai_pll().is_err() {
+                clocks.pllsai_sai = None;
+                clocks.lcd_tft = None;
+                result = Err(ClockError::SaiPllLockTimeout);
+            }
+        }
+        apply_clocks(&clocks);
+        if clocks.source == PllClockSource::HSE {
+            rcc.enable_clock_security_system();
+        }
+        result.map(|()| clocks)
+    }
+}
+
+/*
+   Searches PLLP from the lowest VCO frequency up and PLLM from the highest VCO input up, the
+   VCO input should be close to 2 MHz to limit jitter. VCO = SYSCLK * PLLP is always exact,
+   PLLN = VCO * PLLM / f_input has to be an integer.
+*/
+fn find_main_pll(
+    source: PllClockSource,
+    input: u32,
+    sysclk: u32,
+    exact_pll48: bool,
+) -> Result<MainPll, ClockError> {
+    let mut pll48_missed = false;
+    for p in [2u8, 4, 6, 8] {
+        let vco = sysclk as u64 * p as u64;
+        if !(VCO_OUTPUT_RANGE.0 as u64..=VCO_OUTPUT_RANGE.1 as u64).contains(&vco) {
+            continue;
+        }
+        for m in 2u8..=63 {
+            let m_factor = m as u32;
+            if input < VCO_INPUT_RANGE.0 * m_factor || input > VCO_INPUT_RANGE.1 * m_factor {
+                continue;
+            }
+            if !(vco * m as u64).is_multiple_of(input as u64) {
+                continue;
+            }
+            let n = (vco * m as u64 / input as u64) as u16;
+            let q = if exact_pll48 {
+                if !vco.is_multiple_of(USB_FREQUENCY as u64) {
+                    pll48_missed = true;
+                    continue;
+                }
+                vco / USB_FREQUENCY as u64
+            } else {
+                vco.div_ceil(USB_FREQUENCY as u64).max(2)
+            };
+            if let Some(pll) = MainPll::new(source, m, n, p, q as u8) {
+                return Ok(pll);
+            }
+        }
+    }
+    if pll48_missed {
+        Err(ClockError::UsbClockUnreachable)
+    } else {
+        Err(ClockError::SystemClockUnreachable)
+    }
+}
+
+// Highest VCO input up to 2 MHz, ClockConfig::plan checks the lower limit
+fn pll_input_divider(input: u32) -> u8 {
+    (2u8..=63)
+        .find(|&m| input / m as u32 <= VCO_INPUT_RANGE.1)
+        .unwrap_or(63)
+}
+
+fn divides(clock: u32, divider: u32, frequency: u32) -> bool {
+    clock.is_multiple_of(divider) && clock / divider == frequency
+}
+
+// Err(true) when the requested frequency is above the bus limit
+fn apb_prescaler(hclk: u32, requested: Option<u32>, max: u32) -> Result<u8, bool> {
+    match requested {
+        Some(frequency) if frequency > max => Err(true),
+        Some(frequency) => APB_PRESCALERS
+            .into_iter()
+            .find(|&divider| divides(hclk, divider as u32, frequency))
+            .ok_or(false),
+        None => APB_PRESCALERS
+            .into_iter()
+            .find(|&divider| hclk / divider as u32 <= max)
+            .ok_or(false),
+    }
+}
+
+/*
+   SYSCLK = 168MHz
+   PCLK1 = 42MHz
+   PCLK2 = 84MHz
+   TIMxCLK = 2xPCLKx
+   PLL48CK = 48MHz
+   180MHz with over-drive leaves no exact PLL48CK, the VCO would have to be a multiple of both.
+   Should freeze fail anyway the chip goes back to the clock tree after reset, HSI 16MHz.
+*/
+pub fn setup_clock<R: RegisterBus, P: RegisterBus, F: RegisterBus>(
+    rcc: &RccConf<R>,
+    pwr: &PwrConf<P>,
+    flash: &FlashConf<F>,
+) -> Clocks {
+    let clocks = ClockConfig::new()
+        .use_hse(8_000_000, true)
+        .sysclk(168_000_000)
+        .pclk1(42_000_000)
+        .pclk2(84_000_000)
+        .require_pll48()
+        .freeze(rcc, pwr, flash)
+        .unwrap_or_else(|_| {
+            reset_clock(rcc);
+            apply_clocks(&RESET_CLOCKS);
+            RESET_CLOCKS
+        });
+    // Clock of the independent watchdog
+    rcc.enable_internal_low_speed_oscillator();
+    clocks
+}
+
+/*
+   Back to the clock tree after reset, for handing over to an image which expects it:
+   SYSCLK = HSI 16MHz, AHB and APB not divided, PLL, HSE and the clock security system off.
+   Flash wait states are left as they are, too many are only slower.
+*/
+pub fn reset_clock<R: RegisterBus>(rcc: &RccConf<R>) {
+    rcc.enable_hsi();
+    rcc.set_system_clock(HSI);
+    rcc.set_ahb_prescaler(1);
+    rcc.set_apb_prescaler(1, 1);
+    rcc.disable_main_pll();
+    rcc.disable_clock_security_system();
+    rcc.disable_hse();
+    rcc.reset_main_pll_configuration();
+}
+
+/*
+   Clock security NMI and the PendSV handler finishing its work. Only the clock security system
+   raises the NMI here, a software NMI without CSSF set returns right away. Both handlers are
+   always linked, binding another one to NonMaskableInt or PendSV with exception! fails with a
+   duplicate symbol. The handlers make their own register handles, the owners get them back.
+*/
+#[cfg(target_os = "none")]
+mod handlers {
+    use super::{record_hse_failure, recover_from_hse_failure};
+    use crate::rcc::RccConf;
+    use crate::registers::{rcc, scb};
+    use crate::scb::ScbConf;
+
+    fn clock_security_handler() {
+        let rcc = RccConf::new(rcc::BASE_ADDRESS);
+        if rcc.is_hse_failure_detected() {
+            record_hse_failure(&rcc, &ScbConf::new(scb::BASE_ADDRESS));
+        }
+    }
+
+    fn hse_failure_handler() {
+        recover_from_hse_failure(&RccConf::new(rcc::BASE_ADDRESS));
+    }
+
+    crate::exception!(NonMaskableInt, clock_security_handler);
+    crate::exception!(PendSV, hse_failure_handler);
+}
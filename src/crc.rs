@@ -0,0 +1,57 @@
+/*
+   Software CRCs for the firmware update, computed bit by bit to keep the bootloader small.
+
+   CRC-16/XMODEM: polynomial 0x1021, initial value 0, not reflected, protects YMODEM blocks.
+   CRC-32/ISO-HDLC: polynomial 0x04C11DB7 reflected, initial value and final XOR 0xFFFFFFFF,
+   the CRC of zlib and Python's zlib.crc32. The CRC unit of the MCU computes neither of them.
+*/
+pub fn crc16_xmodem(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+#[derive(Clone, Copy)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    pub const fn new() -> Self {
+        Crc32 { state: 0xFFFF_FFFF }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.state = data.iter().fold(self.state, |crc, &byte| {
+            (0..8).fold(crc ^ byte as u32, |crc, _| {
+                if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xEDB8_8320
+                } else {
+                    crc >> 1
+                }
+            })
+        });
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.state
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Crc32::new()
+    }
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
@@ -1,5 +1,11 @@
+use crate::ccmram::is_in_ccmram;
 use crate::memory::store_barrier;
 use crate::memory_mapped_io::MemoryMappedIo;
+use crate::register::{Field, Register};
+use crate::register_bus::RegisterBus;
+use crate::registers::dma::{
+    lisr, s0cr, s0ndtr, HIFCR, HISR, LIFCR, LISR, S0CR, S0M0AR, S0NDTR, S0PAR, S1CR,
+};
 
 #[repr(u32)]
 pub enum DataTransferDirection {
@@ -56,6 +62,7 @@ impl From<PriorityLevel> for u32 {
     }
 }
 
+#[derive(Default)]
 pub struct StreamConf {
     pub data_transfer_direction: Option<DataTransferDirection>,
     pub memory_increment_mode: Option<MemoryIncrementMode>,
@@ -65,122 +72,208 @@ pub struct StreamConf {
     pub peripheral_data_size: Option<PeripheralDataSize>,
 }
 
-impl Default for StreamConf {
-    fn default() -> Self {
-        StreamConf {
-            data_transfer_direction: None,
-            memory_increment_mode: None,
-            channel: None,
-            priority_level: None,
-            memory_data_size: None,
-            peripheral_data_size: None,
-        }
-    }
+// FEIFx, DMEIFx, TEIFx, HTIFx, TCIFx of each stream within a status/clear register
+const STREAM_FLAGS: [[Field; 5]; 4] = [
+    [
+        lisr::FEIF0,
+        lisr::DMEIF0,
+        lisr::TEIF0,
+        lisr::HTIF0,
+        lisr::TCIF0,
+    ],
+    [
+        lisr::FEIF1,
+        lisr::DMEIF1,
+        lisr::TEIF1,
+        lisr::HTIF1,
+        lisr::TCIF1,
+    ],
+    [
+        lisr::FEIF2,
+        lisr::DMEIF2,
+        lisr::TEIF2,
+        lisr::HTIF2,
+        lisr::TCIF2,
+    ],
+    [
+        lisr::FEIF3,
+        lisr::DMEIF3,
+        lisr::TEIF3,
+        lisr::HTIF3,
+        lisr::TCIF3,
+    ],
+];
+const TRANSFER_COMPLETE: usize = 4;
+
+// Distance between the register blocks of two consecutive streams
+const STREAM_STRIDE: usize = S1CR.offset() - S0CR.offset();
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DmaError {
+    // Buffer in a memory the DMA controllers have no bus access to
+    UnreachableMemory,
+}
+
+pub struct DmaConf<B: RegisterBus = MemoryMappedIo> {
+    reg: B,
+}
+
+/*
+   The eight streams of a controller are independent, each one is handed out separately so a
+   driver can own the stream it transfers with. The shared status and clear registers are only
+   accessed through the flags of the owning stream.
+*/
+pub struct DmaStreams<B: RegisterBus = MemoryMappedIo> {
+    pub stream0: DmaStream<B>,
+    pub stream1: DmaStream<B>,
+    pub stream2: DmaStream<B>,
+    pub stream3: DmaStream<B>,
+    pub stream4: DmaStream<B>,
+    pub stream5: DmaStream<B>,
+    pub stream6: DmaStream<B>,
+    pub stream7: DmaStream<B>,
 }
 
-pub struct DmaConf {
-    reg: MemoryMappedIo,
+pub struct DmaStream<B: RegisterBus = MemoryMappedIo> {
+    reg: B,
+    stream_id: u32,
 }
 
 impl DmaConf {
     pub const fn new(base: u32) -> DmaConf {
-        DmaConf {
-            reg: MemoryMappedIo::new(base),
+        DmaConf::with_bus(MemoryMappedIo::new(base))
+    }
+}
+
+impl<B: RegisterBus> DmaConf<B> {
+    pub const fn with_bus(reg: B) -> Self {
+        DmaConf { reg }
+    }
+}
+
+impl<B: RegisterBus + Clone> DmaConf<B> {
+    pub fn split(self) -> DmaStreams<B> {
+        let stream = |stream_id| DmaStream {
+            reg: self.reg.clone(),
+            stream_id,
+        };
+        DmaStreams {
+            stream0: stream(0),
+            stream1: stream(1),
+            stream2: stream(2),
+            stream3: stream(3),
+            stream4: stream(4),
+            stream5: stream(5),
+            stream6: stream(6),
+            stream7: stream(7),
         }
     }
+}
+
+impl<B: RegisterBus> DmaStream<B> {
+    pub fn stream_id(&self) -> u32 {
+        self.stream_id
+    }
 
     #[inline(always)]
-    fn stream_config_offset(stream_id: u32) -> usize {
-        (4 + 6 * stream_id) as usize
+    fn stream_register(&self, register: Register) -> Register {
+        register.nth(self.stream_id as usize, STREAM_STRIDE)
     }
 
-    pub fn enable_stream(&self, stream_id: u32) {
-        self.reg.set_bit(0, Self::stream_config_offset(stream_id));
+    // Streams 0..3 are reported in LISR/LIFCR, streams 4..7 in HISR/HIFCR with the same layout
+    #[inline(always)]
+    fn stream_flags(&self) -> (Register, Register, &'static [Field; 5]) {
+        let flags = &STREAM_FLAGS[(self.stream_id % 4) as usize];
+        if self.stream_id > 3 {
+            (HISR, HIFCR, flags)
+        } else {
+            (LISR, LIFCR, flags)
+        }
+    }
+
+    pub fn enable_stream(&self) {
+        self.reg
+            .write_field(self.stream_register(S0CR), s0cr::EN, 1);
         unsafe {
             store_barrier();
         }
     }
 
-    pub fn disable_stream(&self, stream_id: u32) {
-        self.reg.clear_bit(0, Self::stream_config_offset(stream_id));
+    pub fn disable_stream(&self) {
+        self.reg
+            .write_field(self.stream_register(S0CR), s0cr::EN, 0);
         unsafe {
             store_barrier();
         }
     }
 
-    pub fn is_stream_disabled(&self, stream_id: u32) -> bool {
-        self.reg.read(Self::stream_config_offset(stream_id)) & 0b1 == 0
+    pub fn is_stream_disabled(&self) -> bool {
+        !self.reg.is_field_set(self.stream_register(S0CR), s0cr::EN)
     }
 
     /*
        Before setting EN bit to '1' to start a new transfer, the event flags corresponding to the
        stream in DMA_LISR or DMA_HISR register must be cleared.
     */
-    pub fn set_stream_config(&self, stream_id: u32, config: StreamConf) {
-        let offset: usize = Self::stream_config_offset(stream_id);
-        let mut current_value: u32 = self.reg.read(offset);
+    pub fn set_stream_config(&self, config: StreamConf) {
+        let register = self.stream_register(S0CR);
+        let mut current_value: u32 = self.reg.read_register(register);
         if let Some(data_transfer_direction) = config.data_transfer_direction {
-            current_value &= !(0b11 << 6);
-            current_value |= u32::from(data_transfer_direction) << 6;
+            current_value = s0cr::DIR.write(current_value, data_transfer_direction.into());
         }
         if let Some(channel) = config.channel {
-            current_value &= !(0b111 << 25);
-            current_value |= (channel as u32) << 25;
+            current_value = s0cr::CHSEL.write(current_value, channel as u32);
         }
         if let Some(memory_increment_mode) = config.memory_increment_mode {
-            current_value &= !(0b1 << 10);
-            current_value |= u32::from(memory_increment_mode) << 10;
+            current_value = s0cr::MINC.write(current_value, memory_increment_mode.into());
         }
         if let Some(priority_level) = config.priority_level {
-            current_value &= !(0b11 << 16);
-            current_value |= u32::from(priority_level) << 16;
+            current_value = s0cr::PL.write(current_value, priority_level.into());
         }
         if let Some(memory_data_size) = config.memory_data_size {
-            current_value &= !(0b11 << 13);
-            current_value |= u32::from(memory_data_size) << 13;
+            current_value = s0cr::MSIZE.write(current_value, memory_data_size.into());
         }
         if let Some(peripheral_data_size) = config.peripheral_data_size {
-            current_value &= !(0b11 << 11);
-            current_value |= u32::from(peripheral_data_size) << 11;
+            current_value = s0cr::PSIZE.write(current_value, peripheral_data_size.into());
         }
-        self.reg.write(current_value, offset);
+        self.reg.write_register(register, current_value);
     }
 
     /*
        Number of data items to be transferred (0 up to 65535). This register can be written only
        when the stream is disabled.
     */
-    pub fn set_stream_data_length(&self, stream_id: u32, length: u16) {
-        self.reg.write(length as u32, (5 + 6 * stream_id) as usize);
+    pub fn set_stream_data_length(&self, length: u16) {
+        self.reg.write_register(
+            self.stream_register(S0NDTR),
+            s0ndtr::NDT.value(length as u32),
+        );
     }
 
-    pub fn set_stream_peripheral_address(&self, stream_id: u32, address: u32) {
-        self.reg.write(address, (6 + 6 * stream_id) as usize);
+    pub fn set_stream_peripheral_address(&self, address: u32) {
+        self.reg
+            .write_register(self.stream_register(S0PAR), address);
     }
 
-    pub fn set_stream_memory0_address(&self, stream_id: u32, address: u32) {
-        self.reg.write(address, (7 + 6 * stream_id) as usize)
+    // The DMA controllers are not connected to CCM, see ccmram.rs
+    pub fn set_stream_memory0_address(&self, address: u32) -> Result<(), DmaError> {
+        if is_in_ccmram(address) {
+            return Err(DmaError::UnreachableMemory);
+        }
+        self.reg
+            .write_register(self.stream_register(S0M0AR), address);
+        Ok(())
     }
 
-    pub fn is_transfer_completed(&self, stream_id: u32) -> bool {
-        const SHIFTS: [u32; 4] = [5, 11, 21, 27];
-        let mask = 0b1 << SHIFTS[(stream_id % 4) as usize];
-        if stream_id > 3 {
-            self.reg.read(1) & mask != 0
-        } else {
-            self.reg.read(0) & mask != 0
-        }
+    pub fn is_transfer_completed(&self) -> bool {
+        let (status, _, flags) = self.stream_flags();
+        self.reg.is_field_set(status, flags[TRANSFER_COMPLETE])
     }
 
-    pub fn clear_stream_interrupt_status_register(&self, stream_id: u32) {
-        const VALUE: u32 = 0b1111101;
-        const SHIFTS: [u32; 4] = [0, 6, 16, 22];
-        let reset_value = VALUE << SHIFTS[(stream_id % 4) as usize];
-        if stream_id > 3 {
-            self.reg.write(reset_value, 3);
-        } else {
-            self.reg.write(reset_value, 2);
-        }
+    pub fn clear_stream_interrupt_status_register(&self) {
+        let (_, clear, flags) = self.stream_flags();
+        let value = flags.iter().fold(0, |acc, flag| acc | flag.mask());
+        self.reg.write_register(clear, value);
         unsafe {
             store_barrier();
         }
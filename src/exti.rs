@@ -1,34 +1,36 @@
 use crate::memory::store_barrier;
 use crate::memory_mapped_io::MemoryMappedIo;
+use crate::register_bus::RegisterBus;
+use crate::registers::exti::{imr, pr, rtsr, IMR, PR, RTSR};
 
-pub struct ExtiConf {
-    reg: MemoryMappedIo,
+pub struct ExtiConf<B: RegisterBus = MemoryMappedIo> {
+    reg: B,
 }
 
 impl ExtiConf {
     pub const fn new(base: u32) -> Self {
-        ExtiConf {
-            reg: MemoryMappedIo::new(base),
-        }
+        ExtiConf::with_bus(MemoryMappedIo::new(base))
+    }
+}
+
+impl<B: RegisterBus> ExtiConf<B> {
+    pub const fn with_bus(reg: B) -> Self {
+        ExtiConf { reg }
     }
 
     pub fn unmask_interrupt(&self, interrupt_number: u32) {
-        let mut current_value = self.reg.read(0);
-        current_value |= 0b1 << interrupt_number;
-        self.reg.write(current_value, 0);
+        self.reg.write_field(IMR, imr::MR0.nth(interrupt_number), 1);
     }
 
     pub fn enable_rising_trigger(&self, interrupt_number: u32) {
-        let mut current_value = self.reg.read(2);
-        current_value |= 0b1 << interrupt_number;
-        self.reg.write(current_value, 2);
+        self.reg
+            .write_field(RTSR, rtsr::TR0.nth(interrupt_number), 1);
     }
 
     pub fn clear_pending(&self, interrupt_number: u32) {
         unsafe {
-            let mut current_value = self.reg.read(5);
-            current_value |= 0b1 << interrupt_number;
-            self.reg.write(current_value, 5);
+            self.reg
+                .write_one_to_clear(PR, pr::PR0.nth(interrupt_number));
             store_barrier();
         }
     }
@@ -0,0 +1,183 @@
+use crate::registers::scb::{cfsr, hfsr};
+use crate::scb::FaultStatus;
+use crate::stm32f439zitx::Exception;
+use core::fmt::{Result, Write};
+
+/*
+   Fault reporting, PM0214 p42 and p232.
+
+   HardFault, MemoryManagement, BusFault and UsageFault enter through a short assembly
+   trampoline which picks the stack the exception frame was pushed to (MSP or PSP, bit 2 of
+   EXC_RETURN in LR). The handler prints the frame and the decoded fault status registers with
+   UsartSingleByteDriver on USART3 and resets the MCU. USART3 is left untouched when the
+   application did not enable its transmitter yet, the report is skipped in that case.
+   A stack overflow into the MPU guard (stack.rs) leaves SP below the stack, the trampoline
+   moves it back to the top so the handler has room to run.
+
+   The handlers are always linked, binding another one to these exceptions with exception!
+   fails with a duplicate symbol.
+*/
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ExceptionFrame {
+    pub r0: u32,
+    pub r1: u32,
+    pub r2: u32,
+    pub r3: u32,
+    pub r12: u32,
+    pub lr: u32,
+    pub pc: u32,
+    pub xpsr: u32,
+}
+
+const CONFIGURABLE_FAULTS: [(u32, &str); 19] = [
+    (
+        cfsr::IACCVIOL.mask(),
+        "IACCVIOL instruction access violation",
+    ),
+    (cfsr::DACCVIOL.mask(), "DACCVIOL data access violation"),
+    (
+        cfsr::MUNSTKERR.mask(),
+        "MUNSTKERR MemManage fault on unstacking",
+    ),
+    (cfsr::MSTKERR.mask(), "MSTKERR MemManage fault on stacking"),
+    (
+        cfsr::MLSPERR.mask(),
+        "MLSPERR MemManage fault on FPU lazy state",
+    ),
+    (cfsr::MMARVALID.mask(), "MMARVALID MMFAR holds the address"),
+    (cfsr::IBUSERR.mask(), "IBUSERR instruction bus error"),
+    (cfsr::PRECISERR.mask(), "PRECISERR precise data bus error"),
+    (
+        cfsr::IMPRECISERR.mask(),
+        "IMPRECISERR imprecise data bus error",
+    ),
+    (cfsr::UNSTKERR.mask(), "UNSTKERR bus fault on unstacking"),
+    (cfsr::STKERR.mask(), "STKERR bus fault on stacking"),
+    (cfsr::LSPERR.mask(), "LSPERR bus fault on FPU lazy state"),
+    (cfsr::BFARVALID.mask(), "BFARVALID BFAR holds the address"),
+    (cfsr::UNDEFINSTR.mask(), "UNDEFINSTR undefined instruction"),
+    (cfsr::INVSTATE.mask(), "INVSTATE invalid EPSR state"),
+    (cfsr::INVPC.mask(), "INVPC invalid EXC_RETURN"),
+    (cfsr::NOCP.mask(), "NOCP coprocessor not enabled"),
+    (cfsr::UNALIGNED.mask(), "UNALIGNED unaligned access"),
+    (cfsr::DIVBYZERO.mask(), "DIVBYZERO division by zero"),
+];
+
+const HARD_FAULTS: [(u32, &str); 3] = [
+    (hfsr::VECTTBL.mask(), "VECTTBL vector table read fault"),
+    (hfsr::FORCED.mask(), "FORCED escalated configurable fault"),
+    (hfsr::DEBUG_VT.mask(), "DEBUG_VT debug event"),
+];
+
+fn exception_name(exception: u32) -> &'static str {
+    match exception {
+        e if e == Exception::HardFault as u32 => "HardFault",
+        e if e == Exception::MemoryManagement as u32 => "MemManage",
+        e if e == Exception::BusFault as u32 => "BusFault",
+        e if e == Exception::UsageFault as u32 => "UsageFault",
+        _ => "Fault",
+    }
+}
+
+fn write_flags(output: &mut impl Write, value: u32, flags: &[(u32, &str)]) -> Result {
+    for (mask, description) in flags {
+        if value & mask != 0 {
+            write!(output, "  {description}\r\n")?;
+        }
+    }
+    Ok(())
+}
+
+pub fn write_fault_report(
+    output: &mut impl Write,
+    exception: u32,
+    frame: &ExceptionFrame,
+    status: &FaultStatus,
+    stack_overflow: bool,
+) -> Result {
+    write!(output, "\r\n*** {} ***\r\n", exception_name(exception))?;
+    if stack_overflow {
+        // The frame could not be stacked completely, the registers below are not reliable
+        write!(output, "Stack overflow into the guard region\r\n")?;
+    }
+    write!(
+        output,
+        "R0   = {:#010x}  R1 = {:#010x}  R2 = {:#010x}  R3 = {:#010x}\r\n",
+        frame.r0, frame.r1, frame.r2, frame.r3
+    )?;
+    write!(
+        output,
+        "R12  = {:#010x}  LR = {:#010x}  PC = {:#010x}  xPSR = {:#010x}\r\n",
+        frame.r12, frame.lr, frame.pc, frame.xpsr
+    )?;
+    write!(output, "CFSR = {:#010x}\r\n", status.configurable)?;
+    write_flags(output, status.configurable, &CONFIGURABLE_FAULTS)?;
+    write!(output, "HFSR = {:#010x}\r\n", status.hard)?;
+    write_flags(output, status.hard, &HARD_FAULTS)?;
+    if status.configurable & cfsr::MMARVALID.mask() != 0 {
+        write!(
+            output,
+            "MMFAR = {:#010x}\r\n",
+            status.memory_management_address
+        )?;
+    }
+    if status.configurable & cfsr::BFARVALID.mask() != 0 {
+        write!(output, "BFAR = {:#010x}\r\n", status.bus_fault_address)?;
+    }
+    write!(output, "Resetting\r\n")
+}
+
+#[cfg(target_os = "none")]
+mod handlers {
+    use super::{write_fault_report, ExceptionFrame};
+    use crate::registers::scb::cfsr;
+    use crate::stack::stack_guard_bounds;
+    use crate::stm32f439zitx::Peripherals;
+    use crate::usart::UsartSingleByteDriver;
+    use core::arch::global_asm;
+
+    // Passes the stacked frame and the exception number to fault_handler
+    macro_rules! fault_trampoline {
+        ($name:literal, $exception:literal) => {
+            global_asm!(
+                concat!(".section .text.", $name, ",\"ax\",%progbits"),
+                concat!(".global ", $name),
+                concat!(".type ", $name, ",%function"),
+                ".thumb_func",
+                concat!($name, ":"),
+                "tst lr, #4",
+                "ite eq",
+                "mrseq r0, msp",
+                "mrsne r0, psp",
+                concat!("movs r1, #", $exception),
+                "ldr r2, =_stack_guard_end",
+                "cmp sp, r2",
+                "itt lo",
+                "ldrlo r2, =_stack_end",
+                "movlo sp, r2",
+                "b {handler}",
+                handler = sym fault_handler,
+            );
+        };
+    }
+
+    fault_trampoline!("HardFault", 3);
+    fault_trampoline!("MemoryManagement", 4);
+    fault_trampoline!("BusFault", 5);
+    fault_trampoline!("UsageFault", 6);
+
+    unsafe extern "C" fn fault_handler(frame: &ExceptionFrame, exception: u32) -> ! {
+        // Nothing returns to the application from here
+        let peripherals = Peripherals::steal();
+        let status = peripherals.scb.fault_status();
+        let stack_overflow = status.configurable & cfsr::MSTKERR.mask() != 0
+            || (status.configurable & cfsr::MMARVALID.mask() != 0
+                && stack_guard_bounds().contains(&status.memory_management_address));
+        if peripherals.usart3.is_transmitter_enabled() {
+            let mut usart = UsartSingleByteDriver::new(peripherals.usart3);
+            let _ = write_fault_report(&mut usart, exception, frame, &status, stack_overflow);
+        }
+        peripherals.scb.system_reset()
+    }
+}
@@ -1,19 +1,93 @@
 use crate::asm::no_operation;
-use crate::clear_mask;
 use crate::memory::store_barrier;
 use crate::memory_mapped_io::MemoryMappedIo;
+use crate::register_bus::RegisterBus;
+use crate::registers::flash::{acr, cr, keyr, sr, ACR, CR, KEYR, SR};
 
-pub struct FlashConf {
-    reg: MemoryMappedIo,
+pub const FLASH_START: u32 = 0x0800_0000;
+pub const FLASH_SIZE: u32 = 2 * 1024 * 1024;
+const BANK_SIZE: u32 = FLASH_SIZE / 2;
+const SECTORS_PER_BANK: u8 = 12;
+
+// RM0090 p85, any other value written to KEYR locks FLASH_CR until the next reset
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xCDEF_89AB;
+
+// PSIZE for 32 bit parallelism, needs a supply of 2.7 V to 3.6 V, RM0090 p85
+const PROGRAM_SIZE_WORD: u32 = 0b10;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlashError {
+    WriteProtection,
+    ProgrammingSequence,
+    ProgrammingParallelism,
+    ProgrammingAlignment,
+    Operation,
+    OutOfRange,
+    // Only reported by SimulatedFlash, the hardware silently ANDs the new value into the old one
+    NotErased,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FlashSector {
+    pub number: u8,
+    pub address: u32,
+    pub size: u32,
 }
 
-const FLASH_ACR: usize = 0;
+/*
+   RM0090 p77, 2 MB devices have two banks with the same layout:
+   4 sectors of 16 KB, 1 sector of 64 KB and 7 sectors of 128 KB.
+   Sectors 0 to 11 are in bank 1, 12 to 23 in bank 2.
+*/
+pub fn flash_sector(address: u32) -> Option<FlashSector> {
+    if !(FLASH_START..FLASH_START + FLASH_SIZE).contains(&address) {
+        return None;
+    }
+    let bank = (address - FLASH_START) / BANK_SIZE;
+    let bank_start = FLASH_START + bank * BANK_SIZE;
+    let offset = address - bank_start;
+    let (index, sector_offset, size) = match offset {
+        0..0x1_0000 => (offset / 0x4000, offset / 0x4000 * 0x4000, 0x4000),
+        0x1_0000..0x2_0000 => (4, 0x1_0000, 0x1_0000),
+        _ => {
+            let index = (offset - 0x2_0000) / 0x2_0000;
+            (5 + index, 0x2_0000 + index * 0x2_0000, 0x2_0000)
+        }
+    };
+    Some(FlashSector {
+        number: (bank as u8) * SECTORS_PER_BANK + index as u8,
+        address: bank_start + sector_offset,
+        size,
+    })
+}
+
+/*
+   Flash as seen by the update flow. Addresses and lengths passed to program are multiples of
+   4, a sector has to be erased before it is programmed.
+*/
+pub trait FlashMemory {
+    // Erases the sector containing address
+    fn erase_sector(&mut self, address: u32) -> Result<FlashSector, FlashError>;
+
+    fn program(&mut self, address: u32, data: &[u8]) -> Result<(), FlashError>;
+
+    fn read(&self, address: u32, buffer: &mut [u8]);
+}
+
+pub struct FlashConf<B: RegisterBus = MemoryMappedIo> {
+    reg: B,
+}
 
 impl FlashConf {
     pub const fn new(base: u32) -> Self {
-        FlashConf {
-            reg: MemoryMappedIo::new(base),
-        }
+        FlashConf::with_bus(MemoryMappedIo::new(base))
+    }
+}
+
+impl<B: RegisterBus> FlashConf<B> {
+    pub const fn with_bus(reg: B) -> Self {
+        FlashConf { reg }
     }
 
     /*
@@ -27,25 +101,195 @@ impl FlashConf {
         data_cache: bool,
         prefetch: bool,
     ) {
-        let mut current_value = self.reg.read(FLASH_ACR);
-        current_value &= clear_mask!(4, 0);
-        current_value |= latency as u32;
-        current_value &= clear_mask!(3, 8); // PRFTEN, ICEN, DCEN
-        if instruction_cache {
-            current_value |= 0b1 << 9;
-        }
-        if data_cache {
-            current_value |= 0b1 << 10;
+        self.reg.modify_register(ACR, |current| {
+            let mut value = acr::LATENCY.write(current, latency as u32);
+            value = acr::ICEN.write(value, instruction_cache as u32);
+            value = acr::DCEN.write(value, data_cache as u32);
+            acr::PRFTEN.write(value, prefetch as u32)
+        });
+        unsafe {
+            store_barrier();
+            while self.reg.read_field(ACR, acr::LATENCY) != (latency as u32) {
+                no_operation();
+            }
         }
-        if prefetch {
-            current_value |= 0b1 << 8;
+    }
+
+    pub fn latency(&self) -> u8 {
+        self.reg.read_field(ACR, acr::LATENCY) as u8
+    }
+
+    /*
+       RM0090 p98
+       The caches may hold lines of erased or reprogrammed sectors, they can only be reset
+       while disabled.
+    */
+    pub fn reset_caches(&self) {
+        let control = self.reg.read_register(ACR);
+        let disabled = acr::ICEN.write(acr::DCEN.write(control, 0), 0);
+        self.reg.write_register(ACR, disabled);
+        self.reg
+            .write_register(ACR, disabled | acr::ICRST.mask() | acr::DCRST.mask());
+        self.reg.write_register(ACR, disabled);
+        self.reg.write_register(ACR, control);
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.reg.is_field_set(CR, cr::LOCK)
+    }
+
+    // RM0090 p85, writing the key sequence to an unlocked FLASH_CR would lock it for good
+    pub fn unlock(&self) {
+        if self.is_locked() {
+            self.reg.write_register(KEYR, keyr::KEY.value(KEY1));
+            self.reg.write_register(KEYR, keyr::KEY.value(KEY2));
         }
-        self.reg.write(current_value, FLASH_ACR);
+    }
+
+    pub fn lock(&self) {
+        self.reg.set_bit(CR, cr::LOCK);
+    }
+
+    /*
+       RM0090 p106
+       Waits for BSY to drop, then reports and clears the error flags of the last operation.
+    */
+    pub fn wait_for_last_operation(&self) -> Result<(), FlashError> {
         unsafe {
             store_barrier();
-            while self.reg.read(FLASH_ACR) & 0b1111 != (latency as u32) {
+            while self.reg.is_field_set(SR, sr::BSY) {
                 no_operation();
             }
         }
+        let status = self.reg.read_register(SR);
+        let errors = [
+            (sr::WRPERR, FlashError::WriteProtection),
+            (sr::PGSERR, FlashError::ProgrammingSequence),
+            (sr::PGPERR, FlashError::ProgrammingParallelism),
+            (sr::PGAERR, FlashError::ProgrammingAlignment),
+            (sr::OPERR, FlashError::Operation),
+        ];
+        let flags = errors
+            .iter()
+            .fold(sr::EOP.mask(), |acc, (field, _)| acc | field.mask());
+        // rc_w1, a single write of the mask clears only those flags
+        if status & flags != 0 {
+            self.reg.write_register(SR, status & flags);
+        }
+        match errors.iter().find(|(field, _)| field.is_set(status)) {
+            Some((_, error)) => Err(*error),
+            None => Ok(()),
+        }
+    }
+
+    /*
+       RM0090 p86
+       SNB codes sectors 12 to 23 of bank 2 as 0b10000 to 0b11011.
+    */
+    pub fn erase_sector(&self, number: u8) -> Result<(), FlashError> {
+        let bank = number / SECTORS_PER_BANK;
+        let sector = (bank << 4) | (number % SECTORS_PER_BANK);
+        self.wait_for_last_operation()?;
+        self.reg.modify_register(CR, |current| {
+            let value = cr::PG.write(current, 0);
+            let value = cr::SER.write(value, 1);
+            let value = cr::SNB.write(value, sector as u32);
+            cr::PSIZE.write(value, PROGRAM_SIZE_WORD)
+        });
+        self.reg.set_bit(CR, cr::STRT);
+        let result = self.wait_for_last_operation();
+        self.reg.clear_bit(CR, cr::SER);
+        result
+    }
+
+    // RM0090 p87, while PG is set every write to the flash memory programs a word
+    pub fn enable_programming(&self) -> Result<(), FlashError> {
+        self.wait_for_last_operation()?;
+        self.reg.modify_register(CR, |current| {
+            let value = cr::SER.write(current, 0);
+            let value = cr::PSIZE.write(value, PROGRAM_SIZE_WORD);
+            cr::PG.write(value, 1)
+        });
+        Ok(())
+    }
+
+    pub fn disable_programming(&self) {
+        self.reg.clear_bit(CR, cr::PG);
+    }
+}
+
+#[cfg(target_os = "none")]
+mod internal {
+    use super::{flash_sector, FlashConf, FlashError, FlashMemory, FlashSector};
+    use crate::register_bus::RegisterBus;
+    use core::ptr::{read_volatile, write_volatile};
+
+    /*
+       The flash memory of the MCU behind FlashConf. FLASH_CR is unlocked for each operation
+       and locked again afterwards. Code running from the bank being written stalls until the
+       operation is done.
+    */
+    pub struct InternalFlash<B: RegisterBus> {
+        control: FlashConf<B>,
+    }
+
+    impl<B: RegisterBus> InternalFlash<B> {
+        pub const fn new(control: FlashConf<B>) -> Self {
+            InternalFlash { control }
+        }
+
+        pub fn release(self) -> FlashConf<B> {
+            self.control
+        }
+    }
+
+    impl<B: RegisterBus> FlashMemory for InternalFlash<B> {
+        fn erase_sector(&mut self, address: u32) -> Result<FlashSector, FlashError> {
+            let sector = flash_sector(address).ok_or(FlashError::OutOfRange)?;
+            self.control.unlock();
+            let result = self.control.erase_sector(sector.number);
+            self.control.lock();
+            self.control.reset_caches();
+            result.map(|_| sector)
+        }
+
+        fn program(&mut self, address: u32, data: &[u8]) -> Result<(), FlashError> {
+            if !address.is_multiple_of(4) || !data.len().is_multiple_of(4) {
+                return Err(FlashError::ProgrammingAlignment);
+            }
+            if data.is_empty() {
+                return Ok(());
+            }
+            if flash_sector(address).is_none()
+                || flash_sector(address + data.len() as u32 - 1).is_none()
+            {
+                return Err(FlashError::OutOfRange);
+            }
+            self.control.unlock();
+            let mut result = self.control.enable_programming();
+            for (index, word) in data.chunks_exact(4).enumerate() {
+                if result.is_err() {
+                    break;
+                }
+                let value = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+                unsafe {
+                    write_volatile((address + 4 * index as u32) as *mut u32, value);
+                }
+                result = self.control.wait_for_last_operation();
+            }
+            self.control.disable_programming();
+            self.control.lock();
+            self.control.reset_caches();
+            result
+        }
+
+        fn read(&self, address: u32, buffer: &mut [u8]) {
+            for (index, byte) in buffer.iter_mut().enumerate() {
+                *byte = unsafe { read_volatile((address + index as u32) as *const u8) };
+            }
+        }
     }
 }
+
+#[cfg(target_os = "none")]
+pub use internal::InternalFlash;
@@ -0,0 +1,33 @@
+use crate::memory::{data_barrier, instruction_barrier};
+use crate::memory_mapped_io::MemoryMappedIo;
+use crate::register_bus::RegisterBus;
+use crate::registers::fpu_cpacr::{cpacr, CPACR};
+
+pub struct FpuConf<B: RegisterBus = MemoryMappedIo> {
+    reg: B,
+}
+
+impl FpuConf {
+    pub const fn new(base: u32) -> Self {
+        FpuConf::with_bus(MemoryMappedIo::new(base))
+    }
+}
+
+impl<B: RegisterBus> FpuConf<B> {
+    pub const fn with_bus(reg: B) -> Self {
+        FpuConf { reg }
+    }
+
+    /*
+       PM0214 p255
+       CP10 and CP11 full access, the barriers make sure no floating point instruction is
+       executed before the new access rights are in place.
+    */
+    pub fn enable_full_access(&self) {
+        self.reg.write_field(CPACR, cpacr::CP, 0b1111);
+        unsafe {
+            data_barrier();
+            instruction_barrier();
+        }
+    }
+}
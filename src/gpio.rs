@@ -1,8 +1,11 @@
-use crate::clear_mask;
 use crate::memory_mapped_io::MemoryMappedIo;
+use crate::register_bus::RegisterBus;
+use crate::registers::gpio::{
+    afrl, bsrr, idr, moder, odr, ospeedr, AFRH, AFRL, BSRR, IDR, MODER, ODR, OSPEEDR,
+};
 
-pub struct GpioConf {
-    reg: MemoryMappedIo,
+pub struct GpioConf<B: RegisterBus = MemoryMappedIo> {
+    reg: B,
 }
 
 #[repr(u32)]
@@ -16,6 +19,8 @@ pub enum PinMode {
 #[repr(u32)]
 #[derive(Clone, Copy)]
 pub enum AlternateFunction {
+    // MCO1, MCO2, SWD, JTAG, TRACE and RTC_REFIN
+    System = 0b0000,
     Usart1_3 = 0b0111,
 }
 
@@ -30,24 +35,21 @@ pub enum OutputSpeed {
 
 impl GpioConf {
     pub const fn new(base: u32) -> GpioConf {
-        GpioConf {
-            reg: MemoryMappedIo::new(base),
-        }
+        GpioConf::with_bus(MemoryMappedIo::new(base))
+    }
+}
+
+impl<B: RegisterBus> GpioConf<B> {
+    pub const fn with_bus(reg: B) -> Self {
+        GpioConf { reg }
     }
 
     pub fn set_pins_mode(&self, mode: PinMode, pins: &[u32]) {
-        let mut current_value = self.reg.read(0);
-        let mut mask: u32 = 0;
-        let mut mode_value: u32 = 0;
-        for element in pins.iter() {
-            let shift = element << 1;
-            let value: u32 = !(0b11 << shift);
-            mask &= value;
-            mode_value |= (mode as u32) << shift;
-        }
-        current_value &= mask;
-        current_value |= mode_value;
-        self.reg.write(current_value, 0);
+        self.reg.modify_register(MODER, |current| {
+            pins.iter().fold(current, |acc, &pin| {
+                moder::MODER0.nth(pin).write(acc, mode as u32)
+            })
+        });
     }
 
     pub fn set_pin_mode(&self, mode: PinMode, pin: u32) {
@@ -55,31 +57,35 @@ impl GpioConf {
     }
 
     pub fn set_pin(&self, pin: u32) {
-        self.reg.write(0b1 << pin, 6);
+        self.reg.write_register(BSRR, bsrr::BS0.nth(pin).value(1));
+    }
+
+    pub fn reset_pin(&self, pin: u32) {
+        self.reg.write_register(BSRR, bsrr::BR0.nth(pin).value(1));
+    }
+
+    pub fn is_pin_high(&self, pin: u32) -> bool {
+        self.reg.is_field_set(IDR, idr::IDR0.nth(pin))
     }
 
+    // ODR is only read, BSRR changes the one pin in a single store like set_pin and reset_pin
     pub fn switch_pin_output(&self, pin: u32) {
-        let pin_value = self.reg.read(5) & (0b1 << pin);
-        if pin_value != 0 {
-            self.reg.write(0b1 << (pin + 16), 6);
+        if self.reg.is_field_set(ODR, odr::ODR0.nth(pin)) {
+            self.reset_pin(pin);
         } else {
-            self.reg.write(0b1 << pin, 6);
+            self.set_pin(pin);
         }
     }
 
     pub fn set_alternate_function(&self, pin: u32, function: AlternateFunction) {
-        let shift: u32 = (pin % 8) << 2;
-        let offset: usize = (pin / 8) as usize;
-        let mut current_value = self.reg.read(8 + offset);
-        current_value &= !(0b1111 << shift);
-        current_value |= (function as u32) << shift;
-        self.reg.write(current_value, 8 + offset);
+        // AFRH directly follows AFRL and holds pins 8 to 15
+        let register = AFRL.nth((pin / 8) as usize, AFRH.offset() - AFRL.offset());
+        self.reg
+            .write_field(register, afrl::AFRL0.nth(pin % 8), function as u32);
     }
 
     pub fn set_output_speed(&self, pin: u32, speed: OutputSpeed) {
-        let mut current_value = self.reg.read(2);
-        current_value &= !(0b11 << (pin << 1));
-        current_value |= (speed as u32) << (pin << 1);
-        self.reg.write(current_value, 2);
+        self.reg
+            .write_field(OSPEEDR, ospeedr::OSPEEDR0.nth(pin), speed as u32);
     }
 }
@@ -0,0 +1,224 @@
+use core::alloc::Layout;
+use core::fmt::{Display, Formatter, Result};
+use core::mem::{align_of, size_of};
+use core::ptr::null_mut;
+
+/*
+   Heap for the optional global allocator (heap feature).
+
+   First-fit allocator over a free list kept in address order. Every free block starts with
+   its size and the next free block, so a block is never smaller than FreeBlock. Freed blocks
+   are merged with their neighbours right away, which keeps the list short for the typical
+   embedded pattern of a few long-lived allocations.
+
+   On the MCU the region is _heap_start.._heap_end from the linker script, by default all RAM
+   between .uninit and the stack. Running out of memory ends in the default alloc error
+   handler, which panics, the panic report then includes the heap statistics.
+*/
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+const BLOCK_ALIGN: usize = align_of::<FreeBlock>();
+const MIN_BLOCK_SIZE: usize = size_of::<FreeBlock>();
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HeapStatistics {
+    pub used: usize,
+    pub free: usize,
+    pub peak: usize,
+}
+
+pub struct Heap {
+    free: *mut FreeBlock,
+    size: usize,
+    used: usize,
+    peak: usize,
+}
+
+const fn align_up(address: usize, align: usize) -> usize {
+    (address + align - 1) & !(align - 1)
+}
+
+// Size actually taken for the layout, dealloc recomputes it from the same layout
+fn block_size(layout: Layout) -> usize {
+    align_up(layout.size().max(MIN_BLOCK_SIZE), BLOCK_ALIGN)
+}
+
+impl Heap {
+    pub const fn empty() -> Self {
+        Heap {
+            free: null_mut(),
+            size: 0,
+            used: 0,
+            peak: 0,
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The region must be unused memory valid for the lifetime of the heap, and the heap must
+    /// not have been initialized before.
+    pub unsafe fn init(&mut self, start: usize, size: usize) {
+        let aligned_start = align_up(start, BLOCK_ALIGN);
+        let size = (start + size).saturating_sub(aligned_start) & !(BLOCK_ALIGN - 1);
+        if size < MIN_BLOCK_SIZE {
+            return;
+        }
+        let block = aligned_start as *mut FreeBlock;
+        block.write(FreeBlock {
+            size,
+            next: null_mut(),
+        });
+        self.free = block;
+        self.size = size;
+    }
+
+    pub fn statistics(&self) -> HeapStatistics {
+        HeapStatistics {
+            used: self.used,
+            free: self.size - self.used,
+            peak: self.peak,
+        }
+    }
+
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let size = block_size(layout);
+        let align = layout.align().max(BLOCK_ALIGN);
+        let mut previous: *mut *mut FreeBlock = &mut self.free;
+        unsafe {
+            let mut current = self.free;
+            while !current.is_null() {
+                let block_start = current as usize;
+                let block_end = block_start + (*current).size;
+                let mut start = align_up(block_start, align);
+                // Padding in front has to be big enough to stay in the list as a block
+                if start != block_start && start - block_start < MIN_BLOCK_SIZE {
+                    start = align_up(block_start + MIN_BLOCK_SIZE, align);
+                }
+                let end = start + size;
+                let remainder = block_end.saturating_sub(end);
+                if end <= block_end && (remainder == 0 || remainder >= MIN_BLOCK_SIZE) {
+                    let mut next = (*current).next;
+                    if remainder != 0 {
+                        let tail = end as *mut FreeBlock;
+                        tail.write(FreeBlock {
+                            size: remainder,
+                            next,
+                        });
+                        next = tail;
+                    }
+                    if start == block_start {
+                        *previous = next;
+                    } else {
+                        (*current).size = start - block_start;
+                        (*current).next = next;
+                    }
+                    self.used += size;
+                    self.peak = self.peak.max(self.used);
+                    return start as *mut u8;
+                }
+                previous = &mut (*current).next;
+                current = (*current).next;
+            }
+        }
+        null_mut()
+    }
+
+    /// # Safety
+    ///
+    /// The pointer must come from allocate on this heap with the same layout.
+    pub unsafe fn deallocate(&mut self, pointer: *mut u8, layout: Layout) {
+        let size = block_size(layout);
+        let address = pointer as usize;
+        let mut previous: *mut FreeBlock = null_mut();
+        let mut current = self.free;
+        while !current.is_null() && (current as usize) < address {
+            previous = current;
+            current = (*current).next;
+        }
+        let block = pointer as *mut FreeBlock;
+        block.write(FreeBlock {
+            size,
+            next: current,
+        });
+        if !current.is_null() && address + size == current as usize {
+            (*block).size += (*current).size;
+            (*block).next = (*current).next;
+        }
+        if previous.is_null() {
+            self.free = block;
+        } else if previous as usize + (*previous).size == address {
+            (*previous).size += (*block).size;
+            (*previous).next = (*block).next;
+        } else {
+            (*previous).next = block;
+        }
+        self.used -= size;
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl Display for HeapStatistics {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> Result {
+        write!(
+            formatter,
+            "heap used {} free {} peak {} bytes",
+            self.used, self.free, self.peak
+        )
+    }
+}
+
+#[cfg(target_os = "none")]
+mod global {
+    use super::{Heap, HeapStatistics};
+    use crate::interrupt;
+    use core::alloc::{GlobalAlloc, Layout};
+    use core::cell::UnsafeCell;
+    use core::ptr::addr_of;
+
+    extern "C" {
+        static _heap_start: u8;
+        static _heap_end: u8;
+    }
+
+    // Accessed with interrupts masked only, allocating from a handler is fine
+    struct LockedHeap(UnsafeCell<Heap>);
+
+    unsafe impl Sync for LockedHeap {}
+
+    unsafe impl GlobalAlloc for LockedHeap {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            interrupt::free(|| (*self.0.get()).allocate(layout))
+        }
+
+        unsafe fn dealloc(&self, pointer: *mut u8, layout: Layout) {
+            interrupt::free(|| (*self.0.get()).deallocate(pointer, layout))
+        }
+    }
+
+    #[global_allocator]
+    static HEAP: LockedHeap = LockedHeap(UnsafeCell::new(Heap::empty()));
+
+    // Called by the reset handler once .bss is zeroed
+    pub(crate) unsafe fn init() {
+        let start = addr_of!(_heap_start) as usize;
+        let end = addr_of!(_heap_end) as usize;
+        (*HEAP.0.get()).init(start, end - start);
+    }
+
+    pub fn statistics() -> HeapStatistics {
+        interrupt::free(|| unsafe { (*HEAP.0.get()).statistics() })
+    }
+}
+
+#[cfg(target_os = "none")]
+pub(crate) use global::init;
+#[cfg(target_os = "none")]
+pub use global::statistics;
@@ -1,20 +1,26 @@
 use crate::memory::store_barrier;
 use crate::memory_mapped_io::MemoryMappedIo;
+use crate::register_bus::RegisterBus;
+use crate::registers::iwdg::{kr, KR};
 
-pub struct IndependentWatchdogConf {
-    reg: MemoryMappedIo,
+pub struct IndependentWatchdogConf<B: RegisterBus = MemoryMappedIo> {
+    reg: B,
 }
 
 impl IndependentWatchdogConf {
     pub const fn new(base: u32) -> IndependentWatchdogConf {
-        IndependentWatchdogConf {
-            reg: MemoryMappedIo::new(base),
-        }
+        IndependentWatchdogConf::with_bus(MemoryMappedIo::new(base))
+    }
+}
+
+impl<B: RegisterBus> IndependentWatchdogConf<B> {
+    pub const fn with_bus(reg: B) -> Self {
+        IndependentWatchdogConf { reg }
     }
 
     pub fn set_key(&self, key: u16) {
         unsafe {
-            self.reg.write(key as u32, 0);
+            self.reg.write_register(KR, kr::KEY.value(key as u32));
             store_barrier();
         }
     }
@@ -0,0 +1,53 @@
+#[cfg(target_arch = "arm")]
+use core::arch::asm;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+/*
+   Runs the closure with interrupts masked (PRIMASK) and restores the previous state
+   afterwards, so critical sections can be nested and used from handlers.
+*/
+#[cfg(target_arch = "arm")]
+#[inline(always)]
+pub fn free<R>(critical_section: impl FnOnce() -> R) -> R {
+    let primask: u32;
+    unsafe {
+        asm!("MRS {}, PRIMASK", out(reg) primask, options(nomem, nostack, preserves_flags));
+        asm!("CPSID i", options(nomem, nostack, preserves_flags));
+    }
+    compiler_fence(Ordering::SeqCst);
+    let result = critical_section();
+    compiler_fence(Ordering::SeqCst);
+    // PRIMASK bit 0 set means interrupts were already masked by the caller
+    if primask & 1 == 0 {
+        unsafe {
+            asm!("CPSIE i", options(nomem, nostack, preserves_flags));
+        }
+    }
+    result
+}
+
+// Host builds have no interrupts preempting the driver code
+#[cfg(not(target_arch = "arm"))]
+#[inline(always)]
+pub fn free<R>(critical_section: impl FnOnce() -> R) -> R {
+    compiler_fence(Ordering::SeqCst);
+    let result = critical_section();
+    compiler_fence(Ordering::SeqCst);
+    result
+}
+
+// Masks interrupts for good, used on the way to a reset
+#[cfg(target_arch = "arm")]
+#[inline(always)]
+pub fn disable() {
+    unsafe {
+        asm!("CPSID i", options(nomem, nostack, preserves_flags));
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+#[cfg(not(target_arch = "arm"))]
+#[inline(always)]
+pub fn disable() {
+    compiler_fence(Ordering::SeqCst);
+}
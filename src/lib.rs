@@ -0,0 +1,54 @@
+#![no_std]
+#![allow(clippy::upper_case_acronyms)]
+
+#[cfg(feature = "heap")]
+extern crate alloc;
+
+pub mod asm;
+pub mod boot;
+pub mod ccmram;
+pub mod clock;
+pub mod crc;
+pub mod dma;
+pub mod exti;
+pub mod fault;
+pub mod flash;
+pub mod fpu;
+pub mod gpio;
+#[cfg(feature = "heap")]
+pub mod heap;
+pub mod independent_watchdog;
+pub mod interrupt;
+pub mod mco;
+pub mod memory;
+pub mod memory_mapped_io;
+pub mod mpu;
+pub mod nucleo_f439zi;
+pub mod nvic;
+pub mod panic;
+pub mod pwr;
+pub mod rcc;
+#[cfg(feature = "simulation")]
+pub mod recording_bus;
+pub mod register;
+pub mod register_bus;
+pub mod registers;
+pub mod scb;
+pub mod shared;
+#[cfg(feature = "simulation")]
+pub mod simulated_bus;
+#[cfg(feature = "simulation")]
+pub mod simulated_flash;
+#[cfg(target_os = "none")]
+pub mod stack;
+#[cfg(target_os = "none")]
+pub mod startup;
+pub mod stm32f439zitx;
+pub mod syscfg;
+pub mod systick;
+pub mod timer;
+pub mod update;
+pub mod usart;
+#[cfg(target_os = "none")]
+pub mod vector_table;
+pub mod ymodem;
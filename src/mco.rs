@@ -0,0 +1,44 @@
+use crate::gpio::OutputSpeed::VeryHigh;
+use crate::gpio::PinMode::Alternate;
+use crate::gpio::{AlternateFunction, GpioConf};
+use crate::rcc::{Mco1Source, Mco2Source, McoPrescaler, Peripheral, RccConf};
+use crate::register_bus::RegisterBus;
+
+/*
+   Microcontroller clock outputs, RM0090 clock-out capability.
+
+   MCO1 is AF0 of PA8 and MCO2 is AF0 of PC9. Source and prescaler are written first, ST
+   advises against changing them while the pin already drives the clock. The GPIO port clock
+   is enabled here, the pin runs at the highest output speed since PLL outputs can go up to
+   100 MHz. The output is left running until the pin gets another mode.
+*/
+pub const MCO1_PIN: u32 = 8;
+pub const MCO2_PIN: u32 = 9;
+
+pub fn output_mco1<R: RegisterBus, G: RegisterBus>(
+    rcc: &RccConf<R>,
+    port_a: &GpioConf<G>,
+    source: Mco1Source,
+    prescaler: McoPrescaler,
+) {
+    rcc.set_mco1(source, prescaler);
+    rcc.enable_peripheral(Peripheral::GpioA);
+    configure_mco_pin(port_a, MCO1_PIN);
+}
+
+pub fn output_mco2<R: RegisterBus, G: RegisterBus>(
+    rcc: &RccConf<R>,
+    port_c: &GpioConf<G>,
+    source: Mco2Source,
+    prescaler: McoPrescaler,
+) {
+    rcc.set_mco2(source, prescaler);
+    rcc.enable_peripheral(Peripheral::GpioC);
+    configure_mco_pin(port_c, MCO2_PIN);
+}
+
+fn configure_mco_pin<G: RegisterBus>(port: &GpioConf<G>, pin: u32) {
+    port.set_alternate_function(pin, AlternateFunction::System);
+    port.set_output_speed(pin, VeryHigh);
+    port.set_pin_mode(Alternate, pin);
+}
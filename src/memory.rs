@@ -1,6 +1,50 @@
+#[cfg(target_arch = "arm")]
 use core::arch::asm;
 
+/// # Safety
+/// Has no side effects besides ordering memory accesses.
+#[cfg(target_arch = "arm")]
 #[inline(always)]
 pub unsafe fn store_barrier() {
     asm!("DSB ST");
 }
+
+/// # Safety
+/// Has no side effects besides ordering memory accesses.
+#[cfg(not(target_arch = "arm"))]
+#[inline(always)]
+pub unsafe fn store_barrier() {
+    core::sync::atomic::fence(core::sync::atomic::Ordering::SeqCst);
+}
+
+/// # Safety
+/// Has no side effects besides ordering memory accesses.
+#[cfg(target_arch = "arm")]
+#[inline(always)]
+pub unsafe fn data_barrier() {
+    asm!("DSB");
+}
+
+/// # Safety
+/// Has no side effects besides ordering memory accesses.
+#[cfg(not(target_arch = "arm"))]
+#[inline(always)]
+pub unsafe fn data_barrier() {
+    core::sync::atomic::fence(core::sync::atomic::Ordering::SeqCst);
+}
+
+/// # Safety
+/// Has no side effects besides flushing the pipeline.
+#[cfg(target_arch = "arm")]
+#[inline(always)]
+pub unsafe fn instruction_barrier() {
+    asm!("ISB");
+}
+
+/// # Safety
+/// Has no side effects besides flushing the pipeline.
+#[cfg(not(target_arch = "arm"))]
+#[inline(always)]
+pub unsafe fn instruction_barrier() {
+    core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+}
@@ -1,5 +1,17 @@
+use crate::register::{Field, Register};
+use crate::register_bus::RegisterBus;
 use core::ptr::{read_volatile, write_volatile};
 
+/*
+   Cortex-M4 peripheral bit-band region, RM0090 p68. Every bit of the first MB of the
+   peripheral space is mapped to a word of the alias region, writing bit 0 of that word sets
+   or clears the bit in a single bus transaction that can not be interrupted.
+*/
+const PERIPHERAL_BIT_BAND_BASE: u32 = 0x4000_0000;
+const PERIPHERAL_BIT_BAND_END: u32 = 0x4010_0000;
+const PERIPHERAL_BIT_BAND_ALIAS: u32 = 0x4200_0000;
+
+#[derive(Clone)]
 pub struct MemoryMappedIo {
     base: u32,
 }
@@ -14,27 +26,59 @@ impl MemoryMappedIo {
         self.base as *mut u32
     }
 
-    pub fn read(&self, offset: usize) -> u32 {
-        unsafe { read_volatile(self.address().add(offset)) }
+    // Alias word of a single bit, None for registers outside of the bit-band region
+    #[inline(always)]
+    pub fn bit_band_address(&self, register: Register, field: Field) -> Option<*mut u32> {
+        let address = self.register_address(register);
+        if (PERIPHERAL_BIT_BAND_BASE..PERIPHERAL_BIT_BAND_END).contains(&address) {
+            let byte_offset = address - PERIPHERAL_BIT_BAND_BASE;
+            let alias = PERIPHERAL_BIT_BAND_ALIAS + byte_offset * 32 + field.position() * 4;
+            Some(alias as *mut u32)
+        } else {
+            None
+        }
+    }
+}
+
+impl RegisterBus for MemoryMappedIo {
+    #[inline(always)]
+    fn base_address(&self) -> u32 {
+        self.base
+    }
+
+    fn read(&self, index: usize) -> u32 {
+        unsafe { read_volatile(self.address().add(index)) }
     }
 
-    pub fn write(&self, value: u32, offset: usize) {
-        unsafe { write_volatile(self.address().add(offset), value) }
+    fn write(&self, value: u32, index: usize) {
+        unsafe { write_volatile(self.address().add(index), value) }
     }
 
-    pub fn set_bit(&self, bit_number: u32, offset: usize) {
-        let mut current_value = self.read(offset);
-        current_value |= 0b1 << bit_number;
-        self.write(current_value, offset);
+    fn set_bit(&self, register: Register, field: Field) {
+        debug_assert!(field.width() == 1);
+        match self.bit_band_address(register, field) {
+            Some(alias) => unsafe { write_volatile(alias, 1) },
+            None => self.modify_register(register, |current| current | field.mask()),
+        }
     }
 
-    pub fn clear_bit(&self, bit_number: u32, offset: usize) {
-        let mut current_value = self.read(offset);
-        current_value &= !(0b1 << bit_number);
-        self.write(current_value, offset);
+    fn clear_bit(&self, register: Register, field: Field) {
+        debug_assert!(field.width() == 1);
+        match self.bit_band_address(register, field) {
+            Some(alias) => unsafe { write_volatile(alias, 0) },
+            None => self.modify_register(register, |current| current & !field.mask()),
+        }
     }
 
-    pub fn is_bit_set(&self, bit_number: u32, offset: usize) -> bool {
-        self.read(offset) & (0b1 << bit_number) != 0
+    /*
+       Reading and writing the alias only touches this bit, concurrent changes to the other
+       bits of the register are never lost.
+    */
+    fn toggle_bit(&self, register: Register, field: Field) {
+        debug_assert!(field.width() == 1);
+        match self.bit_band_address(register, field) {
+            Some(alias) => unsafe { write_volatile(alias, read_volatile(alias) ^ 1) },
+            None => self.modify_register(register, |current| current ^ field.mask()),
+        }
     }
 }
@@ -0,0 +1,104 @@
+use crate::memory::{data_barrier, instruction_barrier};
+use crate::memory_mapped_io::MemoryMappedIo;
+use crate::register_bus::RegisterBus;
+use crate::registers::mpu::{ctrl, rasr, rbar, typer, CTRL, RASR, RBAR, RNR, TYPER};
+
+// PM0214 p196, AP field
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccessPermission {
+    NoAccess = 0b000,
+    PrivilegedReadWrite = 0b001,
+    ReadWrite = 0b011,
+    PrivilegedReadOnly = 0b101,
+    ReadOnly = 0b110,
+}
+
+/*
+   Region over internal SRAM, PM0214 p195: normal memory, shareable, write-through.
+   The base address has to be aligned to the size, sizes are powers of two from 32 bytes.
+*/
+#[derive(Clone, Copy, Debug)]
+pub struct MpuRegion {
+    pub number: u32,
+    pub base_address: u32,
+    pub size: u32,
+    pub access_permission: AccessPermission,
+    pub execute_never: bool,
+}
+
+pub struct MpuConf<B: RegisterBus = MemoryMappedIo> {
+    reg: B,
+}
+
+impl MpuConf {
+    pub const fn new(base: u32) -> Self {
+        MpuConf::with_bus(MemoryMappedIo::new(base))
+    }
+}
+
+impl<B: RegisterBus> MpuConf<B> {
+    pub const fn with_bus(reg: B) -> Self {
+        MpuConf { reg }
+    }
+
+    pub fn region_count(&self) -> u32 {
+        self.reg.read_field(TYPER, typer::DREGION)
+    }
+
+    pub fn set_region(&self, region: MpuRegion) {
+        assert!(region.number < self.region_count(), "no such MPU region");
+        assert!(
+            region.size.is_power_of_two() && region.size >= 32,
+            "MPU region size must be a power of two of at least 32 bytes"
+        );
+        assert!(
+            region.base_address.is_multiple_of(region.size),
+            "MPU region base must be aligned to its size"
+        );
+        // Region size is 2^(SIZE + 1) bytes
+        let size = region.size.trailing_zeros() - 1;
+        self.reg.write_register(
+            RBAR,
+            (region.base_address & rbar::ADDR.mask())
+                | rbar::VALID.value(1)
+                | rbar::REGION.value(region.number),
+        );
+        self.reg.write_register(
+            RASR,
+            rasr::XN.value(region.execute_never as u32)
+                | rasr::AP.value(region.access_permission as u32)
+                | rasr::S.value(1)
+                | rasr::C.value(1)
+                | rasr::SIZE.value(size)
+                | rasr::ENABLE.value(1),
+        );
+    }
+
+    pub fn disable_region(&self, number: u32) {
+        self.reg.write_register(RNR, number);
+        self.reg.write_register(RASR, 0);
+    }
+
+    /*
+       PRIVDEFENA keeps the default memory map for everything outside the regions, the MPU
+       only adds restrictions. It stays off in HardFault and NMI (HFNMIENA cleared).
+    */
+    pub fn enable(&self) {
+        self.reg
+            .write_register(CTRL, ctrl::PRIVDEFENA.value(1) | ctrl::ENABLE.value(1));
+        unsafe {
+            data_barrier();
+            instruction_barrier();
+        }
+    }
+
+    pub fn disable(&self) {
+        unsafe {
+            data_barrier();
+        }
+        self.reg.write_register(CTRL, 0);
+        unsafe {
+            instruction_barrier();
+        }
+    }
+}
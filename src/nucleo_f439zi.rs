@@ -0,0 +1,231 @@
+use crate::clock::{setup_clock, Clocks};
+use crate::dma::DmaStreams;
+use crate::exti::ExtiConf;
+use crate::flash::FlashConf;
+use crate::gpio::AlternateFunction;
+use crate::gpio::GpioConf;
+use crate::gpio::OutputSpeed::VeryHigh;
+use crate::gpio::PinMode::{Alternate, Input, Output};
+use crate::independent_watchdog::IndependentWatchdogConf;
+use crate::mpu::MpuConf;
+use crate::nvic::NvicConf;
+use crate::pwr::PwrConf;
+use crate::rcc::{Peripheral, RccConf, ResetCause};
+use crate::scb::ScbConf;
+use crate::stm32f439zitx::{Interrupt, Peripherals};
+use crate::syscfg::{ExternalInterruptSourcePort, SysConf};
+use crate::systick::SysTickConf;
+use crate::timer::BasicTimerConf;
+use crate::usart::UsartStopBits::Stop1Bit;
+use crate::usart::UsartWordLength::Len1Start8Data;
+use crate::usart::{UsartConf, UsartControl};
+
+/*
+   Nucleo-F439ZI (MB1137), UM1974.
+
+   LD1 green PB0, LD2 blue PB7, LD3 red PB14
+   B1 user button PC13, high while pressed
+   ST-LINK virtual COM port on USART3, PD8 TX and PD9 RX
+   HSE is the 8 MHz MCO output of the ST-LINK
+
+   Board::init takes the MCU peripherals, brings up the clock, the pins and USART3 and hands
+   back the board resources together with the peripherals the board does not use. Another
+   board gets its own module of the same shape, the drivers stay as they are.
+*/
+pub const USER_BUTTON_PIN: u32 = 13;
+const VIRTUAL_COM_PORT_TX_PIN: u32 = 8;
+const VIRTUAL_COM_PORT_RX_PIN: u32 = 9;
+pub const VIRTUAL_COM_PORT_BAUD_RATE: u32 = 115_200;
+// USART3 TX is mapped to DMA1 stream 3 channel 4
+pub const VIRTUAL_COM_PORT_DMA_CHANNEL: u8 = 4;
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Led {
+    Green = 0,
+    Blue = 7,
+    Red = 14,
+}
+
+pub struct Leds {
+    port: GpioConf,
+}
+
+impl Leds {
+    pub fn on(&self, led: Led) {
+        self.port.set_pin(led as u32);
+    }
+
+    pub fn off(&self, led: Led) {
+        self.port.reset_pin(led as u32);
+    }
+
+    pub fn toggle(&self, led: Led) {
+        self.port.switch_pin_output(led as u32);
+    }
+}
+
+pub struct UserButton {
+    port: GpioConf,
+    exti: ExtiConf,
+}
+
+impl UserButton {
+    // EXTI lines 10 to 15 share one vector
+    pub const INTERRUPT: Interrupt = Interrupt::Exti15_10;
+
+    pub fn is_pressed(&self) -> bool {
+        self.port.is_pin_high(USER_BUTTON_PIN)
+    }
+
+    // Rising edge on press, the NVIC side is left to the application
+    pub fn enable_interrupt(&self) {
+        self.exti.unmask_interrupt(USER_BUTTON_PIN);
+        self.exti.enable_rising_trigger(USER_BUTTON_PIN);
+    }
+
+    pub fn clear_pending(&self) {
+        self.exti.clear_pending(USER_BUTTON_PIN);
+    }
+}
+
+pub struct Board {
+    pub leds: Leds,
+    pub user_button: UserButton,
+    // See configure_virtual_com_port
+    pub virtual_com_port: UsartConf,
+    pub reset_cause: ResetCause,
+    pub clocks: Clocks,
+    pub port_a: GpioConf,
+    // PD8 and PD9 are taken by the virtual COM port
+    pub port_d: GpioConf,
+    pub port_e: GpioConf,
+    pub port_f: GpioConf,
+    pub port_g: GpioConf,
+    pub port_h: GpioConf,
+    pub port_i: GpioConf,
+    pub port_j: GpioConf,
+    pub port_k: GpioConf,
+    pub nvic: NvicConf,
+    pub mpu: MpuConf,
+    pub rcc: RccConf,
+    pub scb: ScbConf,
+    pub systick: SysTickConf,
+    pub tim6: BasicTimerConf,
+    pub tim7: BasicTimerConf,
+    pub syscfg: SysConf,
+    pub usart1: UsartConf,
+    pub usart2: UsartConf,
+    pub uart4: UsartConf,
+    pub uart5: UsartConf,
+    pub usart6: UsartConf,
+    pub uart7: UsartConf,
+    pub uart8: UsartConf,
+    pub iwdg: IndependentWatchdogConf,
+    pub dma1: DmaStreams,
+    pub dma2: DmaStreams,
+    pub flash: FlashConf,
+    pub pwr: PwrConf,
+}
+
+impl Board {
+    pub fn init(peripherals: Peripherals) -> Board {
+        let rcc = peripherals.rcc;
+        // Read before anything else can reset, RMVF clears the flags for the next boot
+        let reset_cause = rcc.take_reset_cause();
+        let clocks = setup_clock(&rcc, &peripherals.pwr, &peripherals.flash);
+        rcc.enable_peripheral(Peripheral::GpioB);
+        rcc.enable_peripheral(Peripheral::GpioC);
+        rcc.enable_peripheral(Peripheral::Syscfg);
+        rcc.enable_peripheral(Peripheral::Dma1);
+
+        let leds = Leds {
+            port: peripherals.port_b,
+        };
+        leds.port.set_pins_mode(
+            Output,
+            &[Led::Green as u32, Led::Blue as u32, Led::Red as u32],
+        );
+
+        let user_button = UserButton {
+            port: peripherals.port_c,
+            exti: peripherals.exti,
+        };
+        user_button.port.set_pin_mode(Input, USER_BUTTON_PIN);
+        peripherals.syscfg.set_external_interrupt_source_port(
+            USER_BUTTON_PIN,
+            ExternalInterruptSourcePort::PortC,
+        );
+
+        let virtual_com_port = peripherals.usart3;
+        configure_virtual_com_port(&rcc, &peripherals.port_d, &virtual_com_port, &clocks);
+
+        Board {
+            leds,
+            user_button,
+            virtual_com_port,
+            reset_cause,
+            clocks,
+            port_a: peripherals.port_a,
+            port_d: peripherals.port_d,
+            port_e: peripherals.port_e,
+            port_f: peripherals.port_f,
+            port_g: peripherals.port_g,
+            port_h: peripherals.port_h,
+            port_i: peripherals.port_i,
+            port_j: peripherals.port_j,
+            port_k: peripherals.port_k,
+            nvic: peripherals.nvic,
+            mpu: peripherals.mpu,
+            rcc,
+            scb: peripherals.scb,
+            systick: peripherals.systick,
+            tim6: peripherals.tim6,
+            tim7: peripherals.tim7,
+            syscfg: peripherals.syscfg,
+            usart1: peripherals.usart1,
+            usart2: peripherals.usart2,
+            uart4: peripherals.uart4,
+            uart5: peripherals.uart5,
+            usart6: peripherals.usart6,
+            uart7: peripherals.uart7,
+            uart8: peripherals.uart8,
+            iwdg: peripherals.iwdg,
+            dma1: peripherals.dma1,
+            dma2: peripherals.dma2,
+            flash: peripherals.flash,
+            pwr: peripherals.pwr,
+        }
+    }
+}
+
+/*
+   115.2KBs
+   USART3 APB1, the divider follows PCLK1 of clocks, 22 + 13/16 for the 42MHz of setup_clock.
+   A UsartBaudRate listener keeps the rate when the clock changes later.
+   The transmitter DMA requests are on so UsartDmaDriver can take over the port.
+*/
+pub fn configure_virtual_com_port(
+    rcc: &RccConf,
+    port_d: &GpioConf,
+    usart3: &UsartConf,
+    clocks: &Clocks,
+) {
+    rcc.enable_peripheral(Peripheral::GpioD);
+    rcc.enable_peripheral(Peripheral::Usart3);
+    for pin in [VIRTUAL_COM_PORT_TX_PIN, VIRTUAL_COM_PORT_RX_PIN] {
+        port_d.set_pin_mode(Alternate, pin);
+        port_d.set_alternate_function(pin, AlternateFunction::Usart1_3);
+        port_d.set_output_speed(pin, VeryHigh);
+    }
+    usart3.set_baud_rate_from_clock(clocks.pclk1(), VIRTUAL_COM_PORT_BAUD_RATE);
+    usart3.set_usart_control(UsartControl {
+        enabled: Some(true),
+        parity_control_enabled: Some(false),
+        transmitter_enabled: Some(true),
+        receiver_enabled: Some(true),
+        word_length: Some(Len1Start8Data),
+        stop_bits: Some(Stop1Bit),
+        dma_transmitter_enabled: Some(true),
+    });
+}
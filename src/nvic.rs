@@ -1,22 +1,27 @@
 use crate::memory_mapped_io::MemoryMappedIo;
+use crate::register_bus::RegisterBus;
+use crate::registers::nvic::{iser0, ISER0, ISER1};
 
-pub struct NvicConf {
-    set_enable_base: MemoryMappedIo,
+pub struct NvicConf<B: RegisterBus = MemoryMappedIo> {
+    set_enable_base: B,
 }
 
 impl NvicConf {
     pub const fn new(set_enable_base: u32) -> Self {
-        NvicConf {
-            set_enable_base: MemoryMappedIo::new(set_enable_base),
-        }
+        NvicConf::with_bus(MemoryMappedIo::new(set_enable_base))
+    }
+}
+
+impl<B: RegisterBus> NvicConf<B> {
+    pub const fn with_bus(set_enable_base: B) -> Self {
+        NvicConf { set_enable_base }
     }
 
     pub fn enable_interrupt(&self, index: u32) {
-        let register: usize = (index / 32) as usize;
-        let offset = index % 32;
-        let mut current_value = self.set_enable_base.read(register);
-        current_value |= 0b1 << offset;
-        self.set_enable_base.write(current_value, register);
+        let register = ISER0.nth((index / 32) as usize, ISER1.offset() - ISER0.offset());
+        // Writing zeros has no effect, no need to read the register first
+        self.set_enable_base
+            .write_register(register, iser0::SETENA.value(0b1 << (index % 32)));
     }
 
     pub fn enable_interrupts(&self, indices: &[u32]) {
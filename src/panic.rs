@@ -0,0 +1,179 @@
+use core::fmt::{self, Display, Formatter, Write};
+use core::panic::PanicInfo;
+use core::str::from_utf8;
+
+/*
+   Panic reporting.
+
+   report_and_reset is meant to be called from the application #[panic_handler]. It masks
+   interrupts, keeps a short PanicRecord in the .uninit RAM section, which neither the reset
+   handler nor the bootloader touch, prints the full panic over USART3 by polling and resets
+   through AIRCR. The next boot picks the record up with take_previous_panic.
+
+   USART3 is only used when the application enabled its transmitter, DMA requests are turned
+   off first so a transfer in flight does not interleave with the report. With the heap feature
+   the report ends with the heap statistics, running out of heap panics as well.
+*/
+const PANIC_RECORD_MAGIC: u32 = 0x5041_4E43;
+const FILE_CAPACITY: usize = 48;
+const MESSAGE_CAPACITY: usize = 128;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PanicRecord {
+    magic: u32,
+    line: u32,
+    column: u32,
+    file_length: u32,
+    message_length: u32,
+    file: [u8; FILE_CAPACITY],
+    message: [u8; MESSAGE_CAPACITY],
+}
+
+// Keeps whatever fits, characters are never split so the buffer stays valid UTF-8
+struct TruncatingWriter<'a> {
+    buffer: &'a mut [u8],
+    length: usize,
+}
+
+impl Write for TruncatingWriter<'_> {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        for character in string.chars() {
+            let end = self.length + character.len_utf8();
+            if end > self.buffer.len() {
+                break;
+            }
+            character.encode_utf8(&mut self.buffer[self.length..end]);
+            self.length = end;
+        }
+        Ok(())
+    }
+}
+
+fn truncated(buffer: &mut [u8], arguments: fmt::Arguments) -> u32 {
+    let mut writer = TruncatingWriter { buffer, length: 0 };
+    let _ = writer.write_fmt(arguments);
+    writer.length as u32
+}
+
+impl PanicRecord {
+    pub fn new(info: &PanicInfo) -> Self {
+        let mut record = PanicRecord {
+            magic: PANIC_RECORD_MAGIC,
+            line: 0,
+            column: 0,
+            file_length: 0,
+            message_length: 0,
+            file: [0; FILE_CAPACITY],
+            message: [0; MESSAGE_CAPACITY],
+        };
+        if let Some(location) = info.location() {
+            record.line = location.line();
+            record.column = location.column();
+            record.file_length = truncated(&mut record.file, format_args!("{}", location.file()));
+        }
+        record.message_length = truncated(&mut record.message, format_args!("{}", info.message()));
+        record
+    }
+
+    // RAM holds garbage after power-on, anything without the magic or with lengths out of range is ignored
+    pub fn is_valid(&self) -> bool {
+        self.magic == PANIC_RECORD_MAGIC
+            && self.file_length as usize <= FILE_CAPACITY
+            && self.message_length as usize <= MESSAGE_CAPACITY
+    }
+
+    pub fn file(&self) -> &str {
+        from_utf8(&self.file[..self.file_length as usize]).unwrap_or("?")
+    }
+
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    pub fn column(&self) -> u32 {
+        self.column
+    }
+
+    pub fn message(&self) -> &str {
+        from_utf8(&self.message[..self.message_length as usize]).unwrap_or("?")
+    }
+}
+
+impl Display for PanicRecord {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            formatter,
+            "panicked at {}:{}:{}: {}",
+            self.file(),
+            self.line(),
+            self.column(),
+            self.message()
+        )
+    }
+}
+
+#[cfg(target_os = "none")]
+mod handler {
+    use super::PanicRecord;
+    use crate::interrupt;
+    use crate::stm32f439zitx::Peripherals;
+    use crate::usart::{UsartControl, UsartSingleByteDriver};
+    use core::fmt::Write;
+    use core::mem::MaybeUninit;
+    use core::panic::PanicInfo;
+    use core::ptr::{addr_of, addr_of_mut, read_volatile, write_volatile};
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    #[link_section = ".uninit.PANIC_RECORD"]
+    static mut PANIC_RECORD: MaybeUninit<PanicRecord> = MaybeUninit::uninit();
+
+    static PANICKING: AtomicBool = AtomicBool::new(false);
+
+    // Record left by a panic before the last reset, cleared so it is reported once
+    pub fn take_previous_panic() -> Option<PanicRecord> {
+        unsafe {
+            let record_pointer = addr_of_mut!(PANIC_RECORD) as *mut PanicRecord;
+            let record = read_volatile(record_pointer as *const PanicRecord);
+            write_volatile(addr_of_mut!((*record_pointer).magic), 0);
+            record.is_valid().then_some(record)
+        }
+    }
+
+    pub fn report_and_reset(info: &PanicInfo) -> ! {
+        interrupt::disable();
+        let peripherals = unsafe { Peripherals::steal() };
+        // A panic while printing the report goes straight to reset
+        if PANICKING.swap(true, Ordering::AcqRel) {
+            peripherals.scb.system_reset();
+        }
+        unsafe {
+            write_volatile(
+                addr_of!(PANIC_RECORD) as *mut PanicRecord,
+                PanicRecord::new(info),
+            );
+        }
+        let usart3 = peripherals.usart3;
+        if usart3.is_transmitter_enabled() {
+            usart3.set_usart_control(UsartControl {
+                dma_transmitter_enabled: Some(false),
+                ..Default::default()
+            });
+            let mut usart = UsartSingleByteDriver::new(usart3);
+            let _ = write!(usart, "\r\n*** Panic ***\r\n{info}\r\n");
+            #[cfg(feature = "heap")]
+            let _ = write!(usart, "{}\r\n", crate::heap::statistics());
+            let _ = write!(usart, "Resetting\r\n");
+        }
+        peripherals.scb.system_reset()
+    }
+}
+
+#[cfg(target_os = "none")]
+pub use handler::{report_and_reset, take_previous_panic};
+
+// Host builds have no record surviving a reset
+#[cfg(not(target_os = "none"))]
+pub fn take_previous_panic() -> Option<PanicRecord> {
+    None
+}
@@ -1,24 +1,27 @@
-use crate::clear_mask;
+use crate::asm::no_operation;
 use crate::memory::store_barrier;
 use crate::memory_mapped_io::MemoryMappedIo;
+use crate::register_bus::RegisterBus;
+use crate::registers::pwr::{cr, csr, CR, CSR};
 
-pub struct PwrConf {
-    reg: MemoryMappedIo,
+pub struct PwrConf<B: RegisterBus = MemoryMappedIo> {
+    reg: B,
 }
 
-const PWR_CR: usize = 0;
-const PWR_CSR: usize = 0x04 >> 2;
-
 impl PwrConf {
     pub const fn new(base: u32) -> PwrConf {
-        PwrConf {
-            reg: MemoryMappedIo::new(base),
-        }
+        PwrConf::with_bus(MemoryMappedIo::new(base))
+    }
+}
+
+impl<B: RegisterBus> PwrConf<B> {
+    pub const fn with_bus(reg: B) -> Self {
+        PwrConf { reg }
     }
 
     // Check when PLL is ON
     pub fn is_regulator_voltage_scaling_output_ready(&self) -> bool {
-        self.reg.is_bit_set(14, PWR_CSR)
+        self.reg.is_field_set(CSR, csr::VOSRDY)
     }
 
     // DS9484 p95
@@ -29,13 +32,60 @@ impl PwrConf {
             3 => 0b01,
             _ => 0b01,
         };
-        let mut current_value = self.reg.read(PWR_CR);
-        current_value &= clear_mask!(2, 14); // [15:14] VOS
-        current_value |= value << 14;
-        self.reg.write(current_value, PWR_CR);
+        self.reg.write_field(CR, cr::VOS, value);
+        unsafe {
+            store_barrier();
+        }
+        let _value = self.reg.read_register(CR);
+    }
+
+    pub fn is_over_drive_ready(&self) -> bool {
+        self.reg.is_field_set(CSR, csr::ODRDY)
+    }
+
+    // Set once the regulator runs in over-drive mode
+    pub fn is_over_drive_enabled(&self) -> bool {
+        self.reg.is_field_set(CSR, csr::ODSWRDY)
+    }
+
+    /*
+       RM0090 entering over-drive mode, needed for HCLK above 168 MHz
+       The regulator has to be in scale 1 and SYSCLK on HSI or HSE, the main PLL may be
+       locked already. The voltage is raised with ODEN first and the regulator switched over
+       with ODSWEN afterwards.
+    */
+    pub fn enable_over_drive(&self) {
+        self.reg.set_bit(CR, cr::ODEN);
+        unsafe {
+            store_barrier();
+            while !self.is_over_drive_ready() {
+                no_operation();
+            }
+        }
+        self.reg.set_bit(CR, cr::ODSWEN);
+        unsafe {
+            store_barrier();
+            while !self.is_over_drive_enabled() {
+                no_operation();
+            }
+        }
+    }
+
+    // RM0090 exiting over-drive mode, SYSCLK has to be on HSI or HSE
+    pub fn disable_over_drive(&self) {
+        self.reg.clear_bit(CR, cr::ODSWEN);
+        unsafe {
+            store_barrier();
+            while self.is_over_drive_enabled() {
+                no_operation();
+            }
+        }
+        self.reg.clear_bit(CR, cr::ODEN);
         unsafe {
             store_barrier();
+            while self.is_over_drive_ready() {
+                no_operation();
+            }
         }
-        let _value = self.reg.read(PWR_CR);
     }
 }
@@ -2,31 +2,371 @@ use crate::asm::no_operation;
 use crate::memory::store_barrier;
 use crate::memory_mapped_io::MemoryMappedIo;
 use crate::rcc::SystemClock::{HSE, HSI, PLL};
-use crate::{clear_mask, n_bits};
+use crate::register::Field;
+use crate::register::Register;
+use crate::register_bus::RegisterBus;
+use crate::registers::rcc::{
+    ahb1enr, ahb1lpenr, ahb1rstr, ahb2enr, ahb2lpenr, ahb2rstr, ahb3enr, ahb3lpenr, ahb3rstr,
+    apb1enr, apb1lpenr, apb1rstr, apb2enr, apb2lpenr, apb2rstr, cfgr, cir, cr, csr, dckcfgr,
+    pllcfgr, plli2scfgr, pllsaicfgr, AHB1ENR, AHB1LPENR, AHB1RSTR, AHB2ENR, AHB2LPENR, AHB2RSTR,
+    AHB3ENR, AHB3LPENR, AHB3RSTR, APB1ENR, APB1LPENR, APB1RSTR, APB2ENR, APB2LPENR, APB2RSTR, CFGR,
+    CIR, CR, CSR, DCKCFGR, PLLCFGR, PLLI2SCFGR, PLLSAICFGR,
+};
+
+pub struct RccConf<B: RegisterBus = MemoryMappedIo> {
+    reg: B,
+}
 
-pub struct RccConf {
-    reg: MemoryMappedIo,
+/*
+   HSE start-up takes up to 2 ms with a crystal, DS9484 HSE oscillator characteristics. The
+   HSERDY poll runs from HSI at 16 MHz, a few cycles per poll, it gives up after some 50 ms.
+*/
+const HSE_STARTUP_POLLS: u32 = 200_000;
+
+// PLLI2S and PLLSAI lock within a few hundred microseconds, polled from HSI at the slowest
+const PLL_LOCK_POLLS: u32 = 20_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RccError {
+    HseStartupTimeout,
+    I2sPllLockTimeout,
+    SaiPllLockTimeout,
+    // SWS read back as 0b11, which RM0090 reserves
+    ReservedSystemClockStatus,
 }
 
-#[derive(Clone, Copy)]
-pub enum GpioPort {
-    B = 0b1 << 1,
-    C = 0b1 << 2,
-    D = 0b1 << 3,
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bus {
+    AHB1,
+    AHB2,
+    AHB3,
+    APB1,
+    APB2,
+}
+
+impl Bus {
+    const fn enable_register(self) -> Register {
+        match self {
+            Bus::AHB1 => AHB1ENR,
+            Bus::AHB2 => AHB2ENR,
+            Bus::AHB3 => AHB3ENR,
+            Bus::APB1 => APB1ENR,
+            Bus::APB2 => APB2ENR,
+        }
+    }
+
+    const fn reset_register(self) -> Register {
+        match self {
+            Bus::AHB1 => AHB1RSTR,
+            Bus::AHB2 => AHB2RSTR,
+            Bus::AHB3 => AHB3RSTR,
+            Bus::APB1 => APB1RSTR,
+            Bus::APB2 => APB2RSTR,
+        }
+    }
+
+    const fn low_power_register(self) -> Register {
+        match self {
+            Bus::AHB1 => AHB1LPENR,
+            Bus::AHB2 => AHB2LPENR,
+            Bus::AHB3 => AHB3LPENR,
+            Bus::APB1 => APB1LPENR,
+            Bus::APB2 => APB2LPENR,
+        }
+    }
 }
 
-pub enum BasicTimer {
-    TIM6 = 0b1 << 4,
-    TIM7 = 0b1 << 5,
+macro_rules! peripherals {
+    ($($name:ident: $bus:ident, $enable:expr, $reset:expr, $low_power:expr;)+) => {
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        pub enum Peripheral {
+            $($name,)+
+        }
+
+        impl Peripheral {
+            pub const fn bus(self) -> Bus {
+                match self {
+                    $(Peripheral::$name => Bus::$bus,)+
+                }
+            }
+
+            const fn enable_field(self) -> Field {
+                match self {
+                    $(Peripheral::$name => $enable,)+
+                }
+            }
+
+            const fn reset_field(self) -> Option<Field> {
+                match self {
+                    $(Peripheral::$name => $reset,)+
+                }
+            }
+
+            const fn low_power_field(self) -> Option<Field> {
+                match self {
+                    $(Peripheral::$name => $low_power,)+
+                }
+            }
+        }
+    };
+}
+
+/*
+   RM0090 section 6.3, every peripheral clock gated by RCC.
+   ADC1 to ADC3 share ADCRST, the Ethernet and ULPI sub-clocks are reset with their controller.
+   Backup SRAM and CCM RAM have no reset bit, CCM RAM is not gated in sleep mode either.
+*/
+peripherals! {
+    GpioA: AHB1, ahb1enr::GPIOAEN, Some(ahb1rstr::GPIOARST), Some(ahb1lpenr::GPIOALPEN);
+    GpioB: AHB1, ahb1enr::GPIOBEN, Some(ahb1rstr::GPIOBRST), Some(ahb1lpenr::GPIOBLPEN);
+    GpioC: AHB1, ahb1enr::GPIOCEN, Some(ahb1rstr::GPIOCRST), Some(ahb1lpenr::GPIOCLPEN);
+    GpioD: AHB1, ahb1enr::GPIODEN, Some(ahb1rstr::GPIODRST), Some(ahb1lpenr::GPIODLPEN);
+    GpioE: AHB1, ahb1enr::GPIOEEN, Some(ahb1rstr::GPIOERST), Some(ahb1lpenr::GPIOELPEN);
+    GpioF: AHB1, ahb1enr::GPIOFEN, Some(ahb1rstr::GPIOFRST), Some(ahb1lpenr::GPIOFLPEN);
+    GpioG: AHB1, ahb1enr::GPIOGEN, Some(ahb1rstr::GPIOGRST), Some(ahb1lpenr::GPIOGLPEN);
+    GpioH: AHB1, ahb1enr::GPIOHEN, Some(ahb1rstr::GPIOHRST), Some(ahb1lpenr::GPIOHLPEN);
+    GpioI: AHB1, ahb1enr::GPIOIEN, Some(ahb1rstr::GPIOIRST), Some(ahb1lpenr::GPIOILPEN);
+    GpioJ: AHB1, ahb1enr::GPIOJEN, Some(ahb1rstr::GPIOJRST), Some(ahb1lpenr::GPIOJLPEN);
+    GpioK: AHB1, ahb1enr::GPIOKEN, Some(ahb1rstr::GPIOKRST), Some(ahb1lpenr::GPIOKLPEN);
+    Crc: AHB1, ahb1enr::CRCEN, Some(ahb1rstr::CRCRST), Some(ahb1lpenr::CRCLPEN);
+    BackupSram: AHB1, ahb1enr::BKPSRAMEN, None, Some(ahb1lpenr::BKPSRAMLPEN);
+    CcmDataRam: AHB1, ahb1enr::CCMDATARAMEN, None, None;
+    Dma1: AHB1, ahb1enr::DMA1EN, Some(ahb1rstr::DMA1RST), Some(ahb1lpenr::DMA1LPEN);
+    Dma2: AHB1, ahb1enr::DMA2EN, Some(ahb1rstr::DMA2RST), Some(ahb1lpenr::DMA2LPEN);
+    Dma2d: AHB1, ahb1enr::DMA2DEN, Some(ahb1rstr::DMA2DRST), Some(ahb1lpenr::DMA2DLPEN);
+    EthernetMac: AHB1, ahb1enr::ETHMACEN, Some(ahb1rstr::ETHMACRST), Some(ahb1lpenr::ETHMACLPEN);
+    EthernetMacTx: AHB1, ahb1enr::ETHMACTXEN, Some(ahb1rstr::ETHMACRST),
+        Some(ahb1lpenr::ETHMACTXLPEN);
+    EthernetMacRx: AHB1, ahb1enr::ETHMACRXEN, Some(ahb1rstr::ETHMACRST),
+        Some(ahb1lpenr::ETHMACRXLPEN);
+    EthernetMacPtp: AHB1, ahb1enr::ETHMACPTPEN, Some(ahb1rstr::ETHMACRST),
+        Some(ahb1lpenr::ETHMACPTPLPEN);
+    OtgHs: AHB1, ahb1enr::OTGHSEN, Some(ahb1rstr::OTGHSRST), Some(ahb1lpenr::OTGHSLPEN);
+    OtgHsUlpi: AHB1, ahb1enr::OTGHSULPIEN, Some(ahb1rstr::OTGHSRST), Some(ahb1lpenr::OTGHSULPILPEN);
+    Dcmi: AHB2, ahb2enr::DCMIEN, Some(ahb2rstr::DCMIRST), Some(ahb2lpenr::DCMILPEN);
+    Cryp: AHB2, ahb2enr::CRYPEN, Some(ahb2rstr::CRYPRST), Some(ahb2lpenr::CRYPLPEN);
+    Hash: AHB2, ahb2enr::HASHEN, Some(ahb2rstr::HASHRST), Some(ahb2lpenr::HASHLPEN);
+    Rng: AHB2, ahb2enr::RNGEN, Some(ahb2rstr::RNGRST), Some(ahb2lpenr::RNGLPEN);
+    OtgFs: AHB2, ahb2enr::OTGFSEN, Some(ahb2rstr::OTGFSRST), Some(ahb2lpenr::OTGFSLPEN);
+    Fmc: AHB3, ahb3enr::FMCEN, Some(ahb3rstr::FMCRST), Some(ahb3lpenr::FMCLPEN);
+    Tim2: APB1, apb1enr::TIM2EN, Some(apb1rstr::TIM2RST), Some(apb1lpenr::TIM2LPEN);
+    Tim3: APB1, apb1enr::TIM3EN, Some(apb1rstr::TIM3RST), Some(apb1lpenr::TIM3LPEN);
+    Tim4: APB1, apb1enr::TIM4EN, Some(apb1rstr::TIM4RST), Some(apb1lpenr::TIM4LPEN);
+    Tim5: APB1, apb1enr::TIM5EN, Some(apb1rstr::TIM5RST), Some(apb1lpenr::TIM5LPEN);
+    Tim6: APB1, apb1enr::TIM6EN, Some(apb1rstr::TIM6RST), Some(apb1lpenr::TIM6LPEN);
+    Tim7: APB1, apb1enr::TIM7EN, Some(apb1rstr::TIM7RST), Some(apb1lpenr::TIM7LPEN);
+    Tim12: APB1, apb1enr::TIM12EN, Some(apb1rstr::TIM12RST), Some(apb1lpenr::TIM12LPEN);
+    Tim13: APB1, apb1enr::TIM13EN, Some(apb1rstr::TIM13RST), Some(apb1lpenr::TIM13LPEN);
+    Tim14: APB1, apb1enr::TIM14EN, Some(apb1rstr::TIM14RST), Some(apb1lpenr::TIM14LPEN);
+    Wwdg: APB1, apb1enr::WWDGEN, Some(apb1rstr::WWDGRST), Some(apb1lpenr::WWDGLPEN);
+    Spi2: APB1, apb1enr::SPI2EN, Some(apb1rstr::SPI2RST), Some(apb1lpenr::SPI2LPEN);
+    Spi3: APB1, apb1enr::SPI3EN, Some(apb1rstr::SPI3RST), Some(apb1lpenr::SPI3LPEN);
+    Usart2: APB1, apb1enr::USART2EN, Some(apb1rstr::USART2RST), Some(apb1lpenr::USART2LPEN);
+    Usart3: APB1, apb1enr::USART3EN, Some(apb1rstr::USART3RST), Some(apb1lpenr::USART3LPEN);
+    Uart4: APB1, apb1enr::UART4EN, Some(apb1rstr::UART4RST), Some(apb1lpenr::UART4LPEN);
+    Uart5: APB1, apb1enr::UART5EN, Some(apb1rstr::UART5RST), Some(apb1lpenr::UART5LPEN);
+    I2c1: APB1, apb1enr::I2C1EN, Some(apb1rstr::I2C1RST), Some(apb1lpenr::I2C1LPEN);
+    I2c2: APB1, apb1enr::I2C2EN, Some(apb1rstr::I2C2RST), Some(apb1lpenr::I2C2LPEN);
+    I2c3: APB1, apb1enr::I2C3EN, Some(apb1rstr::I2C3RST), Some(apb1lpenr::I2C3LPEN);
+    Can1: APB1, apb1enr::CAN1EN, Some(apb1rstr::CAN1RST), Some(apb1lpenr::CAN1LPEN);
+    Can2: APB1, apb1enr::CAN2EN, Some(apb1rstr::CAN2RST), Some(apb1lpenr::CAN2LPEN);
+    Pwr: APB1, apb1enr::PWREN, Some(apb1rstr::PWRRST), Some(apb1lpenr::PWRLPEN);
+    Dac: APB1, apb1enr::DACEN, Some(apb1rstr::DACRST), Some(apb1lpenr::DACLPEN);
+    Uart7: APB1, apb1enr::UART7EN, Some(apb1rstr::UART7RST), Some(apb1lpenr::UART7LPEN);
+    Uart8: APB1, apb1enr::UART8EN, Some(apb1rstr::UART8RST), Some(apb1lpenr::UART8LPEN);
+    Tim1: APB2, apb2enr::TIM1EN, Some(apb2rstr::TIM1RST), Some(apb2lpenr::TIM1LPEN);
+    Tim8: APB2, apb2enr::TIM8EN, Some(apb2rstr::TIM8RST), Some(apb2lpenr::TIM8LPEN);
+    Usart1: APB2, apb2enr::USART1EN, Some(apb2rstr::USART1RST), Some(apb2lpenr::USART1LPEN);
+    Usart6: APB2, apb2enr::USART6EN, Some(apb2rstr::USART6RST), Some(apb2lpenr::USART6LPEN);
+    Adc1: APB2, apb2enr::ADC1EN, Some(apb2rstr::ADCRST), Some(apb2lpenr::ADC1LPEN);
+    Adc2: APB2, apb2enr::ADC2EN, Some(apb2rstr::ADCRST), Some(apb2lpenr::ADC2LPEN);
+    Adc3: APB2, apb2enr::ADC3EN, Some(apb2rstr::ADCRST), Some(apb2lpenr::ADC3LPEN);
+    Sdio: APB2, apb2enr::SDIOEN, Some(apb2rstr::SDIORST), Some(apb2lpenr::SDIOLPEN);
+    Spi1: APB2, apb2enr::SPI1EN, Some(apb2rstr::SPI1RST), Some(apb2lpenr::SPI1LPEN);
+    Spi4: APB2, apb2enr::SPI4EN, Some(apb2rstr::SPI4RST), Some(apb2lpenr::SPI4LPEN);
+    Syscfg: APB2, apb2enr::SYSCFGEN, Some(apb2rstr::SYSCFGRST), Some(apb2lpenr::SYSCFGLPEN);
+    Tim9: APB2, apb2enr::TIM9EN, Some(apb2rstr::TIM9RST), Some(apb2lpenr::TIM9LPEN);
+    Tim10: APB2, apb2enr::TIM10EN, Some(apb2rstr::TIM10RST), Some(apb2lpenr::TIM10LPEN);
+    Tim11: APB2, apb2enr::TIM11EN, Some(apb2rstr::TIM11RST), Some(apb2lpenr::TIM11LPEN);
+    Spi5: APB2, apb2enr::SPI5EN, Some(apb2rstr::SPI5RST), Some(apb2lpenr::SPI5LPEN);
+    Spi6: APB2, apb2enr::SPI6EN, Some(apb2rstr::SPI6RST), Some(apb2lpenr::SPI6LPEN);
+    Sai1: APB2, apb2enr::SAI1EN, Some(apb2rstr::SAI1RST), Some(apb2lpenr::SAI1LPEN);
+    Ltdc: APB2, apb2enr::LTDCEN, Some(apb2rstr::LTDCRST), Some(apb2lpenr::LTDCLPEN);
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum PllClockSource {
     HSI,
     HSE,
 }
 
+/*
+   f_VCO = f_PLL_input * (PLLN / PLLM)
+   f_PLL_general_output = f_VCO / PLLP
+   f_48MHz = f_VCO / PLLQ
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MainPll {
+    source: PllClockSource,
+    m: u8,
+    n: u16,
+    p: u8,
+    q: u8,
+}
+
+impl MainPll {
+    // RM0090 p226, the frequency limits are checked by ClockConfig
+    pub const fn new(source: PllClockSource, m: u8, n: u16, p: u8, q: u8) -> Option<MainPll> {
+        let valid = m >= 2
+            && m <= 63
+            && n >= 50
+            && n <= 432
+            && matches!(p, 2 | 4 | 6 | 8)
+            && q >= 2
+            && q <= 15;
+        if valid {
+            Some(MainPll { source, m, n, p, q })
+        } else {
+            None
+        }
+    }
+
+    pub const fn source(&self) -> PllClockSource {
+        self.source
+    }
+
+    pub const fn m(&self) -> u8 {
+        self.m
+    }
+
+    pub const fn n(&self) -> u16 {
+        self.n
+    }
+
+    pub const fn p(&self) -> u8 {
+        self.p
+    }
+
+    pub const fn q(&self) -> u8 {
+        self.q
+    }
+}
+
+/*
+   RM0090 RCC_PLLI2SCFGR and RCC_DCKCFGR
+   PLLI2S runs from the main PLL input divided by PLLM, only PLLN and the output dividers are
+   its own.
+   f_VCO = f_PLL_input / PLLM * PLLI2SN
+   f_I2S = f_VCO / PLLI2SR
+   f_SAI1 = f_VCO / PLLI2SQ / PLLI2SDIVQ
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct I2sPll {
+    n: u16,
+    r: u8,
+    q: u8,
+    div_q: u8,
+}
+
+impl I2sPll {
+    // The VCO range is checked by ClockConfig
+    pub const fn new(n: u16, r: u8, q: u8, div_q: u8) -> Option<I2sPll> {
+        let valid = n >= 50
+            && n <= 432
+            && r >= 2
+            && r <= 7
+            && q >= 2
+            && q <= 15
+            && div_q >= 1
+            && div_q <= 32;
+        if valid {
+            Some(I2sPll { n, r, q, div_q })
+        } else {
+            None
+        }
+    }
+
+    pub const fn n(&self) -> u16 {
+        self.n
+    }
+
+    pub const fn r(&self) -> u8 {
+        self.r
+    }
+
+    pub const fn q(&self) -> u8 {
+        self.q
+    }
+
+    pub const fn div_q(&self) -> u8 {
+        self.div_q
+    }
+}
+
+/*
+   RM0090 RCC_PLLSAICFGR and RCC_DCKCFGR
+   PLLSAI shares the main PLL input and PLLM as well.
+   f_VCO = f_PLL_input / PLLM * PLLSAIN
+   f_SAI1 = f_VCO / PLLSAIQ / PLLSAIDIVQ
+   f_LCD-TFT = f_VCO / PLLSAIR / PLLSAIDIVR
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SaiPll {
+    n: u16,
+    q: u8,
+    r: u8,
+    div_q: u8,
+    div_r: u8,
+}
+
+impl SaiPll {
+    // PLLSAIDIVR is one of 2, 4, 8 and 16
+    pub const fn new(n: u16, q: u8, r: u8, div_q: u8, div_r: u8) -> Option<SaiPll> {
+        let valid = n >= 50
+            && n <= 432
+            && q >= 2
+            && q <= 15
+            && r >= 2
+            && r <= 7
+            && div_q >= 1
+            && div_q <= 32
+            && matches!(div_r, 2 | 4 | 8 | 16);
+        if valid {
+            Some(SaiPll {
+                n,
+                q,
+                r,
+                div_q,
+                div_r,
+            })
+        } else {
+            None
+        }
+    }
+
+    pub const fn n(&self) -> u16 {
+        self.n
+    }
+
+    pub const fn q(&self) -> u8 {
+        self.q
+    }
+
+    pub const fn r(&self) -> u8 {
+        self.r
+    }
+
+    pub const fn div_q(&self) -> u8 {
+        self.div_q
+    }
+
+    pub const fn div_r(&self) -> u8 {
+        self.div_r
+    }
+}
+
 #[repr(u32)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub enum SystemClock {
     HSI = 0b00,
     HSE = 0b01,
@@ -58,13 +398,83 @@ impl TryFrom<u32> for SystemClock {
     }
 }
 
-const RCC_CR: usize = 0;
-const RCC_PLLCFGR: usize = 0x04 >> 2;
-const RCC_CFGR: usize = 0x08 >> 2;
-const RCC_AHB1ENR: usize = 0x30 >> 2;
-const RCC_APB1ENR: usize = 0x40 >> 2;
-const RCC_APB2ENR: usize = 0x44 >> 2;
-const RCC_CSR: usize = 0x74 >> 2;
+// MCO1 on PA8
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mco1Source {
+    HSI = 0b00,
+    LSE = 0b01,
+    HSE = 0b10,
+    PLL = 0b11,
+}
+
+// MCO2 on PC9
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mco2Source {
+    SYSCLK = 0b00,
+    PLLI2S = 0b01,
+    HSE = 0b10,
+    PLL = 0b11,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum McoPrescaler {
+    Div1,
+    Div2,
+    Div3,
+    Div4,
+    Div5,
+}
+
+impl McoPrescaler {
+    // RM0090 RCC_CFGR, 0xx no division, 100 to 111 divide by 2 to 5
+    const fn bits(self) -> u32 {
+        match self {
+            McoPrescaler::Div1 => 0b000,
+            McoPrescaler::Div2 => 0b100,
+            McoPrescaler::Div3 => 0b101,
+            McoPrescaler::Div4 => 0b110,
+            McoPrescaler::Div5 => 0b111,
+        }
+    }
+}
+
+/*
+   RM0090 p224
+   A power-on reset sets PORRSTF, BORRSTF and PINRSTF together, any internal reset (software,
+   watchdogs, low-power) also pulls NRST and sets PINRSTF. Flags are checked from the most
+   specific cause to the least specific one.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResetCause {
+    LowPower,
+    IndependentWatchdog,
+    WindowWatchdog,
+    Software,
+    PowerOn,
+    Brownout,
+    Pin,
+    Unknown,
+}
+
+impl ResetCause {
+    pub fn from_flags(csr_value: u32) -> Self {
+        const CAUSES: [(Field, ResetCause); 7] = [
+            (csr::LPWRRSTF, ResetCause::LowPower),
+            (csr::IWDGRSTF, ResetCause::IndependentWatchdog),
+            (csr::WWDGRSTF, ResetCause::WindowWatchdog),
+            (csr::SFTRSTF, ResetCause::Software),
+            (csr::PORRSTF, ResetCause::PowerOn),
+            (csr::BORRSTF, ResetCause::Brownout),
+            (csr::PINRSTF, ResetCause::Pin),
+        ];
+        CAUSES
+            .iter()
+            .find(|(flag, _)| flag.is_set(csr_value))
+            .map_or(ResetCause::Unknown, |(_, cause)| *cause)
+    }
+}
 
 /*
    ES0206 Errata
@@ -83,195 +493,374 @@ const RCC_CSR: usize = 0x74 >> 2;
 */
 impl RccConf {
     pub const fn new(base: u32) -> Self {
-        RccConf {
-            reg: MemoryMappedIo::new(base),
-        }
+        RccConf::with_bus(MemoryMappedIo::new(base))
     }
+}
 
-    pub fn enable_gpio_ports(&self, ports: &[GpioPort]) {
-        let mut current_value: u32 = self.reg.read(RCC_AHB1ENR);
-        current_value |= ports.iter().fold(0, |acc, e| acc | (*e as u32));
-        self.reg.write(current_value, RCC_AHB1ENR);
-        unsafe {
-            store_barrier();
-        }
-        let _value = self.reg.read(RCC_AHB1ENR);
+impl<B: RegisterBus> RccConf<B> {
+    pub const fn with_bus(reg: B) -> Self {
+        RccConf { reg }
     }
 
-    pub fn enable_system_configuration_controller(&self) {
-        self.reg.set_bit(14, RCC_APB2ENR);
+    /*
+       ES0206 workaround above: DSB, then a dummy read of the enable register, before the
+       peripheral registers are accessed.
+    */
+    pub fn enable_peripheral(&self, peripheral: Peripheral) {
+        let register = peripheral.bus().enable_register();
+        self.reg.set_bit(register, peripheral.enable_field());
         unsafe {
             store_barrier();
         }
-        let _value = self.reg.read(RCC_APB2ENR);
+        let _value = self.reg.read_register(register);
     }
 
-    pub fn enable_basic_timer(&self, timer: BasicTimer) {
-        let mut current_value: u32 = self.reg.read(RCC_APB1ENR);
-        current_value |= timer as u32;
-        self.reg.write(current_value, RCC_APB1ENR);
+    pub fn disable_peripheral(&self, peripheral: Peripheral) {
+        self.reg.clear_bit(
+            peripheral.bus().enable_register(),
+            peripheral.enable_field(),
+        );
         unsafe {
             store_barrier();
         }
-        let _value = self.reg.read(RCC_APB1ENR);
     }
 
-    pub fn enable_usart(&self, usart_number: u32) {
-        match usart_number {
-            3 => {
-                self.reg.set_bit(18, RCC_APB1ENR);
+    pub fn is_peripheral_enabled(&self, peripheral: Peripheral) -> bool {
+        self.reg.is_field_set(
+            peripheral.bus().enable_register(),
+            peripheral.enable_field(),
+        )
+    }
+
+    /*
+       Pulses the reset bit, the peripheral registers are back at their reset values and the
+       clock enable is left as it is. Peripherals without a reset bit are not touched.
+    */
+    pub fn reset_peripheral(&self, peripheral: Peripheral) {
+        if let Some(field) = peripheral.reset_field() {
+            let register = peripheral.bus().reset_register();
+            self.reg.set_bit(register, field);
+            self.reg.clear_bit(register, field);
+            unsafe {
+                store_barrier();
             }
-            _ => {}
+            let _value = self.reg.read_register(register);
         }
-        unsafe {
-            store_barrier();
+    }
+
+    // Whether the peripheral keeps its clock in sleep mode, all of them do after reset
+    pub fn set_low_power_clock(&self, peripheral: Peripheral, enabled: bool) {
+        if let Some(field) = peripheral.low_power_field() {
+            let register = peripheral.bus().low_power_register();
+            self.reg.write_field(register, field, enabled as u32);
+            unsafe {
+                store_barrier();
+            }
+            let _value = self.reg.read_register(register);
         }
-        let _value = self.reg.read(RCC_APB1ENR);
     }
 
     pub fn enable_internal_low_speed_oscillator(&self) {
-        self.reg.set_bit(0, RCC_CSR);
+        self.reg.write_field(CSR, csr::LSION, 1);
         unsafe {
             store_barrier();
             while !self.is_internal_low_speed_oscillator_ready() {
                 no_operation();
             }
         }
-        let _value = self.reg.read(RCC_CSR);
+        let _value = self.reg.read_register(CSR);
+    }
+
+    // Flags survive resets until RMVF is written, so they are cleared once read
+    pub fn take_reset_cause(&self) -> ResetCause {
+        let cause = ResetCause::from_flags(self.reg.read_register(CSR));
+        self.reg.set_bit(CSR, csr::RMVF);
+        cause
     }
 
     pub fn is_main_pll_ready(&self) -> bool {
-        self.reg.is_bit_set(25, RCC_CR)
+        self.reg.is_field_set(CR, cr::PLLRDY)
     }
 
     pub fn enable_main_pll(&self) {
-        self.reg.set_bit(24, RCC_CR);
+        self.reg.write_field(CR, cr::PLLON, 1);
+        unsafe {
+            store_barrier();
+            while !self.is_main_pll_ready() {
+                no_operation();
+            }
+        }
+        let _value = self.reg.read_register(CR);
+    }
+
+    pub fn disable_main_pll(&self) {
+        self.reg.write_field(CR, cr::PLLON, 0);
         unsafe {
             store_barrier();
             while self.is_main_pll_ready() {
                 no_operation();
             }
         }
-        let _value = self.reg.read(RCC_CR);
     }
 
     pub fn is_hse_ready(&self) -> bool {
-        self.reg.is_bit_set(17, RCC_CR)
+        self.reg.is_field_set(CR, cr::HSERDY)
     }
 
-    // High Speed External Clock Signal
-    pub fn enable_hse(&self, oscillator_bypassed: bool) {
-        if oscillator_bypassed {
-            self.reg.set_bit(18, RCC_CR);
-        } else {
-            self.reg.clear_bit(18, RCC_CR);
-        }
-        self.reg.set_bit(16, RCC_CR);
+    /*
+       High Speed External Clock Signal
+       A crystal that does not oscillate or a missing external clock never sets HSERDY, HSE is
+       switched off again after HSE_STARTUP_POLLS.
+    */
+    pub fn enable_hse(&self, oscillator_bypassed: bool) -> Result<(), RccError> {
+        self.reg
+            .write_field(CR, cr::HSEBYP, oscillator_bypassed as u32);
+        self.reg.write_field(CR, cr::HSEON, 1);
+        let mut polls = 0;
         unsafe {
             store_barrier();
             while !self.is_hse_ready() {
+                if polls == HSE_STARTUP_POLLS {
+                    self.disable_hse();
+                    return Err(RccError::HseStartupTimeout);
+                }
+                polls += 1;
+                no_operation();
+            }
+        }
+        let _value = self.reg.read_register(CR);
+        Ok(())
+    }
+
+    pub fn disable_hse(&self) {
+        self.reg.write_field(CR, cr::HSEON, 0);
+        unsafe {
+            store_barrier();
+            while self.is_hse_ready() {
                 no_operation();
             }
         }
-        let _value = self.reg.read(RCC_CR);
+        // HSEBYP can only be written while HSE is off
+        self.reg.write_field(CR, cr::HSEBYP, 0);
+    }
+
+    /*
+       RM0090 p218
+       With CSSON set the clock detector watches HSE once HSERDY is set. On an HSE failure the
+       hardware switches HSE off, moves SYSCLK to HSI when HSE fed it directly or through the
+       main PLL, sets CSSF and raises the NMI. CSSF has to be cleared in the NMI handler, the
+       NMI is raised again as long as it is set.
+    */
+    pub fn enable_clock_security_system(&self) {
+        self.reg.set_bit(CR, cr::CSSON);
+    }
+
+    pub fn disable_clock_security_system(&self) {
+        self.reg.clear_bit(CR, cr::CSSON);
+    }
+
+    pub fn is_hse_failure_detected(&self) -> bool {
+        self.reg.is_field_set(CIR, cir::CSSF)
+    }
+
+    pub fn clear_hse_failure(&self) {
+        self.reg.set_bit(CIR, cir::CSSC);
     }
 
     pub fn is_hsi_ready(&self) -> bool {
-        self.reg.is_bit_set(1, RCC_CR)
+        self.reg.is_field_set(CR, cr::HSIRDY)
     }
 
     // High Speed Internal Clock Signal
     pub fn enable_hsi(&self) {
-        self.reg.set_bit(0, RCC_CR);
+        self.reg.write_field(CR, cr::HSION, 1);
         unsafe {
             store_barrier();
             while !self.is_hsi_ready() {
                 no_operation();
             }
         }
-        let _value = self.reg.read(RCC_CR);
+        let _value = self.reg.read_register(CR);
     }
 
     pub fn disable_hsi(&self) {
-        self.reg.clear_bit(0, RCC_CR);
+        self.reg.write_field(CR, cr::HSION, 0);
         unsafe {
             store_barrier();
         }
-        let _value = self.reg.read(RCC_CR);
+        let _value = self.reg.read_register(CR);
     }
 
     /*
-       f_VCO = f_PLL_input * (multiplication_factor / division_factor)
-       f_PLL_general_output = f_VCO / division_main_system_clock
-       f_48MHz = f_VCO / division_48mhz_click
-       multiplication_factor => PLLN
-       division_factor => PLLM
-       division_main_system_clock => PLLP
-       division_48mhz_click => PLLQ
+       RM0090 p226
+       The PLL input clock has to be running and the PLL disabled, MainPll::new checks the
+       divider ranges.
     */
-    pub fn configure_main_pll(
-        &self,
-        clock_source: PllClockSource,
-        hse_oscillator_bypass: bool,
-        multiplication_factor: u16,
-        division_factor: u8,
-        division_main_system_clock: u8,
-        division_48mhz_click: u8,
-    ) {
-        let mut current_value = self.reg.read(RCC_PLLCFGR);
-        current_value &= !(0b1 << 22); // PLL Clock Source
-        match clock_source {
-            PllClockSource::HSI => {
-                self.enable_hsi();
+    pub fn configure_main_pll(&self, pll: &MainPll) {
+        let pll_source = match pll.source {
+            PllClockSource::HSI => 0,
+            PllClockSource::HSE => 1,
+        };
+        self.reg.modify_register(PLLCFGR, |current| {
+            let mut value = pllcfgr::PLLSRC.write(current, pll_source);
+            value = pllcfgr::PLLN.write(value, pll.n as u32);
+            value = pllcfgr::PLLM.write(value, pll.m as u32);
+            value = pllcfgr::PLLP.write(value, (pll.p / 2 - 1) as u32);
+            pllcfgr::PLLQ.write(value, pll.q as u32)
+        });
+        unsafe {
+            store_barrier();
+        }
+        let _value = self.reg.read_register(PLLCFGR);
+    }
+
+    // PLLSRC and PLLM alone, for PLLI2S and PLLSAI while the main PLL stays off
+    pub fn configure_pll_input(&self, source: PllClockSource, m: u8) {
+        let pll_source = match source {
+            PllClockSource::HSI => 0,
+            PllClockSource::HSE => 1,
+        };
+        self.reg.modify_register(PLLCFGR, |current| {
+            let value = pllcfgr::PLLSRC.write(current, pll_source);
+            pllcfgr::PLLM.write(value, m as u32)
+        });
+        unsafe {
+            store_barrier();
+        }
+        let _value = self.reg.read_register(PLLCFGR);
+    }
+
+    pub fn is_i2s_pll_ready(&self) -> bool {
+        self.reg.is_field_set(CR, cr::PLLI2SRDY)
+    }
+
+    pub fn enable_i2s_pll(&self) -> Result<(), RccError> {
+        self.reg.write_field(CR, cr::PLLI2SON, 1);
+        self.wait_for_pll_lock(cr::PLLI2SON, cr::PLLI2SRDY, RccError::I2sPllLockTimeout)
+    }
+
+    pub fn disable_i2s_pll(&self) {
+        self.reg.write_field(CR, cr::PLLI2SON, 0);
+        unsafe {
+            store_barrier();
+            while self.is_i2s_pll_ready() {
+                no_operation();
             }
-            PllClockSource::HSE => {
-                self.enable_hse(hse_oscillator_bypass);
-                current_value |= 0b1 << 22;
+        }
+    }
+
+    // PLLI2S has to be disabled, PLLM is set with the main PLL
+    pub fn configure_i2s_pll(&self, pll: &I2sPll) {
+        self.reg.modify_register(PLLI2SCFGR, |current| {
+            let value = plli2scfgr::PLLI2SN.write(current, pll.n as u32);
+            let value = plli2scfgr::PLLI2SQ.write(value, pll.q as u32);
+            plli2scfgr::PLLI2SR.write(value, pll.r as u32)
+        });
+        self.reg
+            .write_field(DCKCFGR, dckcfgr::PLLI2SDIVQ, (pll.div_q - 1) as u32);
+        unsafe {
+            store_barrier();
+        }
+        let _value = self.reg.read_register(PLLI2SCFGR);
+    }
+
+    pub fn is_sai_pll_ready(&self) -> bool {
+        self.reg.is_field_set(CR, cr::PLLSAIRDY)
+    }
+
+    pub fn enable_sai_pll(&self) -> Result<(), RccError> {
+        self.reg.write_field(CR, cr::PLLSAION, 1);
+        self.wait_for_pll_lock(cr::PLLSAION, cr::PLLSAIRDY, RccError::SaiPllLockTimeout)
+    }
+
+    pub fn disable_sai_pll(&self) {
+        self.reg.write_field(CR, cr::PLLSAION, 0);
+        unsafe {
+            store_barrier();
+            while self.is_sai_pll_ready() {
+                no_operation();
             }
         }
-        current_value &= !(n_bits!(9) << 6); // [14:6] PLLN
-        current_value |= (multiplication_factor as u32) << 6;
-        current_value &= !n_bits!(6); // [5:0] PLLM
-        current_value |= division_factor as u32;
-        current_value &= !(0b11 << 16); // [17:16] PLLP
-        let main_system_clk_divider = match division_main_system_clock {
-            2 => 0b00,
-            4 => 0b01,
-            6 => 0b10,
-            8 => 0b11,
-            _ => 0b00,
-        };
-        current_value |= main_system_clk_divider << 16;
-        current_value &= !(n_bits!(4) << 24); // [27:24] PLLQ
-        current_value |= (division_48mhz_click as u32) << 24;
-        self.reg.write(current_value, RCC_PLLCFGR);
+    }
+
+    // PLLSAI has to be disabled, PLLSAIDIVR 0 to 3 selects /2, /4, /8 and /16
+    pub fn configure_sai_pll(&self, pll: &SaiPll) {
+        self.reg.modify_register(PLLSAICFGR, |current| {
+            let value = pllsaicfgr::PLLSAIN.write(current, pll.n as u32);
+            let value = pllsaicfgr::PLLSAIQ.write(value, pll.q as u32);
+            pllsaicfgr::PLLSAIR.write(value, pll.r as u32)
+        });
+        self.reg.modify_register(DCKCFGR, |current| {
+            let value = dckcfgr::PLLSAIDIVQ.write(current, (pll.div_q - 1) as u32);
+            dckcfgr::PLLSAIDIVR.write(value, pll.div_r.trailing_zeros() - 1)
+        });
+        unsafe {
+            store_barrier();
+        }
+        let _value = self.reg.read_register(PLLSAICFGR);
+    }
+
+    // The PLL is switched off again when it does not lock
+    fn wait_for_pll_lock(
+        &self,
+        enable: Field,
+        ready: Field,
+        timeout: RccError,
+    ) -> Result<(), RccError> {
+        let mut polls = 0;
         unsafe {
             store_barrier();
+            while !self.reg.is_field_set(CR, ready) {
+                if polls == PLL_LOCK_POLLS {
+                    self.reg.write_field(CR, enable, 0);
+                    return Err(timeout);
+                }
+                polls += 1;
+                no_operation();
+            }
         }
-        let _value = self.reg.read(RCC_PLLCFGR);
+        let _value = self.reg.read_register(CR);
+        Ok(())
+    }
+
+    pub fn reset_main_pll_configuration(&self) {
+        self.reg.reset_register(PLLCFGR);
     }
 
     pub fn is_internal_low_speed_oscillator_ready(&self) -> bool {
-        self.reg.is_bit_set(1, RCC_CSR)
+        self.reg.is_field_set(CSR, csr::LSIRDY)
     }
 
-    pub fn get_system_clock_status(&self) -> SystemClock {
-        let value = (self.reg.read(RCC_CFGR) & (0b11 << 2)) >> 2;
-        SystemClock::try_from(value).unwrap()
+    pub fn get_system_clock_status(&self) -> Result<SystemClock, RccError> {
+        let value = self.reg.read_field(CFGR, cfgr::SWS);
+        SystemClock::try_from(value).map_err(|_| RccError::ReservedSystemClockStatus)
     }
 
     pub fn set_system_clock(&self, system_clock: SystemClock) {
-        let mut current_value = self.reg.read(RCC_CFGR);
-        current_value &= 0b11; // [1:0] SW
-        current_value |= u32::from(system_clock.clone());
-        self.reg.write(current_value, RCC_CFGR);
+        self.reg
+            .write_field(CFGR, cfgr::SW, u32::from(system_clock));
         unsafe {
             store_barrier();
-            while self.get_system_clock_status() != system_clock {
+            while self.get_system_clock_status() != Ok(system_clock) {
                 no_operation();
             }
         }
-        let _value = self.reg.read(RCC_CFGR);
+        let _value = self.reg.read_register(CFGR);
+    }
+
+    // Source and prescaler should be set before the pin is switched to MCO, mco.rs does both
+    pub fn set_mco1(&self, source: Mco1Source, prescaler: McoPrescaler) {
+        self.reg.modify_register(CFGR, |current| {
+            let value = cfgr::MCO1.write(current, source as u32);
+            cfgr::MCO1PRE.write(value, prescaler.bits())
+        });
+    }
+
+    pub fn set_mco2(&self, source: Mco2Source, prescaler: McoPrescaler) {
+        self.reg.modify_register(CFGR, |current| {
+            let value = cfgr::MCO2.write(current, source as u32);
+            cfgr::MCO2PRE.write(value, prescaler.bits())
+        });
     }
 
     fn calculate_apb_prescaler(prescaler: u8) -> u32 {
@@ -285,36 +874,31 @@ impl RccConf {
     }
 
     fn is_apb_prescaler_set(&self, high_speed: u8, low_speed: u8) -> bool {
-        let current = self.reg.read(RCC_CFGR);
-        let high = (current & (n_bits!(3) << 13)) >> 13;
-        let low = (current & (n_bits!(3) << 10)) >> 10;
-        high == Self::calculate_apb_prescaler(high_speed)
-            && low == Self::calculate_apb_prescaler(low_speed)
+        let current = self.reg.read_register(CFGR);
+        cfgr::PPRE2.read(current) == Self::calculate_apb_prescaler(high_speed)
+            && cfgr::PPRE1.read(current) == Self::calculate_apb_prescaler(low_speed)
     }
 
     pub fn set_highest_apb_dividers(&self) {
-        let mut current_value = self.reg.read(RCC_CFGR);
-        current_value &= clear_mask!(3, 13); // [15:13] PPRE2
-        current_value &= clear_mask!(3, 10); // [12:10] PPRE1
-        current_value |= (0b111 << 13) | (0b111 << 10);
-        self.reg.write(current_value, RCC_CFGR);
+        self.reg.modify_register(CFGR, |current| {
+            let value = cfgr::PPRE2.write(current, cfgr::PPRE2.max_value());
+            cfgr::PPRE1.write(value, cfgr::PPRE1.max_value())
+        });
     }
 
     // Advanced Peripheral Bus
     pub fn set_apb_prescaler(&self, high_speed: u8, low_speed: u8) {
-        let mut current_value = self.reg.read(RCC_CFGR);
-        current_value &= clear_mask!(3, 13); // [15:13] PPRE2
-        current_value &= clear_mask!(3, 10); // [12:10] PPRE1
-        current_value |= Self::calculate_apb_prescaler(high_speed) << 13;
-        current_value |= Self::calculate_apb_prescaler(low_speed) << 10;
-        self.reg.write(current_value, RCC_CFGR);
+        self.reg.modify_register(CFGR, |current| {
+            let value = cfgr::PPRE2.write(current, Self::calculate_apb_prescaler(high_speed));
+            cfgr::PPRE1.write(value, Self::calculate_apb_prescaler(low_speed))
+        });
         unsafe {
             store_barrier();
             while !self.is_apb_prescaler_set(high_speed, low_speed) {
                 no_operation();
             }
         }
-        let _value = self.reg.read(RCC_CFGR);
+        let _value = self.reg.read_register(CFGR);
     }
 
     fn calculate_ahb_prescaler(prescaler: u16) -> u32 {
@@ -332,42 +916,48 @@ impl RccConf {
     }
 
     fn is_ahb_prescaler_set(&self, prescaler: u16) -> bool {
-        let current = (self.reg.read(RCC_CFGR) & (n_bits!(4) << 4)) >> 4;
-        Self::calculate_ahb_prescaler(prescaler) == current
+        self.reg.read_field(CFGR, cfgr::HPRE) == Self::calculate_ahb_prescaler(prescaler)
     }
 
-    // Advanced High-performance Bus
-    pub fn set_ahb_prescaler(&self, divider: u16) {
-        let mut current_value = self.reg.read(RCC_CFGR);
-        current_value &= clear_mask!(4, 4); // [7:4] HPRE
-        current_value |= Self::calculate_ahb_prescaler(divider) << 4;
-        self.reg.write(current_value, RCC_CFGR);
-        unsafe {
-            store_barrier();
-            while !self.is_ahb_prescaler_set(divider) {
-                no_operation();
-            }
+    pub fn get_ahb_prescaler(&self) -> u16 {
+        match self.reg.read_field(CFGR, cfgr::HPRE) {
+            0b1000 => 2,
+            0b1001 => 4,
+            0b1010 => 8,
+            0b1011 => 16,
+            0b1100 => 64,
+            0b1101 => 128,
+            0b1110 => 256,
+            0b1111 => 512,
+            _ => 1,
         }
-        let _value = self.reg.read(RCC_CFGR);
     }
 
-    pub fn enable_dma(&self, dma_id: u32) {
-        match dma_id {
-            1 => self.reg.set_bit(21, RCC_AHB1ENR),
-            2 => self.reg.set_bit(22, RCC_AHB1ENR),
-            _ => {}
-        }
-        unsafe {
-            store_barrier();
-        }
-        let _value = self.reg.read(RCC_AHB1ENR);
+    // (high_speed, low_speed) like set_apb_prescaler
+    pub fn get_apb_prescaler(&self) -> (u8, u8) {
+        let decode = |bits| match bits {
+            0b100 => 2,
+            0b101 => 4,
+            0b110 => 8,
+            0b111 => 16,
+            _ => 1,
+        };
+        (
+            decode(self.reg.read_field(CFGR, cfgr::PPRE2)),
+            decode(self.reg.read_field(CFGR, cfgr::PPRE1)),
+        )
     }
 
-    pub fn enable_power_interface(&self) {
-        self.reg.set_bit(28, RCC_APB1ENR);
+    // Advanced High-performance Bus
+    pub fn set_ahb_prescaler(&self, divider: u16) {
+        self.reg
+            .write_field(CFGR, cfgr::HPRE, Self::calculate_ahb_prescaler(divider));
         unsafe {
             store_barrier();
+            while !self.is_ahb_prescaler_set(divider) {
+                no_operation();
+            }
         }
-        let _value = self.reg.read(RCC_APB1ENR);
+        let _value = self.reg.read_register(CFGR);
     }
 }
@@ -0,0 +1,157 @@
+use crate::register_bus::RegisterBus;
+use crate::registers::describe;
+use core::cell::Cell;
+use core::fmt::{Display, Formatter, Result};
+
+/*
+   Register bus wrapper logging every word read and written by a driver.
+
+   Several RecordingBus instances can share one Recorder, so the order of accesses across
+   peripherals (RCC, PWR and FLASH while switching clocks) ends up in a single sequence.
+   The Display output decodes each access into peripheral, register and non-zero fields and
+   is what the golden files are compared against.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Access {
+    pub kind: AccessKind,
+    pub address: u32,
+    pub value: u32,
+}
+
+pub struct Recorder<const CAPACITY: usize> {
+    accesses: [Cell<Access>; CAPACITY],
+    length: Cell<usize>,
+}
+
+#[derive(Clone)]
+pub struct RecordingBus<'a, B: RegisterBus, const CAPACITY: usize> {
+    inner: B,
+    recorder: &'a Recorder<CAPACITY>,
+}
+
+impl<const CAPACITY: usize> Recorder<CAPACITY> {
+    pub const fn new() -> Self {
+        Recorder {
+            accesses: [const {
+                Cell::new(Access {
+                    kind: AccessKind::Read,
+                    address: 0,
+                    value: 0,
+                })
+            }; CAPACITY],
+            length: Cell::new(0),
+        }
+    }
+
+    pub fn record(&self, access: Access) {
+        let length = self.length.get();
+        assert!(length < CAPACITY, "recorder is full");
+        self.accesses[length].set(access);
+        self.length.set(length + 1);
+    }
+
+    pub fn len(&self) -> usize {
+        self.length.get()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.length.set(0);
+    }
+
+    pub fn accesses(&self) -> impl Iterator<Item = Access> + '_ {
+        self.accesses[..self.len()].iter().map(Cell::get)
+    }
+}
+
+impl<const CAPACITY: usize> Default for Recorder<CAPACITY> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const CAPACITY: usize> Display for Recorder<CAPACITY> {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> Result {
+        for access in self.accesses() {
+            writeln!(formatter, "{access}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Access {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> Result {
+        let kind = match self.kind {
+            AccessKind::Read => "R",
+            AccessKind::Write => "W",
+        };
+        write!(
+            formatter,
+            "{kind} {:#010x} {:#010x}",
+            self.address, self.value
+        )?;
+        if let Some(description) = describe(self.address) {
+            write!(
+                formatter,
+                " {}.{}",
+                description.peripheral, description.register
+            )?;
+            for (name, field) in description.fields {
+                let value = field.read(self.value);
+                if value != 0 {
+                    write!(formatter, " {name}={value:#x}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, B: RegisterBus, const CAPACITY: usize> RecordingBus<'a, B, CAPACITY> {
+    pub const fn new(inner: B, recorder: &'a Recorder<CAPACITY>) -> Self {
+        RecordingBus { inner, recorder }
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    fn address(&self, index: usize) -> u32 {
+        self.inner.base_address() + (index * 4) as u32
+    }
+}
+
+// Bit operations use the default read-modify-write, the bit-band path of the inner bus is bypassed
+impl<B: RegisterBus, const CAPACITY: usize> RegisterBus for RecordingBus<'_, B, CAPACITY> {
+    fn base_address(&self) -> u32 {
+        self.inner.base_address()
+    }
+
+    fn read(&self, index: usize) -> u32 {
+        let value = self.inner.read(index);
+        self.recorder.record(Access {
+            kind: AccessKind::Read,
+            address: self.address(index),
+            value,
+        });
+        value
+    }
+
+    fn write(&self, value: u32, index: usize) {
+        self.recorder.record(Access {
+            kind: AccessKind::Write,
+            address: self.address(index),
+            value,
+        });
+        self.inner.write(value, index);
+    }
+}
@@ -0,0 +1,103 @@
+/*
+   Register and bitfield descriptions.
+
+   A Register is a byte offset from the peripheral base plus the value it holds after reset.
+   A Field is a bit position and width inside a register. Both are plain constants, so a
+   layout is written down once in registers.rs and drivers only refer to it by name.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Register {
+    offset: usize,
+    reset_value: u32,
+}
+
+impl Register {
+    pub const fn new(offset: usize, reset_value: u32) -> Self {
+        assert!(
+            offset.is_multiple_of(4),
+            "register offset must be word aligned"
+        );
+        Register {
+            offset,
+            reset_value,
+        }
+    }
+
+    pub const fn offset(self) -> usize {
+        self.offset
+    }
+
+    // Word index used by RegisterBus
+    pub const fn index(self) -> usize {
+        self.offset >> 2
+    }
+
+    pub const fn reset_value(self) -> u32 {
+        self.reset_value
+    }
+
+    // n-th element of a register array, e.g. DMA stream registers or GPIO AFRL/AFRH
+    pub const fn nth(self, n: usize, stride: usize) -> Self {
+        Register::new(self.offset + n * stride, self.reset_value)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Field {
+    position: u32,
+    width: u32,
+}
+
+impl Field {
+    pub const fn new(position: u32, width: u32) -> Self {
+        assert!(width > 0 && position + width <= 32, "field out of register");
+        Field { position, width }
+    }
+
+    pub const fn position(self) -> u32 {
+        self.position
+    }
+
+    pub const fn width(self) -> u32 {
+        self.width
+    }
+
+    pub const fn max_value(self) -> u32 {
+        u32::MAX >> (32 - self.width)
+    }
+
+    pub const fn mask(self) -> u32 {
+        self.max_value() << self.position
+    }
+
+    // n-th repetition of a field, e.g. MODER0.nth(7) is the mode of pin 7
+    pub const fn nth(self, n: u32) -> Self {
+        Field::new(self.position + n * self.width, self.width)
+    }
+
+    // Field value moved into place, for registers written without reading first
+    pub const fn value(self, value: u32) -> u32 {
+        debug_assert!(value <= self.max_value(), "value does not fit in field");
+        (value << self.position) & self.mask()
+    }
+
+    pub const fn read(self, register_value: u32) -> u32 {
+        (register_value & self.mask()) >> self.position
+    }
+
+    pub const fn write(self, register_value: u32, value: u32) -> u32 {
+        (register_value & !self.mask()) | self.value(value)
+    }
+
+    pub const fn is_set(self, register_value: u32) -> bool {
+        register_value & self.mask() != 0
+    }
+}
+
+// Names of a register and its fields at an absolute address, generated from the SVD
+pub struct RegisterDescription {
+    pub peripheral: &'static str,
+    pub register: &'static str,
+    pub address: u32,
+    pub fields: &'static [(&'static str, Field)],
+}
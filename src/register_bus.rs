@@ -0,0 +1,120 @@
+use crate::interrupt;
+use crate::register::{Field, Register};
+
+/*
+   Word-addressed access to a block of peripheral registers.
+
+   MemoryMappedIo implements it for the target and SimulatedBus keeps the registers in memory
+   so driver logic can run on the host. Drivers use the Register/Field helpers, the raw word
+   accessors are the only thing an implementation has to provide.
+
+   Read-modify-write goes through a critical section so an interrupt handler writing the same
+   register in between can not lose its update. Single-bit fields are written with set_bit and
+   clear_bit which an implementation can make atomic without masking interrupts, MemoryMappedIo
+   uses the bit-band alias for that.
+*/
+pub trait RegisterBus {
+    fn base_address(&self) -> u32;
+
+    fn read(&self, index: usize) -> u32;
+
+    fn write(&self, value: u32, index: usize);
+
+    fn register_address(&self, register: Register) -> u32 {
+        self.base_address() + register.offset() as u32
+    }
+
+    fn read_register(&self, register: Register) -> u32 {
+        self.read(register.index())
+    }
+
+    fn write_register(&self, register: Register, value: u32) {
+        self.write(value, register.index());
+    }
+
+    fn modify_register(&self, register: Register, modify: impl FnOnce(u32) -> u32) {
+        interrupt::free(|| {
+            let current_value = self.read_register(register);
+            self.write_register(register, modify(current_value));
+        });
+    }
+
+    fn set_bit(&self, register: Register, field: Field) {
+        debug_assert!(field.width() == 1);
+        self.modify_register(register, |current| current | field.mask());
+    }
+
+    fn clear_bit(&self, register: Register, field: Field) {
+        debug_assert!(field.width() == 1);
+        self.modify_register(register, |current| current & !field.mask());
+    }
+
+    fn toggle_bit(&self, register: Register, field: Field) {
+        debug_assert!(field.width() == 1);
+        self.modify_register(register, |current| current ^ field.mask());
+    }
+
+    /*
+       rc_w1 flags are cleared by writing 1 and writing 0 has no effect, a single write of the
+       flag mask is atomic. Neither modify_register nor the bit-band alias may be used on these
+       registers, both write back the other flags that were read as 1 and clear them as well.
+    */
+    fn write_one_to_clear(&self, register: Register, field: Field) {
+        self.write_register(register, field.mask());
+    }
+
+    // rc_w0 flags are cleared by writing 0, writing 1 to the other flags has no effect
+    fn write_zero_to_clear(&self, register: Register, field: Field) {
+        self.write_register(register, !field.mask());
+    }
+
+    fn reset_register(&self, register: Register) {
+        self.write_register(register, register.reset_value());
+    }
+
+    fn read_field(&self, register: Register, field: Field) -> u32 {
+        field.read(self.read_register(register))
+    }
+
+    fn write_field(&self, register: Register, field: Field, value: u32) {
+        if field.width() == 1 {
+            if value & 1 == 1 {
+                self.set_bit(register, field);
+            } else {
+                self.clear_bit(register, field);
+            }
+        } else {
+            self.modify_register(register, |current| field.write(current, value));
+        }
+    }
+
+    fn is_field_set(&self, register: Register, field: Field) -> bool {
+        field.is_set(self.read_register(register))
+    }
+}
+
+impl<B: RegisterBus> RegisterBus for &B {
+    fn base_address(&self) -> u32 {
+        (*self).base_address()
+    }
+
+    fn read(&self, index: usize) -> u32 {
+        (*self).read(index)
+    }
+
+    fn write(&self, value: u32, index: usize) {
+        (*self).write(value, index)
+    }
+
+    fn set_bit(&self, register: Register, field: Field) {
+        (*self).set_bit(register, field)
+    }
+
+    fn clear_bit(&self, register: Register, field: Field) {
+        (*self).clear_bit(register, field)
+    }
+
+    fn toggle_bit(&self, register: Register, field: Field) {
+        (*self).toggle_bit(register, field)
+    }
+}
@@ -0,0 +1,21 @@
+/*
+   STM32F439 register map generated by build.rs from svd/STM32F439x.svd, with the core
+   peripherals (NVIC, SCB, MPU, STK, FPU) taken from svd/cortex-m4-core.svd.
+
+   Every peripheral gets a module with its BASE_ADDRESS and registers, fields live in a module
+   named after their register. Peripherals derived from another one in the SVD re-export its
+   registers and only carry their own base address.
+*/
+include!(concat!(env!("OUT_DIR"), "/registers.rs"));
+
+// Layouts shared by every instance of a peripheral, drivers are written against these
+pub use self::dma2 as dma;
+pub use self::gpioc as gpio;
+pub use self::tim6 as basic_timer;
+pub use self::usart1 as usart;
+
+pub fn describe(address: u32) -> Option<&'static crate::register::RegisterDescription> {
+    REGISTER_DESCRIPTIONS
+        .iter()
+        .find(|description| description.address == address)
+}
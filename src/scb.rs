@@ -0,0 +1,106 @@
+use crate::asm::no_operation;
+use crate::memory::{data_barrier, instruction_barrier};
+use crate::memory_mapped_io::MemoryMappedIo;
+use crate::register_bus::RegisterBus;
+use crate::registers::scb::{
+    aircr, icsr, shcsr, vtor, AIRCR, BFAR, CFSR, HFSR, ICSR, MMFAR, SHCSR, VTOR,
+};
+
+// Writes to AIRCR are ignored unless VECTKEY holds this value
+const AIRCR_KEY: u32 = 0x05FA;
+
+#[derive(Clone, Copy, Debug)]
+pub struct FaultStatus {
+    pub configurable: u32,
+    pub hard: u32,
+    pub memory_management_address: u32,
+    pub bus_fault_address: u32,
+}
+
+pub struct ScbConf<B: RegisterBus = MemoryMappedIo> {
+    reg: B,
+}
+
+impl ScbConf {
+    pub const fn new(base: u32) -> Self {
+        ScbConf::with_bus(MemoryMappedIo::new(base))
+    }
+}
+
+impl<B: RegisterBus> ScbConf<B> {
+    pub const fn with_bus(reg: B) -> Self {
+        ScbConf { reg }
+    }
+
+    /*
+       PM0214 p231
+       Without these bits MemManage, BusFault and UsageFault escalate to HardFault.
+    */
+    pub fn enable_fault_handlers(&self) {
+        self.reg.modify_register(SHCSR, |current| {
+            current
+                | shcsr::MEMFAULTENA.mask()
+                | shcsr::BUSFAULTENA.mask()
+                | shcsr::USGFAULTENA.mask()
+        });
+    }
+
+    // MMFAR and BFAR only hold the address while MMARVALID/BFARVALID are set in CFSR
+    pub fn fault_status(&self) -> FaultStatus {
+        FaultStatus {
+            configurable: self.reg.read_register(CFSR),
+            hard: self.reg.read_register(HFSR),
+            memory_management_address: self.reg.read_register(MMFAR),
+            bus_fault_address: self.reg.read_register(BFAR),
+        }
+    }
+
+    /*
+       PM0214 p225
+       TBLOFF drops the low 9 bits, the table needs 107 entries and so a 512 byte alignment.
+    */
+    pub fn set_vector_table_offset(&self, address: u32) {
+        assert!(
+            address & !vtor::TBLOFF.mask() == 0,
+            "vector table must be 512 byte aligned"
+        );
+        self.reg.write_register(VTOR, address);
+        unsafe {
+            data_barrier();
+            instruction_barrier();
+        }
+    }
+
+    /*
+       PM0214 p226
+       PendSV runs once no other exception is active, writing zero to the other bits does nothing.
+    */
+    pub fn set_pend_sv(&self) {
+        self.reg.write_register(ICSR, icsr::PENDSVSET.value(1));
+    }
+
+    /*
+       PM0214 p229
+       SYSRESETREQ resets the whole MCU, the priority grouping is written back unchanged.
+    */
+    pub fn system_reset(&self) -> ! {
+        unsafe {
+            data_barrier();
+        }
+        let priority_group = self.reg.read_field(AIRCR, aircr::PRIGROUP);
+        self.reg.write_register(
+            AIRCR,
+            aircr::VECTKEYSTAT.value(AIRCR_KEY)
+                | aircr::PRIGROUP.value(priority_group)
+                | aircr::SYSRESETREQ.value(1),
+        );
+        unsafe {
+            data_barrier();
+        }
+        loop {
+            unsafe {
+                no_operation();
+            }
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+const EMPTY: u8 = 0;
+const INITIALIZING: u8 = 1;
+const READY: u8 = 2;
+
+/*
+   Hands a peripheral over from the reset handler to interrupt handlers. The value is moved in
+   exactly once, afterwards every handler gets the same shared reference. Handlers running
+   before the value was moved in get None and should return without touching the hardware.
+*/
+pub struct Shared<T> {
+    state: AtomicU8,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send + Sync> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+    pub const fn new() -> Self {
+        Shared {
+            state: AtomicU8::new(EMPTY),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    // Returns None and drops the value when it was already initialized
+    pub fn init(&'static self, value: T) -> Option<&'static T> {
+        if self
+            .state
+            .compare_exchange(EMPTY, INITIALIZING, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return None;
+        }
+        let value = unsafe { (*self.value.get()).write(value) };
+        self.state.store(READY, Ordering::Release);
+        Some(value)
+    }
+
+    pub fn get(&'static self) -> Option<&'static T> {
+        if self.state.load(Ordering::Acquire) == READY {
+            unsafe { Some((*self.value.get()).assume_init_ref()) }
+        } else {
+            None
+        }
+    }
+}
+
+impl<T> Default for Shared<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
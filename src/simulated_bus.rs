@@ -0,0 +1,90 @@
+use crate::register::Register;
+use crate::register_bus::RegisterBus;
+use core::cell::Cell;
+
+/*
+   In-memory register file used to run drivers on the host.
+
+   Writes are stored as-is unless a hardware model is attached. The model receives the
+   register word index, its previous content and the value written by the driver and returns
+   what the register reads back afterwards, e.g. setting HSERDY once HSEON is written so
+   wait loops terminate. A register file model sees all registers instead, for status bits
+   that live in another register than the bit that sets them, e.g. PWR_CSR ODRDY following
+   PWR_CR ODEN. It stores the written value itself.
+*/
+pub type HardwareModel = fn(index: usize, previous: u32, written: u32) -> u32;
+pub type RegisterFileModel = fn(registers: &[Cell<u32>], index: usize, written: u32);
+
+#[derive(Clone, Copy)]
+enum Hardware {
+    Register(HardwareModel),
+    RegisterFile(RegisterFileModel),
+}
+
+pub struct SimulatedBus<const SIZE: usize> {
+    base: u32,
+    registers: [Cell<u32>; SIZE],
+    hardware: Option<Hardware>,
+}
+
+impl<const SIZE: usize> SimulatedBus<SIZE> {
+    pub const fn new(base: u32) -> Self {
+        SimulatedBus {
+            base,
+            registers: [const { Cell::new(0) }; SIZE],
+            hardware: None,
+        }
+    }
+
+    pub const fn with_hardware(base: u32, hardware: HardwareModel) -> Self {
+        SimulatedBus {
+            base,
+            registers: [const { Cell::new(0) }; SIZE],
+            hardware: Some(Hardware::Register(hardware)),
+        }
+    }
+
+    pub const fn with_register_file_hardware(base: u32, hardware: RegisterFileModel) -> Self {
+        SimulatedBus {
+            base,
+            registers: [const { Cell::new(0) }; SIZE],
+            hardware: Some(Hardware::RegisterFile(hardware)),
+        }
+    }
+
+    pub fn load_reset_values(&self, registers: &[Register]) {
+        registers
+            .iter()
+            .for_each(|&register| self.preset(register, register.reset_value()));
+    }
+
+    // Sets a register the way the hardware would, bypassing the hardware model
+    pub fn preset(&self, register: Register, value: u32) {
+        self.registers[register.index()].set(value);
+    }
+
+    pub fn peek(&self, register: Register) -> u32 {
+        self.registers[register.index()].get()
+    }
+}
+
+impl<const SIZE: usize> RegisterBus for SimulatedBus<SIZE> {
+    fn base_address(&self) -> u32 {
+        self.base
+    }
+
+    fn read(&self, index: usize) -> u32 {
+        self.registers[index].get()
+    }
+
+    fn write(&self, value: u32, index: usize) {
+        match self.hardware {
+            Some(Hardware::Register(model)) => {
+                let stored = model(index, self.registers[index].get(), value);
+                self.registers[index].set(stored);
+            }
+            Some(Hardware::RegisterFile(model)) => model(&self.registers, index, value),
+            None => self.registers[index].set(value),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use crate::flash::{flash_sector, FlashError, FlashMemory, FlashSector};
+
+/*
+   Flash memory on the host for running the update flow, backed by a slice that stands in for
+   the part of the address space starting at base. Uses the sector layout of the MCU and is
+   stricter than the hardware: programming a byte that is not erased fails with NotErased.
+*/
+pub struct SimulatedFlash<'a> {
+    base: u32,
+    memory: &'a mut [u8],
+    erased_sectors: usize,
+}
+
+impl<'a> SimulatedFlash<'a> {
+    pub fn new(base: u32, memory: &'a mut [u8]) -> Self {
+        SimulatedFlash {
+            base,
+            memory,
+            erased_sectors: 0,
+        }
+    }
+
+    pub fn erased_sectors(&self) -> usize {
+        self.erased_sectors
+    }
+
+    pub fn contents(&self, address: u32, length: usize) -> &[u8] {
+        let start = self
+            .offset(address, length)
+            .expect("outside the simulated flash");
+        &self.memory[start..start + length]
+    }
+
+    fn offset(&self, address: u32, length: usize) -> Option<usize> {
+        let start = address.checked_sub(self.base)? as usize;
+        (start + length <= self.memory.len()).then_some(start)
+    }
+}
+
+impl FlashMemory for SimulatedFlash<'_> {
+    fn erase_sector(&mut self, address: u32) -> Result<FlashSector, FlashError> {
+        let sector = flash_sector(address).ok_or(FlashError::OutOfRange)?;
+        let start = self
+            .offset(sector.address, sector.size as usize)
+            .ok_or(FlashError::OutOfRange)?;
+        self.memory[start..start + sector.size as usize].fill(0xFF);
+        self.erased_sectors += 1;
+        Ok(sector)
+    }
+
+    fn program(&mut self, address: u32, data: &[u8]) -> Result<(), FlashError> {
+        if !address.is_multiple_of(4) || !data.len().is_multiple_of(4) {
+            return Err(FlashError::ProgrammingAlignment);
+        }
+        let start = self
+            .offset(address, data.len())
+            .ok_or(FlashError::OutOfRange)?;
+        let target = &mut self.memory[start..start + data.len()];
+        if target.iter().any(|&byte| byte != 0xFF) {
+            return Err(FlashError::NotErased);
+        }
+        target.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn read(&self, address: u32, buffer: &mut [u8]) {
+        buffer.copy_from_slice(self.contents(address, buffer.len()));
+    }
+}
@@ -0,0 +1,74 @@
+use crate::mpu::{AccessPermission, MpuConf, MpuRegion};
+use crate::register_bus::RegisterBus;
+use core::arch::asm;
+use core::ops::Range;
+use core::ptr::{addr_of, read_volatile, write_volatile};
+
+/*
+   Main stack bounds and usage.
+
+   The stack is the top _stack_size bytes of RAM (16 KB unless the application defines
+   _stack_size). The reset handler paints the free part of it with STACK_PAINT before anything
+   else runs, stack_high_watermark finds the deepest word overwritten since then.
+
+   enable_stack_guard makes the lowest 128 bytes of the stack read-only through the MPU, an
+   overflow then raises MemManage instead of overwriting the RAM sections below the stack.
+   An overflow escalating to HardFault stacks with the MPU off, the guard is larger than the
+   104 bytes of an exception frame with FP state so that frame still lands inside it.
+   The fault trampolines move SP back to the top of the stack when it is below the guard, so
+   the report in fault.rs can still be printed.
+*/
+const STACK_PAINT: u32 = 0xDEAD_BEEF;
+// MPU regions are aligned to their size, the linker script keeps _stack_start aligned to 128
+const STACK_GUARD_REGION: u32 = 0;
+
+extern "C" {
+    static _stack_start: u32;
+    static _stack_guard_end: u32;
+    static _stack_end: u32;
+}
+
+pub fn stack_bounds() -> Range<u32> {
+    addr_of!(_stack_start) as u32..addr_of!(_stack_end) as u32
+}
+
+pub fn stack_guard_bounds() -> Range<u32> {
+    addr_of!(_stack_start) as u32..addr_of!(_stack_guard_end) as u32
+}
+
+pub fn stack_size() -> usize {
+    stack_bounds().len()
+}
+
+// Largest number of bytes the stack ever held since reset
+pub fn stack_high_watermark() -> usize {
+    let bounds = stack_bounds();
+    let mut address = bounds.start;
+    while address < bounds.end && unsafe { read_volatile(address as *const u32) } == STACK_PAINT {
+        address += 4;
+    }
+    (bounds.end - address) as usize
+}
+
+pub fn enable_stack_guard<B: RegisterBus>(mpu: &MpuConf<B>) {
+    let guard = stack_guard_bounds();
+    mpu.set_region(MpuRegion {
+        number: STACK_GUARD_REGION,
+        base_address: guard.start,
+        size: guard.len() as u32,
+        access_permission: AccessPermission::ReadOnly,
+        execute_never: true,
+    });
+    mpu.enable();
+}
+
+// Called first thing by the reset handler, everything below the current SP is free
+pub(crate) unsafe fn paint_stack() {
+    let stack_pointer: u32;
+    asm!("mov {}, sp", out(reg) stack_pointer, options(nomem, nostack, preserves_flags));
+    let mut address = addr_of!(_stack_start) as *mut u32;
+    while (address as u32) < stack_pointer {
+        write_volatile(address, STACK_PAINT);
+        address = address.add(1);
+    }
+}
@@ -0,0 +1,127 @@
+use crate::fpu::FpuConf;
+use crate::registers::{fpu_cpacr, scb};
+use crate::scb::ScbConf;
+use crate::stack::paint_stack;
+use core::ptr::{addr_of, addr_of_mut, read_volatile, write_volatile};
+
+/*
+   Runtime startup, RESET_HANDLER is the second entry of the vector table.
+
+   1. CP10/CP11 are enabled before anything else. Code is compiled for thumbv7em-none-eabihf,
+      a floating point instruction in any of the later steps would otherwise be a UsageFault.
+   2. The free part of the stack is painted to measure its usage later, see stack.rs.
+      __pre_init runs next, .data and .bss are not initialized yet so it must not touch
+      any static. Meant for things like waking up external memory.
+   3. .data is copied from its load address in flash and .bss is zeroed, then the same for
+      .ccmram and .ccmram_bss. The CCM clock is enabled out of reset (RCC_AHB1ENR CCMDATARAMEN).
+      With the heap feature the allocator takes the RAM between the sections and the stack.
+   4. MemManage, BusFault and UsageFault are enabled so faults get reported by their own
+      handler instead of escalating to HardFault, see fault.rs.
+   5. __post_init runs with statics in place but before the application, e.g. to relocate
+      the vector table.
+   6. The application entry defined with entry! is called, it never returns.
+
+   The hooks default to an empty function in the linker script, applications override them
+   with pre_init! and post_init!.
+*/
+extern "C" {
+    static mut _data_start: u32;
+    static mut _data_end: u32;
+    static _data_load: u32;
+    static mut _bss_start: u32;
+    static mut _bss_end: u32;
+    static mut _ccmram_data_start: u32;
+    static mut _ccmram_data_end: u32;
+    static _ccmram_data_load: u32;
+    static mut _ccmram_bss_start: u32;
+    static mut _ccmram_bss_end: u32;
+
+    fn __pre_init();
+    fn __post_init();
+    fn __avocado_main() -> !;
+}
+
+#[no_mangle]
+#[link_section = ".vector_table.reset"]
+pub static RESET_HANDLER: unsafe extern "C" fn() -> ! = reset;
+
+#[no_mangle]
+unsafe extern "C" fn reset() -> ! {
+    FpuConf::new(fpu_cpacr::BASE_ADDRESS).enable_full_access();
+    paint_stack();
+    __pre_init();
+    copy_words(
+        addr_of!(_data_load),
+        addr_of_mut!(_data_start),
+        addr_of_mut!(_data_end),
+    );
+    zero_words(addr_of_mut!(_bss_start), addr_of_mut!(_bss_end));
+    copy_words(
+        addr_of!(_ccmram_data_load),
+        addr_of_mut!(_ccmram_data_start),
+        addr_of_mut!(_ccmram_data_end),
+    );
+    zero_words(
+        addr_of_mut!(_ccmram_bss_start),
+        addr_of_mut!(_ccmram_bss_end),
+    );
+    #[cfg(feature = "heap")]
+    crate::heap::init();
+    ScbConf::new(scb::BASE_ADDRESS).enable_fault_handlers();
+    __post_init();
+    __avocado_main()
+}
+
+#[no_mangle]
+pub extern "C" fn __default_init_hook() {}
+
+unsafe fn copy_words(mut source: *const u32, mut destination: *mut u32, end: *mut u32) {
+    while destination < end {
+        write_volatile(destination, read_volatile(source));
+        destination = destination.add(1);
+        source = source.add(1);
+    }
+}
+
+unsafe fn zero_words(mut address: *mut u32, end: *mut u32) {
+    while address < end {
+        write_volatile(address, 0);
+        address = address.add(1);
+    }
+}
+
+// Defines the application entry called by the reset handler, fn() -> !
+#[macro_export]
+macro_rules! entry {
+    ($path:path) => {
+        #[export_name = "__avocado_main"]
+        unsafe extern "C" fn __avocado_main() -> ! {
+            let entry: fn() -> ! = $path;
+            entry()
+        }
+    };
+}
+
+// Runs the function before .data and .bss are initialized, unsafe fn()
+#[macro_export]
+macro_rules! pre_init {
+    ($path:path) => {
+        #[export_name = "__pre_init"]
+        unsafe extern "C" fn __pre_init() {
+            let hook: unsafe fn() = $path;
+            hook()
+        }
+    };
+}
+
+// Runs the function after .data and .bss are initialized, fn()
+#[macro_export]
+macro_rules! post_init {
+    ($path:path) => {
+        #[export_name = "__post_init"]
+        unsafe extern "C" fn __post_init() {
+            let hook: fn() = $path;
+            hook()
+        }
+    };
+}
@@ -1,41 +1,148 @@
-use crate::dma::DmaConf;
+use crate::dma::{DmaConf, DmaStreams};
 use crate::exti::ExtiConf;
 use crate::flash::FlashConf;
 use crate::gpio::GpioConf;
 use crate::independent_watchdog::IndependentWatchdogConf;
+use crate::mpu::MpuConf;
 use crate::nvic::NvicConf;
 use crate::pwr::PwrConf;
 use crate::rcc::RccConf;
+use crate::registers::{
+    dma1, dma2, exti, flash, gpioa, gpiob, gpioc, gpiod, gpioe, gpiof, gpiog, gpioh, gpioi, gpioj,
+    gpiok, iwdg, mpu, nvic, pwr, rcc, scb, stk, syscfg, tim6, tim7, uart4, uart5, uart7, uart8,
+    usart1, usart2, usart3, usart6,
+};
+use crate::scb::ScbConf;
 use crate::syscfg::SysConf;
+use crate::systick::SysTickConf;
 use crate::timer::BasicTimerConf;
-use crate::usart::{UsartConf, UsartDmaDriver, UsartSingleByteDriver};
-
-pub static PORT_B: GpioConf = GpioConf::new(0x40020400);
-pub static PORT_C: GpioConf = GpioConf::new(0x40020800);
-pub static PORT_D: GpioConf = GpioConf::new(0x40020C00);
-pub static NVIC: NvicConf = NvicConf::new(0xE000E100);
-pub static RCC: RccConf = RccConf::new(0x40023800);
-pub static TIM6: BasicTimerConf = BasicTimerConf::new(0x40001000);
-pub static TIM7: BasicTimerConf = BasicTimerConf::new(0x40001400);
-pub static EXTI: ExtiConf = ExtiConf::new(0x40013C00);
-pub static SYSCFG: SysConf = SysConf::new(0x40013800);
-pub static USART3: UsartConf = UsartConf::new(0x40004800);
-pub static USART3_SINGLE_BYTE_DRIVER: UsartSingleByteDriver = UsartSingleByteDriver::new(&USART3);
-pub static IWDG: IndependentWatchdogConf = IndependentWatchdogConf::new(0x40003000);
-pub static DMA1: DmaConf = DmaConf::new(0x40026000);
-pub static mut USART3_DMA1_DRIVER: UsartDmaDriver<1024> = UsartDmaDriver::new(&USART3, &DMA1, 3, 4);
-pub static FLASH: FlashConf = FlashConf::new(0x40023C00);
-pub static PWR: PwrConf = PwrConf::new(0x40007000);
+use crate::usart::UsartConf;
+use core::sync::atomic::{AtomicBool, Ordering};
 
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+/*
+   Every peripheral of the MCU exists exactly once, Peripherals::take() hands all of them out
+   on the first call and returns None afterwards. Drivers consume the handles they are built
+   from, so a peripheral used by a driver can not be reconfigured behind its back.
+*/
+pub struct Peripherals {
+    pub port_a: GpioConf,
+    pub port_b: GpioConf,
+    pub port_c: GpioConf,
+    pub port_d: GpioConf,
+    pub port_e: GpioConf,
+    pub port_f: GpioConf,
+    pub port_g: GpioConf,
+    pub port_h: GpioConf,
+    pub port_i: GpioConf,
+    pub port_j: GpioConf,
+    pub port_k: GpioConf,
+    pub nvic: NvicConf,
+    pub mpu: MpuConf,
+    pub rcc: RccConf,
+    pub scb: ScbConf,
+    pub systick: SysTickConf,
+    pub tim6: BasicTimerConf,
+    pub tim7: BasicTimerConf,
+    pub exti: ExtiConf,
+    pub syscfg: SysConf,
+    pub usart1: UsartConf,
+    pub usart2: UsartConf,
+    pub usart3: UsartConf,
+    pub uart4: UsartConf,
+    pub uart5: UsartConf,
+    pub usart6: UsartConf,
+    pub uart7: UsartConf,
+    pub uart8: UsartConf,
+    pub iwdg: IndependentWatchdogConf,
+    pub dma1: DmaStreams,
+    pub dma2: DmaStreams,
+    pub flash: FlashConf,
+    pub pwr: PwrConf,
+}
+
+impl Peripherals {
+    pub fn take() -> Option<Peripherals> {
+        if TAKEN.swap(true, Ordering::AcqRel) {
+            None
+        } else {
+            unsafe { Some(Peripherals::steal()) }
+        }
+    }
+
+    /// Creates the handles regardless of whether they were already taken.
+    ///
+    /// # Safety
+    /// The caller shares the peripherals with their owner. Only meant for code that never
+    /// returns to the owner, like fault and panic handlers.
+    pub unsafe fn steal() -> Peripherals {
+        TAKEN.store(true, Ordering::Release);
+        Peripherals {
+            port_a: GpioConf::new(gpioa::BASE_ADDRESS),
+            port_b: GpioConf::new(gpiob::BASE_ADDRESS),
+            port_c: GpioConf::new(gpioc::BASE_ADDRESS),
+            port_d: GpioConf::new(gpiod::BASE_ADDRESS),
+            port_e: GpioConf::new(gpioe::BASE_ADDRESS),
+            port_f: GpioConf::new(gpiof::BASE_ADDRESS),
+            port_g: GpioConf::new(gpiog::BASE_ADDRESS),
+            port_h: GpioConf::new(gpioh::BASE_ADDRESS),
+            port_i: GpioConf::new(gpioi::BASE_ADDRESS),
+            port_j: GpioConf::new(gpioj::BASE_ADDRESS),
+            port_k: GpioConf::new(gpiok::BASE_ADDRESS),
+            nvic: NvicConf::new(nvic::BASE_ADDRESS),
+            mpu: MpuConf::new(mpu::BASE_ADDRESS),
+            rcc: RccConf::new(rcc::BASE_ADDRESS),
+            scb: ScbConf::new(scb::BASE_ADDRESS),
+            systick: SysTickConf::new(stk::BASE_ADDRESS),
+            tim6: BasicTimerConf::new(tim6::BASE_ADDRESS),
+            tim7: BasicTimerConf::new(tim7::BASE_ADDRESS),
+            exti: ExtiConf::new(exti::BASE_ADDRESS),
+            syscfg: SysConf::new(syscfg::BASE_ADDRESS),
+            usart1: UsartConf::new(usart1::BASE_ADDRESS),
+            usart2: UsartConf::new(usart2::BASE_ADDRESS),
+            usart3: UsartConf::new(usart3::BASE_ADDRESS),
+            uart4: UsartConf::new(uart4::BASE_ADDRESS),
+            uart5: UsartConf::new(uart5::BASE_ADDRESS),
+            usart6: UsartConf::new(usart6::BASE_ADDRESS),
+            uart7: UsartConf::new(uart7::BASE_ADDRESS),
+            uart8: UsartConf::new(uart8::BASE_ADDRESS),
+            iwdg: IndependentWatchdogConf::new(iwdg::BASE_ADDRESS),
+            dma1: DmaConf::new(dma1::BASE_ADDRESS).split(),
+            dma2: DmaConf::new(dma2::BASE_ADDRESS).split(),
+            flash: FlashConf::new(flash::BASE_ADDRESS),
+            pwr: PwrConf::new(pwr::BASE_ADDRESS),
+        }
+    }
+}
+
+// Cortex-M4 system exceptions by exception number, PM0214 p38
 #[repr(u32)]
-pub enum Interrupt {
-    Exti15_10 = 40,
-    Tim6Dac = 54,
-    Tim7 = 55,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exception {
+    Reset = 1,
+    NonMaskableInt = 2,
+    HardFault = 3,
+    MemoryManagement = 4,
+    BusFault = 5,
+    UsageFault = 6,
+    SVCall = 11,
+    DebugMonitor = 12,
+    PendSV = 14,
+    SysTick = 15,
 }
 
+// STM32F439 interrupts by position in the vector table, generated from the SVD
+include!(concat!(env!("OUT_DIR"), "/interrupts.rs"));
+
 impl From<Interrupt> for u32 {
     fn from(value: Interrupt) -> Self {
         value as u32
     }
 }
+
+impl From<Exception> for u32 {
+    fn from(value: Exception) -> Self {
+        value as u32
+    }
+}
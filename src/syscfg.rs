@@ -1,7 +1,9 @@
 use crate::memory_mapped_io::MemoryMappedIo;
+use crate::register_bus::RegisterBus;
+use crate::registers::syscfg::{exticr1, EXTICR1};
 
-pub struct SysConf {
-    reg: MemoryMappedIo,
+pub struct SysConf<B: RegisterBus = MemoryMappedIo> {
+    reg: B,
 }
 
 #[repr(u32)]
@@ -17,9 +19,13 @@ impl From<ExternalInterruptSourcePort> for u32 {
 
 impl SysConf {
     pub const fn new(base: u32) -> Self {
-        SysConf {
-            reg: MemoryMappedIo::new(base),
-        }
+        SysConf::with_bus(MemoryMappedIo::new(base))
+    }
+}
+
+impl<B: RegisterBus> SysConf<B> {
+    pub const fn with_bus(reg: B) -> Self {
+        SysConf { reg }
     }
 
     pub fn set_external_interrupt_source_port(
@@ -27,12 +33,9 @@ impl SysConf {
         exti_number: u32,
         port: ExternalInterruptSourcePort,
     ) {
-        let register_number = (exti_number >> 2) as usize;
-        let register_offset: u32 = exti_number % 4;
-        let mut current_value: u32 = self.reg.read(2 + register_number);
-        current_value &= !(0b1111 << (register_offset << 2));
-        let port_value: u32 = port.into();
-        current_value |= port_value << (register_offset << 2);
-        self.reg.write(current_value, 2 + register_number);
+        // EXTICR1..EXTICR4 hold four lines each
+        let register = EXTICR1.nth((exti_number >> 2) as usize, 0x04);
+        self.reg
+            .write_field(register, exticr1::EXTI0.nth(exti_number % 4), port.into());
     }
 }
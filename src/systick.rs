@@ -0,0 +1,43 @@
+use crate::memory_mapped_io::MemoryMappedIo;
+use crate::register_bus::RegisterBus;
+use crate::registers::stk::{ctrl, load, CTRL, LOAD, VAL};
+
+pub struct SysTickConf<B: RegisterBus = MemoryMappedIo> {
+    reg: B,
+}
+
+impl SysTickConf {
+    pub const fn new(base: u32) -> Self {
+        SysTickConf::with_bus(MemoryMappedIo::new(base))
+    }
+}
+
+impl<B: RegisterBus> SysTickConf<B> {
+    pub const fn with_bus(reg: B) -> Self {
+        SysTickConf { reg }
+    }
+
+    /*
+       PM0214 p247
+       Counts down from RELOAD on the processor clock and sets COUNTFLAG each millisecond,
+       without raising the SysTick exception. Writing VAL clears the counter and COUNTFLAG.
+    */
+    pub fn start_millisecond_ticks(&self, processor_clock: u32) {
+        self.reg
+            .write_register(LOAD, load::RELOAD.value(processor_clock / 1000 - 1));
+        self.reg.write_register(VAL, 0);
+        self.reg
+            .write_register(CTRL, ctrl::CLKSOURCE.mask() | ctrl::ENABLE.mask());
+    }
+
+    // COUNTFLAG is cleared by the read, each tick is reported once
+    pub fn has_ticked(&self) -> bool {
+        self.reg.is_field_set(CTRL, ctrl::COUNTFLAG)
+    }
+
+    pub fn disable(&self) {
+        self.reg.reset_register(CTRL);
+        self.reg.reset_register(LOAD);
+        self.reg.write_register(VAL, 0);
+    }
+}
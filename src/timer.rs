@@ -1,41 +1,95 @@
+use crate::clock::{ClockListener, Clocks};
 use crate::memory::store_barrier;
 use crate::memory_mapped_io::MemoryMappedIo;
+use crate::rcc::Peripheral;
+use crate::register_bus::RegisterBus;
+use crate::registers::basic_timer::{arr, cr1, dier, psc, sr, ARR, CR1, DIER, PSC, SR};
+use crate::shared::Shared;
 
-pub struct BasicTimerConf {
-    reg: MemoryMappedIo,
+pub struct BasicTimerConf<B: RegisterBus = MemoryMappedIo> {
+    reg: B,
 }
 
 impl BasicTimerConf {
     pub const fn new(base: u32) -> Self {
-        BasicTimerConf {
-            reg: MemoryMappedIo::new(base),
-        }
+        BasicTimerConf::with_bus(MemoryMappedIo::new(base))
+    }
+}
+
+impl<B: RegisterBus> BasicTimerConf<B> {
+    pub const fn with_bus(reg: B) -> Self {
+        BasicTimerConf { reg }
     }
 
     pub fn clear_status_flag(&self) {
         unsafe {
-            self.reg.write(0b0, 4);
+            self.reg.write_zero_to_clear(SR, sr::UIF);
             store_barrier();
         }
     }
 
     pub fn set_prescaler(&self, value: u32) {
-        self.reg.write(value, 10);
+        self.reg.write_register(PSC, psc::PSC.value(value));
     }
 
     pub fn set_auto_reload(&self, value: u32) {
-        self.reg.write(value, 11);
+        self.reg.write_register(ARR, arr::ARR.value(value));
     }
 
     pub fn update_interrupt_enable(&self) {
-        let mut current_value: u32 = self.reg.read(3);
-        current_value |= 0b1;
-        self.reg.write(current_value, 3);
+        self.reg.write_field(DIER, dier::UIE, 1);
     }
 
     pub fn enable_timer(&self) {
-        let mut current_value: u32 = self.reg.read(0);
-        current_value |= 0b1;
-        self.reg.write(current_value, 0);
+        self.reg.write_field(CR1, cr1::CEN, 1);
+    }
+
+    /*
+       Smallest prescaler that fits the period into the 16 bit counter, periods too long for
+       the timer clock end up at the longest one. The prescaler is buffered, a running timer
+       finishes the current period with the old one.
+    */
+    pub fn set_period(&self, timer_clock: u32, period_us: u32) {
+        let cycles = (timer_clock as u64 * period_us as u64 / 1_000_000).max(2);
+        let prescaler = ((cycles - 1) >> 16).min(0xFFFF);
+        let auto_reload = (cycles / (prescaler + 1) - 1).clamp(1, 0xFFFF);
+        self.set_prescaler(prescaler as u32);
+        self.set_auto_reload(auto_reload as u32);
+    }
+}
+
+/*
+   Keeps a basic timer at the same update period when the bus clock changes, register it with
+   clock::register_clock_listener. It borrows the timer from the Shared the interrupt handler
+   uses and does nothing until that is initialized. The IWDG counts LSI and needs nothing, but
+   the timer feeding it does: a feed timer slowed down by a lower clock would let the watchdog
+   reset the chip.
+*/
+pub struct BasicTimerPeriod<B: RegisterBus + 'static = MemoryMappedIo> {
+    timer: &'static Shared<BasicTimerConf<B>>,
+    peripheral: Peripheral,
+    period_us: u32,
+}
+
+impl<B: RegisterBus> BasicTimerPeriod<B> {
+    pub const fn new(
+        timer: &'static Shared<BasicTimerConf<B>>,
+        peripheral: Peripheral,
+        period_us: u32,
+    ) -> Self {
+        BasicTimerPeriod {
+            timer,
+            peripheral,
+            period_us,
+        }
+    }
+}
+
+impl<B: RegisterBus> ClockListener for BasicTimerPeriod<B> {
+    fn clocks_changed(&self, clocks: &Clocks) {
+        if let Some(timer) = self.timer.get() {
+            let timer_clock = clocks.timer_clock(self.peripheral.bus());
+            timer.set_period(timer_clock, self.period_us);
+        }
     }
 }
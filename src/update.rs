@@ -0,0 +1,162 @@
+use crate::boot::{APPLICATION_ADDRESS, STAGING_ADDRESS, STAGING_SIZE, UPDATE_RECORD_ADDRESS};
+use crate::crc::Crc32;
+use crate::flash::{FlashError, FlashMemory};
+use crate::ymodem::{self, SerialPort, YmodemError};
+
+/*
+   Firmware update through the staging area in bank 2.
+
+   An update image is the application binary followed by the CRC-32 of the binary, little
+   endian (just image). receive_update writes it to the staging area over YMODEM, checks the
+   CRC on what was read back from flash and only then writes the UpdateRecord. The running
+   application stays untouched until the next reset, where the bootloader finds the record
+   and apply_update copies the staged binary over the application. The record is erased last,
+   an interrupted copy is started again on the following reset.
+*/
+const UPDATE_MAGIC: u32 = 0x5550_4454;
+const CRC_LENGTH: u32 = 4;
+const COPY_CHUNK: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UpdateError {
+    Transfer(YmodemError<FlashError>),
+    Flash(FlashError),
+    ImageTooSmall,
+    CrcMismatch,
+}
+
+impl From<FlashError> for UpdateError {
+    fn from(value: FlashError) -> Self {
+        UpdateError::Flash(value)
+    }
+}
+
+// The magic is the last word programmed, a record cut short by a reset is not valid
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UpdateRecord {
+    pub size: u32,
+    pub crc: u32,
+}
+
+impl UpdateRecord {
+    fn to_bytes(self) -> [u8; 12] {
+        let mut bytes = [0; 12];
+        bytes[0..4].copy_from_slice(&self.size.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.crc.to_le_bytes());
+        bytes[8..12].copy_from_slice(&UPDATE_MAGIC.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; 12]) -> Option<UpdateRecord> {
+        let word = |index: usize| {
+            u32::from_le_bytes([
+                bytes[index],
+                bytes[index + 1],
+                bytes[index + 2],
+                bytes[index + 3],
+            ])
+        };
+        let record = UpdateRecord {
+            size: word(0),
+            crc: word(4),
+        };
+        (word(8) == UPDATE_MAGIC && record.size <= STAGING_SIZE - CRC_LENGTH).then_some(record)
+    }
+}
+
+pub fn pending_update<F: FlashMemory>(flash: &F) -> Option<UpdateRecord> {
+    let mut bytes = [0; 12];
+    flash.read(UPDATE_RECORD_ADDRESS, &mut bytes);
+    UpdateRecord::from_bytes(&bytes)
+}
+
+/*
+   The record sector is erased when the first block arrives, a pending record no longer matches
+   the staging area once that is written. A transfer that times out erases nothing, the
+   bootloader retries it for as long as nobody sends. Staging sectors are erased right before
+   their first block is written, the sender waits for the ACK meanwhile.
+*/
+pub fn receive_update<P: SerialPort, F: FlashMemory>(
+    port: &mut P,
+    flash: &mut F,
+) -> Result<UpdateRecord, UpdateError> {
+    let mut erased_end = STAGING_ADDRESS;
+    let size = ymodem::receive(port, STAGING_SIZE, |offset, data| {
+        let address = STAGING_ADDRESS + offset;
+        if erased_end == STAGING_ADDRESS {
+            flash.erase_sector(UPDATE_RECORD_ADDRESS)?;
+        }
+        while erased_end < address + data.len() as u32 {
+            let sector = flash.erase_sector(erased_end)?;
+            erased_end = sector.address + sector.size;
+        }
+        flash.program(address, data)
+    })
+    .map_err(UpdateError::Transfer)?;
+    if size <= CRC_LENGTH {
+        return Err(UpdateError::ImageTooSmall);
+    }
+
+    let record = UpdateRecord {
+        size: size - CRC_LENGTH,
+        crc: read_word(flash, STAGING_ADDRESS + size - CRC_LENGTH),
+    };
+    if flash_crc(flash, STAGING_ADDRESS, record.size) != record.crc {
+        return Err(UpdateError::CrcMismatch);
+    }
+    flash.program(UPDATE_RECORD_ADDRESS, &record.to_bytes())?;
+    Ok(record)
+}
+
+/*
+   A staged image that no longer matches its record is dropped together with the record. A
+   copy that fails its check keeps the record, the next reset tries again.
+*/
+pub fn apply_update<F: FlashMemory>(
+    flash: &mut F,
+    record: &UpdateRecord,
+) -> Result<(), UpdateError> {
+    if flash_crc(flash, STAGING_ADDRESS, record.size) != record.crc {
+        flash.erase_sector(UPDATE_RECORD_ADDRESS)?;
+        return Err(UpdateError::CrcMismatch);
+    }
+    let mut erased_end = APPLICATION_ADDRESS;
+    while erased_end < APPLICATION_ADDRESS + record.size {
+        let sector = flash.erase_sector(erased_end)?;
+        erased_end = sector.address + sector.size;
+    }
+    // Whole words, the bytes after the binary are the CRC and the block padding
+    let length = record.size.next_multiple_of(4);
+    let mut buffer = [0; COPY_CHUNK];
+    let mut offset = 0;
+    while offset < length {
+        let chunk = &mut buffer[..COPY_CHUNK.min((length - offset) as usize)];
+        flash.read(STAGING_ADDRESS + offset, chunk);
+        flash.program(APPLICATION_ADDRESS + offset, chunk)?;
+        offset += chunk.len() as u32;
+    }
+    if flash_crc(flash, APPLICATION_ADDRESS, record.size) != record.crc {
+        return Err(UpdateError::CrcMismatch);
+    }
+    flash.erase_sector(UPDATE_RECORD_ADDRESS)?;
+    Ok(())
+}
+
+fn read_word<F: FlashMemory>(flash: &F, address: u32) -> u32 {
+    let mut bytes = [0; 4];
+    flash.read(address, &mut bytes);
+    u32::from_le_bytes(bytes)
+}
+
+fn flash_crc<F: FlashMemory>(flash: &F, address: u32, length: u32) -> u32 {
+    let mut crc = Crc32::new();
+    let mut buffer = [0; COPY_CHUNK];
+    let mut offset = 0;
+    while offset < length {
+        let chunk = &mut buffer[..COPY_CHUNK.min((length - offset) as usize)];
+        flash.read(address + offset, chunk);
+        crc.update(chunk);
+        offset += chunk.len() as u32;
+    }
+    crc.finish()
+}
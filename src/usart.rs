@@ -1,9 +1,18 @@
 use crate::asm::no_operation;
+use crate::ccmram::is_dma_accessible;
+use crate::clock::{ClockListener, Clocks};
 use crate::dma::DataTransferDirection::MemoryToPeripheral;
 use crate::dma::PriorityLevel::VeryHigh;
-use crate::dma::{DmaConf, MemoryDataSize, MemoryIncrementMode, PeripheralDataSize, StreamConf};
+use crate::dma::{
+    DmaError, DmaStream, MemoryDataSize, MemoryIncrementMode, PeripheralDataSize, StreamConf,
+};
 use crate::memory::store_barrier;
 use crate::memory_mapped_io::MemoryMappedIo;
+use crate::rcc::Peripheral;
+use crate::register_bus::RegisterBus;
+use crate::registers::usart::{brr, cr1, cr2, cr3, dr, sr, BRR, CR1, CR2, CR3, DR, SR};
+use crate::shared::Shared;
+use core::fmt::{self, Write};
 use core::ptr::copy_nonoverlapping;
 
 #[repr(u32)]
@@ -28,112 +37,139 @@ impl From<UsartStopBits> for u32 {
     }
 }
 
+#[derive(Default)]
 pub struct UsartControl {
     pub enabled: Option<bool>,
     pub parity_control_enabled: Option<bool>,
     pub transmitter_enabled: Option<bool>,
+    pub receiver_enabled: Option<bool>,
     pub dma_transmitter_enabled: Option<bool>,
     pub word_length: Option<UsartWordLength>,
     pub stop_bits: Option<UsartStopBits>,
 }
 
-impl Default for UsartControl {
-    fn default() -> Self {
-        UsartControl {
-            enabled: None,
-            parity_control_enabled: None,
-            transmitter_enabled: None,
-            word_length: None,
-            stop_bits: None,
-            dma_transmitter_enabled: None,
-        }
-    }
-}
-
-pub struct UsartConf {
-    reg: MemoryMappedIo,
+pub struct UsartConf<B: RegisterBus = MemoryMappedIo> {
+    reg: B,
 }
 
 impl UsartConf {
     pub const fn new(base: u32) -> Self {
-        Self {
-            reg: MemoryMappedIo::new(base),
-        }
+        UsartConf::with_bus(MemoryMappedIo::new(base))
     }
+}
 
-    pub unsafe fn data_register(&self) -> *mut u32 {
-        self.reg.address().add(1)
+impl<B: RegisterBus> UsartConf<B> {
+    pub const fn with_bus(reg: B) -> Self {
+        Self { reg }
+    }
+
+    // A handle for a driver while the owner stays in a Shared, where UsartBaudRate finds it
+    pub const fn by_ref(&self) -> UsartConf<&B> {
+        UsartConf::with_bus(&self.reg)
+    }
+
+    pub fn data_register(&self) -> u32 {
+        self.reg.register_address(DR)
     }
 
     pub fn set_usart_control(&self, usart_control: UsartControl) {
-        let mut current_value_ctrl1: u32 = self.reg.read(3);
-        let mut current_value_ctrl2: u32 = self.reg.read(4);
-        let mut current_value_ctrl3: u32 = self.reg.read(5);
+        let mut current_value_ctrl1: u32 = self.reg.read_register(CR1);
+        let mut current_value_ctrl2: u32 = self.reg.read_register(CR2);
+        let mut current_value_ctrl3: u32 = self.reg.read_register(CR3);
         if let Some(enabled) = usart_control.enabled {
-            current_value_ctrl1 &= !(0b1 << 13);
-            current_value_ctrl1 |= (enabled as u32) << 13;
+            current_value_ctrl1 = cr1::UE.write(current_value_ctrl1, enabled as u32);
         }
         if let Some(parity_control_enabled) = usart_control.parity_control_enabled {
-            current_value_ctrl1 &= !(0b1 << 10);
-            current_value_ctrl1 |= (parity_control_enabled as u32) << 10;
+            current_value_ctrl1 =
+                cr1::PCE.write(current_value_ctrl1, parity_control_enabled as u32);
         }
         if let Some(transmitter_enabled) = usart_control.transmitter_enabled {
-            current_value_ctrl1 &= !(0b1 << 3);
-            current_value_ctrl1 |= (transmitter_enabled as u32) << 3;
+            current_value_ctrl1 = cr1::TE.write(current_value_ctrl1, transmitter_enabled as u32);
+        }
+        if let Some(receiver_enabled) = usart_control.receiver_enabled {
+            current_value_ctrl1 = cr1::RE.write(current_value_ctrl1, receiver_enabled as u32);
         }
         if let Some(word_length) = usart_control.word_length {
-            current_value_ctrl1 &= !(0b1 << 12);
-            current_value_ctrl1 |= u32::from(word_length) << 12;
+            current_value_ctrl1 = cr1::M.write(current_value_ctrl1, u32::from(word_length));
         }
         if let Some(stop_bits) = usart_control.stop_bits {
-            current_value_ctrl2 &= !(0b11 << 12);
-            current_value_ctrl2 |= u32::from(stop_bits) << 12;
+            current_value_ctrl2 = cr2::STOP.write(current_value_ctrl2, u32::from(stop_bits));
         }
         if let Some(enabled) = usart_control.dma_transmitter_enabled {
-            current_value_ctrl3 &= !(0b1 << 7);
-            current_value_ctrl3 |= (enabled as u32) << 7;
+            current_value_ctrl3 = cr3::DMAT.write(current_value_ctrl3, enabled as u32);
         }
-        self.reg.write(current_value_ctrl1, 3);
-        self.reg.write(current_value_ctrl2, 4);
-        self.reg.write(current_value_ctrl3, 5);
+        self.reg.write_register(CR1, current_value_ctrl1);
+        self.reg.write_register(CR2, current_value_ctrl2);
+        self.reg.write_register(CR3, current_value_ctrl3);
     }
 
     // See RM0090 page 981 for details
     pub fn set_baud_rate(&self, mantissa: u32, fraction: u32) {
-        let value: u32 = (mantissa << 4) | fraction;
-        self.reg.write(value, 2);
+        let value = brr::DIV_MANTISSA.value(mantissa) | brr::DIV_FRACTION.value(fraction);
+        self.reg.write_register(BRR, value);
+    }
+
+    // Oversampling by 16, USARTDIV = PCLK / (16 * baud rate) rounded to 1/16
+    pub fn set_baud_rate_from_clock(&self, pclk: u32, baud_rate: u32) {
+        let divider = (pclk + baud_rate / 2) / baud_rate;
+        self.set_baud_rate(divider >> 4, divider & 0xF);
+    }
+
+    pub fn is_transmitter_enabled(&self) -> bool {
+        let control = self.reg.read_register(CR1);
+        cr1::UE.is_set(control) && cr1::TE.is_set(control)
     }
 
     pub fn is_transmit_data_register_empty(&self) -> bool {
-        self.reg.read(0) & (0b1 << 7) != 0
+        self.reg.is_field_set(SR, sr::TXE)
     }
 
     pub fn is_transmission_completed(&self) -> bool {
-        self.reg.read(0) & (0b1 << 6) != 0
+        self.reg.is_field_set(SR, sr::TC)
     }
 
     pub fn set_data(&self, data: u8) {
-        self.reg.write(data as u32, 1);
+        self.reg.write_register(DR, dr::DR.value(data as u32));
+    }
+
+    pub fn is_read_data_register_not_empty(&self) -> bool {
+        self.reg.is_field_set(SR, sr::RXNE)
+    }
+
+    // Reading DR clears RXNE, after a read of SR it clears the overrun and error flags as well
+    pub fn data(&self) -> u8 {
+        self.reg.read_field(DR, dr::DR) as u8
     }
 
     pub fn clear_transmission_complete(&self) {
-        self.reg.clear_bit(6, 0);
+        self.reg.write_zero_to_clear(SR, sr::TC);
     }
 }
 
-pub struct UsartSingleByteDriver<'a> {
-    control: &'a UsartConf,
+pub struct UsartSingleByteDriver<B: RegisterBus = MemoryMappedIo> {
+    control: UsartConf<B>,
 }
 
-impl<'a> UsartSingleByteDriver<'a> {
-    pub const fn new(control: &'a UsartConf) -> UsartSingleByteDriver<'a> {
+impl<B: RegisterBus> UsartSingleByteDriver<B> {
+    pub const fn new(control: UsartConf<B>) -> UsartSingleByteDriver<B> {
         UsartSingleByteDriver { control }
     }
 
+    pub fn release(self) -> UsartConf<B> {
+        self.control
+    }
+
+    // Polls for a received byte, bytes lost to an overrun are left to the protocol to detect
+    pub fn receive_byte(&self) -> Option<u8> {
+        self.control
+            .is_read_data_register_not_empty()
+            .then(|| self.control.data())
+    }
+
     pub fn send_bytes(&self, bytes: &[u8]) {
         unsafe {
             for character in bytes.iter() {
-                self.control.set_data(character.clone());
+                self.control.set_data(*character);
                 while !self.control.is_transmit_data_register_empty()
                     || !self.control.is_transmission_completed()
                 {
@@ -144,34 +180,52 @@ impl<'a> UsartSingleByteDriver<'a> {
     }
 }
 
-pub struct UsartDmaDriver<'a, const BUFFER_SIZE: usize> {
-    control: &'a UsartConf,
-    dma: &'a DmaConf,
+impl<B: RegisterBus> Write for UsartSingleByteDriver<B> {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        self.send_bytes(string.as_bytes());
+        Ok(())
+    }
+}
+
+pub struct UsartDmaDriver<
+    const BUFFER_SIZE: usize,
+    U: RegisterBus = MemoryMappedIo,
+    D: RegisterBus = MemoryMappedIo,
+> {
+    control: UsartConf<U>,
+    stream: DmaStream<D>,
     buffer: [u8; BUFFER_SIZE],
     buffer_offset: usize,
-    stream_id: u32,
     channel: u8,
 }
 
-impl<'a, const BUFFER_SIZE: usize> UsartDmaDriver<'a, BUFFER_SIZE> {
+impl<const BUFFER_SIZE: usize, U: RegisterBus, D: RegisterBus> UsartDmaDriver<BUFFER_SIZE, U, D> {
     pub const fn new(
-        control: &'a UsartConf,
-        dma: &'a DmaConf,
-        stream_id: u32,
+        control: UsartConf<U>,
+        stream: DmaStream<D>,
         channel: u8,
-    ) -> UsartDmaDriver<'a, BUFFER_SIZE> {
+    ) -> UsartDmaDriver<BUFFER_SIZE, U, D> {
         UsartDmaDriver {
             control,
-            dma,
+            stream,
             buffer: [0; BUFFER_SIZE],
             buffer_offset: 0,
-            stream_id,
             channel,
         }
     }
 
+    // Waits for the pending transfer so the stream is not released in the middle of it
+    pub fn release(self) -> (UsartConf<U>, DmaStream<D>) {
+        while !self.stream.is_stream_disabled() && !self.is_transmission_completed() {
+            unsafe {
+                no_operation();
+            }
+        }
+        (self.control, self.stream)
+    }
+
     pub fn is_transmission_completed(&self) -> bool {
-        self.control.is_transmission_completed() && self.dma.is_transfer_completed(self.stream_id)
+        self.control.is_transmission_completed() && self.stream.is_transfer_completed()
     }
 
     pub fn buffer_capacity(&self) -> usize {
@@ -197,39 +251,40 @@ impl<'a, const BUFFER_SIZE: usize> UsartDmaDriver<'a, BUFFER_SIZE> {
         bytes_to_write
     }
 
-    pub fn flush(&mut self) {
+    // Fails when the driver, and so its buffer, lives in CCM, nothing is sent in that case
+    pub fn flush(&mut self) -> Result<(), DmaError> {
+        if !is_dma_accessible(self.buffer.as_ptr() as u32, BUFFER_SIZE) {
+            return Err(DmaError::UnreachableMemory);
+        }
         unsafe {
             self.control.clear_transmission_complete();
-            self.dma.disable_stream(self.stream_id);
-            while !self.dma.is_stream_disabled(self.stream_id) {
+            self.stream.disable_stream();
+            while !self.stream.is_stream_disabled() {
                 no_operation();
             }
-            self.dma
-                .clear_stream_interrupt_status_register(self.stream_id);
-            self.dma
-                .set_stream_data_length(self.stream_id, self.buffer_offset as u16);
-            self.dma
-                .set_stream_memory0_address(self.stream_id, self.buffer.as_ptr() as u32);
-            self.dma
-                .set_stream_peripheral_address(self.stream_id, self.control.data_register() as u32);
-            self.dma.set_stream_config(
-                self.stream_id,
-                StreamConf {
-                    data_transfer_direction: Some(MemoryToPeripheral),
-                    memory_increment_mode: Some(MemoryIncrementMode::AddressIncrement),
-                    channel: Some(self.channel),
-                    priority_level: Some(VeryHigh),
-                    memory_data_size: Some(MemoryDataSize::Byte),
-                    peripheral_data_size: Some(PeripheralDataSize::Byte),
-                },
-            );
+            self.stream.clear_stream_interrupt_status_register();
+            self.stream
+                .set_stream_data_length(self.buffer_offset as u16);
+            self.stream
+                .set_stream_memory0_address(self.buffer.as_ptr() as u32)?;
+            self.stream
+                .set_stream_peripheral_address(self.control.data_register());
+            self.stream.set_stream_config(StreamConf {
+                data_transfer_direction: Some(MemoryToPeripheral),
+                memory_increment_mode: Some(MemoryIncrementMode::AddressIncrement),
+                channel: Some(self.channel),
+                priority_level: Some(VeryHigh),
+                memory_data_size: Some(MemoryDataSize::Byte),
+                peripheral_data_size: Some(PeripheralDataSize::Byte),
+            });
             store_barrier();
-            self.dma.enable_stream(self.stream_id);
+            self.stream.enable_stream();
             self.buffer_offset = 0;
         }
+        Ok(())
     }
 
-    pub fn print_line(&mut self, string: &str) {
+    pub fn print_line(&mut self, string: &str) -> Result<(), DmaError> {
         let payload = string.as_bytes();
         const END_OF_LINE: &[u8] = "\r\n".as_bytes();
         let mut saved = 0;
@@ -242,7 +297,7 @@ impl<'a, const BUFFER_SIZE: usize> UsartDmaDriver<'a, BUFFER_SIZE> {
             if end_of_line_saved == END_OF_LINE.len() {
                 break;
             } else {
-                self.flush();
+                self.flush()?;
                 while !self.is_transmission_completed() {
                     unsafe {
                         no_operation();
@@ -250,5 +305,42 @@ impl<'a, const BUFFER_SIZE: usize> UsartDmaDriver<'a, BUFFER_SIZE> {
                 }
             }
         }
+        Ok(())
+    }
+}
+
+/*
+   Keeps a USART at the same baud rate when the bus clock changes, register it with
+   clock::register_clock_listener. It borrows the USART from the Shared owning it and only
+   writes BRR, drivers built on UsartConf::by_ref keep working. Until the Shared is initialized
+   the listener does nothing. A frame sent while the divider changes is garbled,
+   ClockConfig::freeze should run with the port idle.
+*/
+pub struct UsartBaudRate<B: RegisterBus + 'static = MemoryMappedIo> {
+    usart: &'static Shared<UsartConf<B>>,
+    peripheral: Peripheral,
+    baud_rate: u32,
+}
+
+impl<B: RegisterBus> UsartBaudRate<B> {
+    pub const fn new(
+        usart: &'static Shared<UsartConf<B>>,
+        peripheral: Peripheral,
+        baud_rate: u32,
+    ) -> Self {
+        UsartBaudRate {
+            usart,
+            peripheral,
+            baud_rate,
+        }
+    }
+}
+
+impl<B: RegisterBus> ClockListener for UsartBaudRate<B> {
+    fn clocks_changed(&self, clocks: &Clocks) {
+        if let Some(usart) = self.usart.get() {
+            let pclk = clocks.bus_clock(self.peripheral.bus());
+            usart.set_baud_rate_from_clock(pclk, self.baud_rate);
+        }
     }
 }
@@ -0,0 +1,110 @@
+/*
+   Vector table, PM0214 p37. The linker script places the initial stack pointer and
+   RESET_HANDLER first, followed by EXCEPTIONS and the 91 device INTERRUPTS.
+
+   Every handler is an undefined symbol named after its Exception or Interrupt variant, the
+   linker script defaults the ones nobody defined to DefaultHandler. Handlers are bound with
+   the exception! and interrupt! macros:
+
+       avocado::interrupt!(Tim7, led_blink);
+*/
+use crate::asm::no_operation;
+
+pub union Vector {
+    pub handler: unsafe extern "C" fn(),
+    pub reserved: usize,
+}
+
+#[allow(non_snake_case)]
+extern "C" {
+    fn NonMaskableInt();
+    fn HardFault();
+    fn MemoryManagement();
+    fn BusFault();
+    fn UsageFault();
+    fn SVCall();
+    fn DebugMonitor();
+    fn PendSV();
+    fn SysTick();
+}
+
+// Exceptions 2 to 15, reset is the entry before them
+#[no_mangle]
+#[link_section = ".vector_table.exceptions"]
+pub static EXCEPTIONS: [Vector; 14] = [
+    Vector {
+        handler: NonMaskableInt,
+    },
+    Vector { handler: HardFault },
+    Vector {
+        handler: MemoryManagement,
+    },
+    Vector { handler: BusFault },
+    Vector {
+        handler: UsageFault,
+    },
+    Vector { reserved: 0 },
+    Vector { reserved: 0 },
+    Vector { reserved: 0 },
+    Vector { reserved: 0 },
+    Vector { handler: SVCall },
+    Vector {
+        handler: DebugMonitor,
+    },
+    Vector { reserved: 0 },
+    Vector { handler: PendSV },
+    Vector { handler: SysTick },
+];
+
+include!(concat!(env!("OUT_DIR"), "/vectors.rs"));
+
+// DefaultHandler unless the application binds its own with exception!(DefaultHandler, ...)
+#[no_mangle]
+pub extern "C" fn __default_handler() -> ! {
+    loop {
+        unsafe {
+            no_operation();
+        }
+    }
+}
+
+// Binds fn() or unsafe fn() to an Interrupt variant, unknown names do not compile
+#[macro_export]
+macro_rules! interrupt {
+    ($name:ident, $path:path) => {
+        const _: () = {
+            let _ = $crate::stm32f439zitx::Interrupt::$name;
+
+            #[export_name = stringify!($name)]
+            unsafe extern "C" fn handler() {
+                let handler: unsafe fn() = $path;
+                handler()
+            }
+        };
+    };
+}
+
+// Binds fn() or unsafe fn() to an Exception variant or to DefaultHandler
+#[macro_export]
+macro_rules! exception {
+    (DefaultHandler, $path:path) => {
+        const _: () = {
+            #[export_name = "DefaultHandler"]
+            unsafe extern "C" fn handler() {
+                let handler: unsafe fn() = $path;
+                handler()
+            }
+        };
+    };
+    ($name:ident, $path:path) => {
+        const _: () = {
+            let _ = $crate::stm32f439zitx::Exception::$name;
+
+            #[export_name = stringify!($name)]
+            unsafe extern "C" fn handler() {
+                let handler: unsafe fn() = $path;
+                handler()
+            }
+        };
+    };
+}
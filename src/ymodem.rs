@@ -0,0 +1,265 @@
+use crate::crc::crc16_xmodem;
+
+/*
+   YMODEM receiver, CRC-16 variant with 128 and 1024 byte blocks (YMODEM-1k).
+
+   Receiver                       Sender
+   C                         ->
+                             <-   SOH 00 FF "name\0size ..." CRC    block 0, file header
+   ACK C                     ->
+                             <-   STX 01 FE data CRC                 blocks 1, 2, ...
+   ACK                       ->
+                             <-   EOT
+   NAK                       ->
+                             <-   EOT
+   ACK C                     ->
+                             <-   SOH 00 FF "\0" CRC                 empty header, end of batch
+   ACK                       ->
+
+   A block is repeated after NAK, a repeated block that was already acknowledged is ignored.
+   Two CAN in a row cancel the transfer in either direction. Only the first file of a batch is
+   received, the padding of the last block is passed on like the rest of the data.
+*/
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+const CRC_MODE: u8 = b'C';
+
+pub const BLOCK_SIZE: usize = 128;
+pub const BLOCK_SIZE_1K: usize = 1024;
+
+// Milliseconds, a C is sent every START_TIMEOUT until the sender starts
+const START_TIMEOUT: u32 = 1000;
+const START_ATTEMPTS: u32 = 60;
+const PACKET_TIMEOUT: u32 = 10_000;
+const BYTE_TIMEOUT: u32 = 1000;
+const MAX_ERRORS: u32 = 10;
+
+pub trait SerialPort {
+    fn write_byte(&mut self, byte: u8);
+
+    // None when no byte arrived within timeout milliseconds
+    fn read_byte(&mut self, timeout: u32) -> Option<u8>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum YmodemError<E> {
+    Timeout,
+    Cancelled,
+    TooManyErrors,
+    OutOfSequence,
+    InvalidHeader,
+    NoFile,
+    FileTooLarge,
+    Storage(E),
+}
+
+enum PacketError {
+    Timeout,
+    Corrupted,
+    EndOfTransmission,
+    Cancelled,
+}
+
+struct Packet {
+    number: u8,
+    length: usize,
+}
+
+/*
+   Receives one file and passes its blocks to store together with their offset in the file.
+   Returns the file size from the header, store may have seen up to a block of padding more.
+   Fails with FileTooLarge before any data is sent when the file exceeds capacity bytes.
+*/
+pub fn receive<P: SerialPort, E>(
+    port: &mut P,
+    capacity: u32,
+    mut store: impl FnMut(u32, &[u8]) -> Result<(), E>,
+) -> Result<u32, YmodemError<E>> {
+    let mut buffer = [0u8; BLOCK_SIZE_1K];
+    let size = receive_header(port, &mut buffer)?;
+    if size > capacity {
+        cancel(port);
+        return Err(YmodemError::FileTooLarge);
+    }
+    port.write_byte(ACK);
+    port.write_byte(CRC_MODE);
+
+    let mut expected: u8 = 1;
+    let mut offset: u32 = 0;
+    let mut errors = 0;
+    let mut end_of_transmission = false;
+    loop {
+        match read_packet(port, &mut buffer) {
+            Ok(packet) if packet.number == expected => {
+                if offset < size {
+                    let length = packet.length.min((capacity - offset) as usize);
+                    if let Err(error) = store(offset, &buffer[..length]) {
+                        cancel(port);
+                        return Err(YmodemError::Storage(error));
+                    }
+                }
+                offset += packet.length as u32;
+                expected = expected.wrapping_add(1);
+                errors = 0;
+                port.write_byte(ACK);
+            }
+            // Our ACK got lost and the sender repeats the last block
+            Ok(packet) if packet.number == expected.wrapping_sub(1) => port.write_byte(ACK),
+            Ok(_) => {
+                cancel(port);
+                return Err(YmodemError::OutOfSequence);
+            }
+            // The first EOT is answered with NAK, a repeated one can not be line noise
+            Err(PacketError::EndOfTransmission) if !end_of_transmission => {
+                end_of_transmission = true;
+                port.write_byte(NAK);
+            }
+            Err(PacketError::EndOfTransmission) => {
+                port.write_byte(ACK);
+                break;
+            }
+            Err(PacketError::Cancelled) => return Err(YmodemError::Cancelled),
+            Err(PacketError::Timeout) | Err(PacketError::Corrupted) => {
+                errors += 1;
+                if errors > MAX_ERRORS {
+                    cancel(port);
+                    return Err(YmodemError::TooManyErrors);
+                }
+                purge(port);
+                port.write_byte(NAK);
+            }
+        }
+    }
+
+    finish_batch(port, &mut buffer);
+    Ok(size)
+}
+
+fn receive_header<P: SerialPort, E>(
+    port: &mut P,
+    buffer: &mut [u8; BLOCK_SIZE_1K],
+) -> Result<u32, YmodemError<E>> {
+    let mut errors = 0;
+    loop {
+        port.write_byte(CRC_MODE);
+        match read_packet_within(port, buffer, START_TIMEOUT) {
+            Ok(Packet { number: 0, length }) => {
+                return match parse_header(&buffer[..length]) {
+                    Some(Some(size)) => Ok(size),
+                    Some(None) => {
+                        port.write_byte(ACK);
+                        Err(YmodemError::NoFile)
+                    }
+                    None => {
+                        cancel(port);
+                        Err(YmodemError::InvalidHeader)
+                    }
+                };
+            }
+            Err(PacketError::Cancelled) => return Err(YmodemError::Cancelled),
+            Err(PacketError::Timeout) => {
+                errors += 1;
+                if errors >= START_ATTEMPTS {
+                    return Err(YmodemError::Timeout);
+                }
+            }
+            Ok(_) | Err(PacketError::Corrupted) | Err(PacketError::EndOfTransmission) => {
+                errors += 1;
+                if errors >= START_ATTEMPTS {
+                    cancel(port);
+                    return Err(YmodemError::TooManyErrors);
+                }
+                purge(port);
+            }
+        }
+    }
+}
+
+/*
+   Block 0 holds the NUL terminated file name followed by the size in decimal, then optional
+   fields separated by spaces. An empty name ends the batch, Some(None) is returned for it.
+*/
+fn parse_header(data: &[u8]) -> Option<Option<u32>> {
+    let name_length = data.iter().position(|&byte| byte == 0)?;
+    if name_length == 0 {
+        return Some(None);
+    }
+    let digits = data[name_length + 1..]
+        .iter()
+        .take_while(|&&byte| byte != b' ' && byte != 0);
+    let mut size: u32 = 0;
+    let mut length = 0;
+    for &digit in digits {
+        if !digit.is_ascii_digit() {
+            return None;
+        }
+        size = size.checked_mul(10)?.checked_add((digit - b'0') as u32)?;
+        length += 1;
+    }
+    (length > 0).then_some(Some(size))
+}
+
+// Acknowledges the empty header closing the batch, a second file is refused
+fn finish_batch<P: SerialPort>(port: &mut P, buffer: &mut [u8; BLOCK_SIZE_1K]) {
+    port.write_byte(CRC_MODE);
+    if let Ok(Packet { number: 0, length }) = read_packet(port, buffer) {
+        match parse_header(&buffer[..length]) {
+            Some(None) => port.write_byte(ACK),
+            _ => cancel(port),
+        }
+    }
+}
+
+fn read_packet<P: SerialPort>(
+    port: &mut P,
+    buffer: &mut [u8; BLOCK_SIZE_1K],
+) -> Result<Packet, PacketError> {
+    read_packet_within(port, buffer, PACKET_TIMEOUT)
+}
+
+fn read_packet_within<P: SerialPort>(
+    port: &mut P,
+    buffer: &mut [u8; BLOCK_SIZE_1K],
+    timeout: u32,
+) -> Result<Packet, PacketError> {
+    let length = match port.read_byte(timeout).ok_or(PacketError::Timeout)? {
+        SOH => BLOCK_SIZE,
+        STX => BLOCK_SIZE_1K,
+        EOT => return Err(PacketError::EndOfTransmission),
+        CAN => {
+            return match port.read_byte(BYTE_TIMEOUT) {
+                Some(CAN) => Err(PacketError::Cancelled),
+                _ => Err(PacketError::Corrupted),
+            }
+        }
+        _ => return Err(PacketError::Corrupted),
+    };
+    let number = read_byte(port)?;
+    let complement = read_byte(port)?;
+    for byte in buffer[..length].iter_mut() {
+        *byte = read_byte(port)?;
+    }
+    let crc = u16::from_be_bytes([read_byte(port)?, read_byte(port)?]);
+    if number != !complement || crc != crc16_xmodem(&buffer[..length]) {
+        return Err(PacketError::Corrupted);
+    }
+    Ok(Packet { number, length })
+}
+
+fn read_byte<P: SerialPort>(port: &mut P) -> Result<u8, PacketError> {
+    port.read_byte(BYTE_TIMEOUT).ok_or(PacketError::Corrupted)
+}
+
+// Drops the rest of a broken packet, the line has to be quiet before NAK
+fn purge<P: SerialPort>(port: &mut P) {
+    while port.read_byte(BYTE_TIMEOUT).is_some() {}
+}
+
+fn cancel<P: SerialPort>(port: &mut P) {
+    port.write_byte(CAN);
+    port.write_byte(CAN);
+}
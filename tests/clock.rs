@@ -0,0 +1,440 @@
+/*
+   Clock tree planning on the host, the register sequence of ClockConfig::freeze is covered by
+   the setup_clock golden test. The HSE failure tests run freeze against simulated registers.
+   Registering clock listeners is global state, tests/clock_listeners.rs covers it on its own.
+*/
+use avocado::clock::{
+    record_hse_failure, recover_from_hse_failure, take_hse_failure, ClockConfig, ClockError,
+    ClockListener,
+};
+use avocado::flash::FlashConf;
+use avocado::pwr::PwrConf;
+use avocado::rcc::{I2sPll, MainPll, Peripheral, PllClockSource, RccConf, SaiPll, SystemClock};
+use avocado::registers::pwr::{cr as pwr_cr, csr as pwr_csr};
+use avocado::registers::rcc::{cfgr, cir, cr};
+use avocado::registers::scb::icsr;
+use avocado::registers::{basic_timer, flash, pwr, rcc, scb, usart};
+use avocado::scb::ScbConf;
+use avocado::shared::Shared;
+use avocado::simulated_bus::SimulatedBus;
+use avocado::timer::{BasicTimerConf, BasicTimerPeriod};
+use avocado::usart::{UsartBaudRate, UsartConf};
+use core::cell::Cell;
+
+type RccBus = SimulatedBus<{ rcc::DCKCFGR.index() + 1 }>;
+type PwrBus = SimulatedBus<{ pwr::CSR.index() + 1 }>;
+type FlashBus = SimulatedBus<{ flash::OPTCR1.index() + 1 }>;
+
+// HSI and the PLL get ready, HSE never does, CSSC clears CSSF
+fn dead_hse(index: usize, previous: u32, written: u32) -> u32 {
+    match index {
+        i if i == rcc::CR.index() => {
+            let value = cr::HSIRDY.write(written, cr::HSION.read(written));
+            cr::PLLRDY.write(value, cr::PLLON.read(value))
+        }
+        i if i == rcc::CFGR.index() => cfgr::SWS.write(written, cfgr::SW.read(written)),
+        i if i == rcc::CIR.index() && cir::CSSC.is_set(written) => {
+            cir::CSSF.write(cir::CSSC.write(written, 0), 0)
+        }
+        i if i == rcc::CIR.index() => cir::CSSF.write(written, cir::CSSF.read(previous)),
+        _ => written,
+    }
+}
+
+fn running_hse(index: usize, previous: u32, written: u32) -> u32 {
+    match index {
+        i if i == rcc::CR.index() => {
+            cr::HSERDY.write(dead_hse(index, previous, written), cr::HSEON.read(written))
+        }
+        _ => dead_hse(index, previous, written),
+    }
+}
+
+// ODRDY and ODSWRDY follow ODEN and ODSWEN, VOSRDY stays set
+fn over_drive(registers: &[Cell<u32>], index: usize, written: u32) {
+    registers[index].set(written);
+    if index == pwr::CR.index() {
+        let status = registers[pwr::CSR.index()].get();
+        let status = pwr_csr::ODRDY.write(status, pwr_cr::ODEN.read(written));
+        registers[pwr::CSR.index()]
+            .set(pwr_csr::ODSWRDY.write(status, pwr_cr::ODSWEN.read(written)));
+    }
+}
+
+fn buses(hardware: fn(usize, u32, u32) -> u32) -> (RccBus, PwrBus, FlashBus) {
+    let rcc_bus = RccBus::with_hardware(rcc::BASE_ADDRESS, hardware);
+    rcc_bus.load_reset_values(&[rcc::CR, rcc::PLLCFGR, rcc::CFGR]);
+    let pwr_bus = PwrBus::with_register_file_hardware(pwr::BASE_ADDRESS, over_drive);
+    pwr_bus.preset(pwr::CSR, pwr::csr::VOSRDY.mask());
+    let flash_bus = FlashBus::new(flash::BASE_ADDRESS);
+    (rcc_bus, pwr_bus, flash_bus)
+}
+
+fn nucleo_clock_config() -> ClockConfig {
+    ClockConfig::new()
+        .use_hse(8_000_000, true)
+        .sysclk(168_000_000)
+        .pclk1(42_000_000)
+        .pclk2(84_000_000)
+        .require_pll48()
+}
+
+#[test]
+fn nucleo_clock_tree_uses_the_known_pll_setting() {
+    let plan = nucleo_clock_config().plan().unwrap();
+
+    assert_eq!(plan.system_clock, SystemClock::PLL);
+    assert_eq!(plan.pll, MainPll::new(PllClockSource::HSE, 4, 168, 2, 7));
+    assert_eq!(
+        (plan.ahb_prescaler, plan.apb1_prescaler, plan.apb2_prescaler),
+        (1, 4, 2)
+    );
+    assert_eq!(plan.flash_latency, 5);
+    let clocks = plan.clocks;
+    assert_eq!(clocks.sysclk(), 168_000_000);
+    assert_eq!(clocks.hclk(), 168_000_000);
+    assert_eq!(clocks.pclk1(), 42_000_000);
+    assert_eq!(clocks.pclk2(), 84_000_000);
+    assert_eq!(clocks.timer_clock1(), 84_000_000);
+    assert_eq!(clocks.timer_clock2(), 168_000_000);
+    assert_eq!(clocks.pll48(), Some(48_000_000));
+    assert_eq!(clocks.source(), PllClockSource::HSE);
+}
+
+#[test]
+fn reset_clock_tree_needs_no_pll() {
+    let plan = ClockConfig::new().plan().unwrap();
+
+    assert_eq!(plan.system_clock, SystemClock::HSI);
+    assert_eq!(plan.pll, None);
+    assert_eq!(plan.flash_latency, 0);
+    assert_eq!(plan.clocks.pclk1(), 16_000_000);
+    assert_eq!(plan.clocks.timer_clock1(), 16_000_000);
+    assert_eq!(plan.clocks.pll48(), None);
+}
+
+#[test]
+fn bus_clocks_default_to_the_fastest_allowed() {
+    let plan = ClockConfig::new()
+        .sysclk(120_000_000)
+        .hclk(60_000_000)
+        .plan()
+        .unwrap();
+
+    let pll = plan.pll.unwrap();
+    assert_eq!(pll.source(), PllClockSource::HSI);
+    assert!((1_000_000..=2_000_000).contains(&(16_000_000 / pll.m() as u32)));
+    assert_eq!(
+        16_000_000 / pll.m() as u32 * pll.n() as u32 / pll.p() as u32,
+        120_000_000
+    );
+    assert_eq!(plan.ahb_prescaler, 2);
+    assert_eq!(plan.clocks.pclk1(), 30_000_000);
+    assert_eq!(plan.clocks.pclk2(), 60_000_000);
+    assert_eq!(plan.flash_latency, 1);
+    assert!(plan.clocks.pll48().unwrap() <= 48_000_000);
+}
+
+#[test]
+fn unreachable_targets_are_reported() {
+    let hse = ClockConfig::new().use_hse(8_000_000, true);
+    assert_eq!(
+        hse.sysclk(181_000_000).plan(),
+        Err(ClockError::SystemClockTooHigh)
+    );
+    assert_eq!(
+        hse.sysclk(100_000_001).plan(),
+        Err(ClockError::SystemClockUnreachable)
+    );
+    assert_eq!(
+        hse.sysclk(100_000_000).require_pll48().plan(),
+        Err(ClockError::UsbClockUnreachable)
+    );
+    assert_eq!(
+        hse.sysclk(168_000_000).hclk(50_000_000).plan(),
+        Err(ClockError::AhbClockUnreachable)
+    );
+    assert_eq!(
+        hse.sysclk(168_000_000).pclk1(84_000_000).plan(),
+        Err(ClockError::Apb1ClockTooHigh)
+    );
+    assert_eq!(
+        hse.sysclk(168_000_000).pclk2(50_000_000).plan(),
+        Err(ClockError::Apb2ClockUnreachable)
+    );
+    assert_eq!(
+        ClockConfig::new().use_hse(30_000_000, false).plan(),
+        Err(ClockError::HseFrequencyOutOfRange)
+    );
+}
+
+#[test]
+fn main_pll_rejects_invalid_dividers() {
+    assert_eq!(MainPll::new(PllClockSource::HSE, 4, 168, 3, 7), None);
+    assert_eq!(MainPll::new(PllClockSource::HSE, 1, 168, 2, 7), None);
+    assert_eq!(MainPll::new(PllClockSource::HSE, 4, 433, 2, 7), None);
+    assert_eq!(MainPll::new(PllClockSource::HSE, 4, 168, 2, 16), None);
+}
+
+#[test]
+fn i2s_and_sai_pll_outputs_follow_the_shared_input() {
+    let plan = nucleo_clock_config()
+        .i2s_pll(I2sPll::new(192, 2, 4, 2).unwrap())
+        .sai_pll(SaiPll::new(168, 7, 6, 1, 4).unwrap())
+        .plan()
+        .unwrap();
+
+    assert_eq!(plan.pll_m, Some(4));
+    let clocks = plan.clocks;
+    assert_eq!(clocks.i2s(), Some(192_000_000));
+    assert_eq!(clocks.plli2s_sai(), Some(48_000_000));
+    assert_eq!(clocks.pllsai_sai(), Some(48_000_000));
+    assert_eq!(clocks.lcd_tft(), Some(14_000_000));
+
+    // HSI straight to SYSCLK, PLLM is set for PLLI2S alone
+    let plan = ClockConfig::new()
+        .i2s_pll(I2sPll::new(192, 2, 4, 2).unwrap())
+        .plan()
+        .unwrap();
+    assert_eq!(plan.system_clock, SystemClock::HSI);
+    assert_eq!(plan.pll, None);
+    assert_eq!(plan.pll_m, Some(8));
+    assert_eq!(plan.clocks.i2s(), Some(192_000_000));
+    assert_eq!(plan.clocks.lcd_tft(), None);
+
+    assert_eq!(
+        nucleo_clock_config()
+            .i2s_pll(I2sPll::new(300, 2, 4, 1).unwrap())
+            .plan(),
+        Err(ClockError::I2sPllVcoOutOfRange)
+    );
+    // 1.5 MHz input, 90 MHz VCO
+    assert_eq!(
+        ClockConfig::new()
+            .use_hse(3_000_000, true)
+            .sai_pll(SaiPll::new(60, 2, 2, 1, 2).unwrap())
+            .plan(),
+        Err(ClockError::SaiPllVcoOutOfRange)
+    );
+    assert_eq!(I2sPll::new(192, 8, 4, 1), None);
+    assert_eq!(I2sPll::new(192, 2, 4, 33), None);
+    assert_eq!(SaiPll::new(192, 4, 2, 1, 3), None);
+}
+
+#[test]
+fn i2s_pll_that_does_not_lock_is_reported() {
+    let (rcc_bus, pwr_bus, flash_bus) = buses(running_hse);
+
+    assert_eq!(
+        nucleo_clock_config()
+            .i2s_pll(I2sPll::new(192, 2, 4, 2).unwrap())
+            .freeze(
+                &RccConf::with_bus(&rcc_bus),
+                &PwrConf::with_bus(&pwr_bus),
+                &FlashConf::with_bus(&flash_bus),
+            ),
+        Err(ClockError::I2sPllLockTimeout)
+    );
+    let control = rcc_bus.peek(rcc::CR);
+    assert!(!cr::PLLI2SON.is_set(control));
+    assert!(cr::PLLRDY.is_set(control));
+}
+
+#[test]
+fn sysclk_above_168_mhz_uses_over_drive() {
+    let hse = ClockConfig::new().use_hse(8_000_000, true);
+    let plan = hse.sysclk(180_000_000).plan().unwrap();
+    assert!(plan.over_drive);
+    assert_eq!(plan.flash_latency, 5);
+    assert_eq!(plan.clocks.pclk1(), 45_000_000);
+    assert_eq!(plan.clocks.pclk2(), 90_000_000);
+    assert!(!hse.sysclk(168_000_000).plan().unwrap().over_drive);
+    assert_eq!(
+        hse.sysclk(168_000_000).pclk1(45_000_000).plan(),
+        Err(ClockError::Apb1ClockTooHigh)
+    );
+
+    let (rcc_bus, pwr_bus, flash_bus) = buses(running_hse);
+    let rcc_conf = RccConf::with_bus(&rcc_bus);
+    let pwr_conf = PwrConf::with_bus(&pwr_bus);
+    let flash_conf = FlashConf::with_bus(&flash_bus);
+    hse.sysclk(180_000_000)
+        .freeze(&rcc_conf, &pwr_conf, &flash_conf)
+        .unwrap();
+    assert!(pwr_conf.is_over_drive_enabled());
+    assert_eq!(flash_conf.latency(), 5);
+    assert_eq!(rcc_conf.get_system_clock_status(), Ok(SystemClock::PLL));
+
+    // Back below 168 MHz the over-drive mode is left again
+    hse.sysclk(120_000_000)
+        .freeze(&rcc_conf, &pwr_conf, &flash_conf)
+        .unwrap();
+    assert!(!pwr_conf.is_over_drive_enabled());
+    assert!(!pwr_conf.is_over_drive_ready());
+    assert_eq!(flash_conf.latency(), 3);
+}
+
+#[test]
+fn hse_startup_timeout_falls_back_to_hsi() {
+    let (rcc_bus, pwr_bus, flash_bus) = buses(dead_hse);
+
+    let clocks = nucleo_clock_config()
+        .freeze(
+            &RccConf::with_bus(&rcc_bus),
+            &PwrConf::with_bus(&pwr_bus),
+            &FlashConf::with_bus(&flash_bus),
+        )
+        .unwrap();
+
+    assert_eq!(clocks.source(), PllClockSource::HSI);
+    assert_eq!(clocks.sysclk(), 168_000_000);
+    assert_eq!(clocks.pclk1(), 42_000_000);
+    assert_eq!(clocks.pll48(), Some(48_000_000));
+    let control = rcc_bus.peek(rcc::CR);
+    assert!(!cr::HSEON.is_set(control));
+    assert!(!cr::CSSON.is_set(control));
+    assert!(cr::HSION.is_set(control));
+    assert_eq!(pllcfgr_source(&rcc_bus), 0);
+    // 151.5 MHz VCO from a 1.5 MHz input, out of reach for the 1 to 2 MHz steps of HSI
+    assert_eq!(
+        ClockConfig::new()
+            .use_hse(3_000_000, true)
+            .sysclk(18_937_500)
+            .freeze(
+                &RccConf::with_bus(&rcc_bus),
+                &PwrConf::with_bus(&pwr_bus),
+                &FlashConf::with_bus(&flash_bus),
+            ),
+        Err(ClockError::HseStartupTimeout)
+    );
+}
+
+#[test]
+fn hse_failure_moves_the_clock_tree_to_hsi() {
+    let (rcc_bus, pwr_bus, flash_bus) = buses(running_hse);
+    let rcc_conf = RccConf::with_bus(&rcc_bus);
+    let clocks = nucleo_clock_config()
+        .freeze(
+            &rcc_conf,
+            &PwrConf::with_bus(&pwr_bus),
+            &FlashConf::with_bus(&flash_bus),
+        )
+        .unwrap();
+    assert_eq!(clocks.source(), PllClockSource::HSE);
+    assert!(cr::CSSON.is_set(rcc_bus.peek(rcc::CR)));
+
+    // What the hardware does on its own: HSE and the PLL off, SYSCLK on HSI, CSSF set
+    let control = rcc_bus.peek(rcc::CR);
+    rcc_bus.preset(
+        rcc::CR,
+        control & !(cr::HSEON.mask() | cr::HSERDY.mask() | cr::PLLON.mask() | cr::PLLRDY.mask())
+            | cr::HSION.mask()
+            | cr::HSIRDY.mask(),
+    );
+    rcc_bus.preset(rcc::CFGR, cfgr::SW.write(rcc_bus.peek(rcc::CFGR), 0));
+    rcc_bus.preset(rcc::CFGR, cfgr::SWS.write(rcc_bus.peek(rcc::CFGR), 0));
+    rcc_bus.preset(rcc::CIR, cir::CSSF.mask());
+    assert!(rcc_conf.is_hse_failure_detected());
+
+    // The NMI only clears CSSF and pends PendSV for the rest
+    assert_eq!(recover_from_hse_failure(&rcc_conf), None);
+    let scb_bus = SimulatedBus::<{ scb::ICSR.index() + 1 }>::new(scb::BASE_ADDRESS);
+    let control = rcc_bus.peek(rcc::CR);
+    record_hse_failure(&rcc_conf, &ScbConf::with_bus(&scb_bus));
+    assert!(!rcc_conf.is_hse_failure_detected());
+    assert_eq!(rcc_bus.peek(rcc::CR), control);
+    assert!(icsr::PENDSVSET.is_set(scb_bus.peek(scb::ICSR)));
+
+    let clocks = recover_from_hse_failure(&rcc_conf).unwrap();
+
+    assert_eq!(rcc_conf.get_system_clock_status(), Ok(SystemClock::HSI));
+    assert_eq!(clocks.source(), PllClockSource::HSI);
+    assert_eq!(clocks.sysclk(), 16_000_000);
+    assert_eq!(clocks.hclk(), 16_000_000);
+    assert_eq!(clocks.pclk1(), 4_000_000);
+    assert_eq!(clocks.pclk2(), 8_000_000);
+    assert_eq!(clocks.timer_clock1(), 8_000_000);
+    assert_eq!(clocks.pll48(), None);
+    assert!(!cr::HSEON.is_set(rcc_bus.peek(rcc::CR)));
+    assert_eq!(recover_from_hse_failure(&rcc_conf), None);
+    assert!(take_hse_failure());
+    assert!(!take_hse_failure());
+}
+
+#[test]
+fn listeners_keep_baud_rate_and_period_when_throttling() {
+    let usart_bus: &'static _ = Box::leak(Box::new(
+        SimulatedBus::<{ usart::GTPR.index() + 1 }>::new(usart::BASE_ADDRESS),
+    ));
+    let timer_bus: &'static _ = Box::leak(Box::new(
+        SimulatedBus::<{ basic_timer::ARR.index() + 1 }>::new(basic_timer::BASE_ADDRESS),
+    ));
+    let shared_usart: &'static Shared<UsartConf<_>> = Box::leak(Box::new(Shared::new()));
+    let shared_timer: &'static Shared<BasicTimerConf<_>> = Box::leak(Box::new(Shared::new()));
+    let baud_rate = UsartBaudRate::new(shared_usart, Peripheral::Usart3, 115_200);
+    let period = BasicTimerPeriod::new(shared_timer, Peripheral::Tim7, 500_000);
+    let brr = || {
+        let value = usart_bus.peek(usart::BRR);
+        (
+            usart::brr::DIV_MANTISSA.read(value),
+            usart::brr::DIV_FRACTION.read(value),
+        )
+    };
+    let timer = || {
+        (
+            timer_bus.peek(basic_timer::PSC),
+            timer_bus.peek(basic_timer::ARR),
+        )
+    };
+
+    let full_speed = nucleo_clock_config().plan().unwrap().clocks;
+    // Nothing to update before the drivers were handed over
+    baud_rate.clocks_changed(&full_speed);
+    period.clocks_changed(&full_speed);
+    assert_eq!(brr(), (0, 0));
+    assert_eq!(timer(), (0, 0));
+
+    shared_usart.init(UsartConf::with_bus(usart_bus)).unwrap();
+    shared_timer
+        .init(BasicTimerConf::with_bus(timer_bus))
+        .unwrap();
+    baud_rate.clocks_changed(&full_speed);
+    period.clocks_changed(&full_speed);
+    // 42 MHz / 115200 = 16 * 22.8125, 84 MHz * 0.5 s = 641 * 65522
+    assert_eq!(brr(), (22, 13));
+    assert_eq!(timer(), (640, 65521));
+
+    let throttled = ClockConfig::new()
+        .use_hse(8_000_000, true)
+        .sysclk(48_000_000)
+        .pclk1(12_000_000)
+        .pclk2(24_000_000)
+        .plan()
+        .unwrap()
+        .clocks;
+    assert_eq!(throttled.timer_clock1(), 24_000_000);
+    baud_rate.clocks_changed(&throttled);
+    period.clocks_changed(&throttled);
+    // 12 MHz / 115200 = 16 * 6.5104, 24 MHz * 0.5 s = 184 * 65217
+    assert_eq!(brr(), (6, 8));
+    assert_eq!(timer(), (183, 65216));
+}
+
+#[test]
+fn timer_period_is_clamped_to_the_counter() {
+    let timer_bus =
+        SimulatedBus::<{ basic_timer::ARR.index() + 1 }>::new(basic_timer::BASE_ADDRESS);
+    let timer = BasicTimerConf::with_bus(&timer_bus);
+
+    timer.set_period(84_000_000, 780);
+    assert_eq!(timer_bus.peek(basic_timer::PSC), 0);
+    assert_eq!(timer_bus.peek(basic_timer::ARR), 65519);
+
+    timer.set_period(168_000_000, 60_000_000);
+    assert_eq!(timer_bus.peek(basic_timer::PSC), 0xFFFF);
+    assert_eq!(timer_bus.peek(basic_timer::ARR), 0xFFFF);
+}
+
+fn pllcfgr_source(rcc_bus: &RccBus) -> u32 {
+    avocado::registers::rcc::pllcfgr::PLLSRC.read(rcc_bus.peek(rcc::PLLCFGR))
+}
@@ -0,0 +1,132 @@
+/*
+   Clock listeners are registered for the whole program, this binary holds the only test that
+   registers any so other freeze calls do not show up in what the listeners see. The HSE
+   failure is part of it for the same reason, the NMI changes what current_clocks reports.
+*/
+use avocado::clock::{
+    current_clocks, record_hse_failure, recover_from_hse_failure, register_clock_listener,
+    take_hse_failure, ClockConfig, ClockError, ClockListener, Clocks,
+};
+use avocado::flash::FlashConf;
+use avocado::pwr::PwrConf;
+use avocado::rcc::RccConf;
+use avocado::registers::rcc::{cfgr, cr};
+use avocado::registers::{flash, pwr, rcc, scb};
+use avocado::scb::ScbConf;
+use avocado::simulated_bus::SimulatedBus;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+type RccBus = SimulatedBus<{ rcc::DCKCFGR.index() + 1 }>;
+type PwrBus = SimulatedBus<{ pwr::CSR.index() + 1 }>;
+type FlashBus = SimulatedBus<{ flash::OPTCR1.index() + 1 }>;
+
+// Oscillators and the PLL get ready, SWS follows SW
+fn ready_clocks(index: usize, _previous: u32, written: u32) -> u32 {
+    match index {
+        i if i == rcc::CR.index() => {
+            let value = cr::HSIRDY.write(written, cr::HSION.read(written));
+            let value = cr::HSERDY.write(value, cr::HSEON.read(value));
+            cr::PLLRDY.write(value, cr::PLLON.read(value))
+        }
+        i if i == rcc::CFGR.index() => cfgr::SWS.write(written, cfgr::SW.read(written)),
+        _ => written,
+    }
+}
+
+struct Pclk1 {
+    frequency: AtomicU32,
+    calls: AtomicU32,
+}
+
+impl Pclk1 {
+    const fn new() -> Self {
+        Pclk1 {
+            frequency: AtomicU32::new(0),
+            calls: AtomicU32::new(0),
+        }
+    }
+
+    fn seen(&self) -> (u32, u32) {
+        (
+            self.frequency.load(Ordering::Relaxed),
+            self.calls.load(Ordering::Relaxed),
+        )
+    }
+}
+
+impl ClockListener for Pclk1 {
+    fn clocks_changed(&self, clocks: &Clocks) {
+        self.frequency.store(clocks.pclk1(), Ordering::Relaxed);
+        self.calls.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+static EARLY: Pclk1 = Pclk1::new();
+static LATE: Pclk1 = Pclk1::new();
+static SPARE: [Pclk1; 7] = [const { Pclk1::new() }; 7];
+
+#[test]
+fn freeze_notifies_registered_listeners() {
+    let rcc_bus = RccBus::with_hardware(rcc::BASE_ADDRESS, ready_clocks);
+    rcc_bus.load_reset_values(&[rcc::CR, rcc::PLLCFGR, rcc::CFGR]);
+    let pwr_bus = PwrBus::new(pwr::BASE_ADDRESS);
+    pwr_bus.preset(pwr::CSR, pwr::csr::VOSRDY.mask());
+    let flash_bus = FlashBus::new(flash::BASE_ADDRESS);
+    let rcc_conf = RccConf::with_bus(&rcc_bus);
+    let pwr_conf = PwrConf::with_bus(&pwr_bus);
+    let flash_conf = FlashConf::with_bus(&flash_bus);
+    let config = ClockConfig::new().use_hse(8_000_000, true);
+
+    // Nothing to tell before the first freeze
+    assert_eq!(current_clocks(), None);
+    register_clock_listener(&EARLY).unwrap();
+    assert_eq!(EARLY.seen(), (0, 0));
+
+    config
+        .sysclk(168_000_000)
+        .pclk1(42_000_000)
+        .freeze(&rcc_conf, &pwr_conf, &flash_conf)
+        .unwrap();
+    assert_eq!(EARLY.seen(), (42_000_000, 1));
+
+    // A late listener starts from the running clocks
+    register_clock_listener(&LATE).unwrap();
+    assert_eq!(LATE.seen(), (42_000_000, 1));
+
+    config
+        .sysclk(48_000_000)
+        .pclk1(12_000_000)
+        .freeze(&rcc_conf, &pwr_conf, &flash_conf)
+        .unwrap();
+    assert_eq!(EARLY.seen(), (12_000_000, 2));
+    assert_eq!(LATE.seen(), (12_000_000, 2));
+
+    // The NMI reports the HSI frequencies right away, PendSV tells the listeners
+    let scb_bus = SimulatedBus::<{ scb::ICSR.index() + 1 }>::new(scb::BASE_ADDRESS);
+    record_hse_failure(&rcc_conf, &ScbConf::with_bus(&scb_bus));
+    assert_eq!(current_clocks().unwrap().pclk1(), 4_000_000);
+    assert_eq!(EARLY.seen(), (12_000_000, 2));
+    assert!(!take_hse_failure());
+    recover_from_hse_failure(&rcc_conf).unwrap();
+    assert_eq!(EARLY.seen(), (4_000_000, 3));
+    assert_eq!(current_clocks().unwrap().pclk1(), 4_000_000);
+    assert!(take_hse_failure());
+
+    // A freeze after the recovery is reported again instead of the NMI frequencies
+    config
+        .sysclk(48_000_000)
+        .pclk1(12_000_000)
+        .freeze(&rcc_conf, &pwr_conf, &flash_conf)
+        .unwrap();
+    assert_eq!(current_clocks().unwrap().pclk1(), 12_000_000);
+    assert_eq!(LATE.seen(), (12_000_000, 4));
+
+    for listener in &SPARE[..6] {
+        register_clock_listener(listener).unwrap();
+    }
+    assert_eq!(
+        register_clock_listener(&SPARE[6]),
+        Err(ClockError::TooManyClockListeners)
+    );
+    assert_eq!(SPARE[6].seen(), (0, 0));
+}
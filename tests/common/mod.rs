@@ -0,0 +1,42 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/*
+   Compares a recorded register access sequence with tests/golden/<name>.txt. Running the
+   tests with UPDATE_GOLDEN=1 writes the recorded sequence instead, the diff of the golden
+   file is then reviewed like any other change.
+*/
+pub fn assert_golden(name: &str, recorded: &str) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("{name}.txt"));
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, recorded).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_else(|error| {
+        panic!(
+            "{}: {error}, run with UPDATE_GOLDEN=1 to create it",
+            path.display()
+        )
+    });
+    if expected != recorded {
+        let line = expected
+            .lines()
+            .zip(recorded.lines())
+            .position(|(expected, recorded)| expected != recorded)
+            .unwrap_or_else(|| expected.lines().count().min(recorded.lines().count()));
+        panic!(
+            "{name}: recorded sequence differs from {} at line {}\n\
+             expected: {}\n\
+             recorded: {}",
+            path.display(),
+            line + 1,
+            expected.lines().nth(line).unwrap_or("<end>"),
+            recorded.lines().nth(line).unwrap_or("<end>")
+        );
+    }
+}
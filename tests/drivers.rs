@@ -0,0 +1,62 @@
+use avocado::gpio::{AlternateFunction, GpioConf};
+use avocado::rcc::{MainPll, PllClockSource, RccConf, RccError, SystemClock};
+use avocado::registers::gpio::{afrl, bsrr, odr, AFRH, BSRR, ODR};
+use avocado::registers::rcc::{cfgr, pllcfgr, CFGR, CSR, PLLCFGR};
+use avocado::registers::{gpiod, rcc};
+use avocado::simulated_bus::SimulatedBus;
+
+#[test]
+fn configure_main_pll_writes_the_dividers() {
+    let bus = SimulatedBus::<{ CSR.index() + 1 }>::new(rcc::BASE_ADDRESS);
+    bus.load_reset_values(&[PLLCFGR]);
+    let rcc = RccConf::with_bus(&bus);
+
+    rcc.configure_main_pll(&MainPll::new(PllClockSource::HSE, 4, 168, 2, 7).unwrap());
+
+    let pll = bus.peek(PLLCFGR);
+    assert_eq!(pllcfgr::PLLSRC.read(pll), 1);
+    assert_eq!(pllcfgr::PLLN.read(pll), 168);
+    assert_eq!(pllcfgr::PLLM.read(pll), 4);
+    assert_eq!(pllcfgr::PLLP.read(pll), 0);
+    assert_eq!(pllcfgr::PLLQ.read(pll), 7);
+}
+
+#[test]
+fn reserved_system_clock_status_is_an_error() {
+    let bus = SimulatedBus::<{ CSR.index() + 1 }>::new(rcc::BASE_ADDRESS);
+    let rcc = RccConf::with_bus(&bus);
+
+    bus.preset(CFGR, cfgr::SWS.value(0b10));
+    assert_eq!(rcc.get_system_clock_status(), Ok(SystemClock::PLL));
+    bus.preset(CFGR, cfgr::SWS.value(0b11));
+    assert_eq!(
+        rcc.get_system_clock_status(),
+        Err(RccError::ReservedSystemClockStatus)
+    );
+}
+
+#[test]
+fn set_alternate_function_keeps_the_other_pins() {
+    let bus = SimulatedBus::<{ AFRH.index() + 1 }>::new(gpiod::BASE_ADDRESS);
+    bus.preset(AFRH, 0xFFFF_FFFF);
+    let port = GpioConf::with_bus(&bus);
+
+    port.set_alternate_function(9, AlternateFunction::Usart1_3);
+
+    assert_eq!(bus.peek(AFRH), 0xFFFF_FF7F);
+    assert_eq!(afrl::AFRL0.nth(1).read(bus.peek(AFRH)), 0b0111);
+}
+
+#[test]
+fn switch_pin_output_writes_bsrr_only() {
+    let bus = SimulatedBus::<{ AFRH.index() + 1 }>::new(gpiod::BASE_ADDRESS);
+    bus.preset(ODR, odr::ODR0.nth(7).value(1));
+    let port = GpioConf::with_bus(&bus);
+
+    port.switch_pin_output(7);
+    assert_eq!(bus.peek(BSRR), bsrr::BR0.nth(7).value(1));
+
+    port.switch_pin_output(0);
+    assert_eq!(bus.peek(BSRR), bsrr::BS0.nth(0).value(1));
+    assert_eq!(bus.peek(ODR), odr::ODR0.nth(7).value(1));
+}
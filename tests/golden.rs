@@ -0,0 +1,290 @@
+/*
+   Golden-sequence tests: drivers run against simulated registers behind a RecordingBus and the
+   decoded access sequence is compared with tests/golden. A changed bring-up order shows up as
+   a diff of the golden file in review.
+*/
+mod common;
+
+use avocado::ccmram::CCMRAM_START;
+use avocado::clock::{setup_clock, ClockConfig};
+use avocado::dma::DataTransferDirection::MemoryToPeripheral;
+use avocado::dma::{DmaConf, DmaError, MemoryIncrementMode, PriorityLevel, StreamConf};
+use avocado::flash::FlashConf;
+use avocado::gpio::GpioConf;
+use avocado::mco::{output_mco1, output_mco2};
+use avocado::pwr::PwrConf;
+use avocado::rcc::{
+    I2sPll, Mco1Source, Mco2Source, McoPrescaler, Peripheral, PllClockSource, RccConf, ResetCause,
+    SaiPll,
+};
+use avocado::recording_bus::{Recorder, RecordingBus};
+use avocado::registers::{dma1, flash, gpioa, gpioc, pwr, rcc, usart3};
+use avocado::simulated_bus::SimulatedBus;
+use avocado::usart::{UsartConf, UsartDmaDriver};
+use common::assert_golden;
+use core::cell::Cell;
+
+type RccBus = SimulatedBus<{ rcc::DCKCFGR.index() + 1 }>;
+type PwrBus = SimulatedBus<{ pwr::CSR.index() + 1 }>;
+type FlashBus = SimulatedBus<{ flash::OPTCR1.index() + 1 }>;
+type UsartBus = SimulatedBus<{ usart3::GTPR.index() + 1 }>;
+type DmaBus = SimulatedBus<{ dma1::S7FCR.index() + 1 }>;
+type GpioBus = SimulatedBus<{ gpioa::AFRH.index() + 1 }>;
+
+// Oscillators and the PLL are ready as soon as they are switched on, SWS follows SW
+fn rcc_hardware(index: usize, _previous: u32, written: u32) -> u32 {
+    use avocado::registers::rcc::{cfgr, cr, csr};
+    match index {
+        i if i == rcc::CR.index() => {
+            let value = cr::HSIRDY.write(written, cr::HSION.read(written));
+            let value = cr::HSERDY.write(value, cr::HSEON.read(value));
+            let value = cr::PLLRDY.write(value, cr::PLLON.read(value));
+            let value = cr::PLLI2SRDY.write(value, cr::PLLI2SON.read(value));
+            cr::PLLSAIRDY.write(value, cr::PLLSAION.read(value))
+        }
+        i if i == rcc::CFGR.index() => cfgr::SWS.write(written, cfgr::SW.read(written)),
+        i if i == rcc::CSR.index() => csr::LSIRDY.write(written, csr::LSION.read(written)),
+        _ => written,
+    }
+}
+
+// ODRDY and ODSWRDY follow ODEN and ODSWEN
+fn pwr_hardware(registers: &[Cell<u32>], index: usize, written: u32) {
+    use avocado::registers::pwr::{cr, csr};
+    registers[index].set(written);
+    if index == pwr::CR.index() {
+        let status = csr::ODRDY.write(registers[pwr::CSR.index()].get(), cr::ODEN.read(written));
+        let status = csr::ODSWRDY.write(status, cr::ODSWEN.read(written));
+        registers[pwr::CSR.index()].set(status);
+    }
+}
+
+fn rcc_bus() -> RccBus {
+    let bus = RccBus::with_hardware(rcc::BASE_ADDRESS, rcc_hardware);
+    bus.load_reset_values(&[
+        rcc::CR,
+        rcc::PLLCFGR,
+        rcc::CFGR,
+        rcc::AHB1ENR,
+        rcc::APB1ENR,
+        rcc::APB2ENR,
+        rcc::CSR,
+    ]);
+    bus
+}
+
+#[test]
+fn setup_clock_sequence() {
+    let recorder: Recorder<256> = Recorder::new();
+    let rcc_bus = rcc_bus();
+    let pwr_bus = PwrBus::new(pwr::BASE_ADDRESS);
+    pwr_bus.load_reset_values(&[pwr::CR, pwr::CSR]);
+    pwr_bus.preset(pwr::CSR, pwr::csr::VOSRDY.mask());
+    let flash_bus = FlashBus::new(flash::BASE_ADDRESS);
+    flash_bus.load_reset_values(&[flash::ACR]);
+
+    setup_clock(
+        &RccConf::with_bus(RecordingBus::new(&rcc_bus, &recorder)),
+        &PwrConf::with_bus(RecordingBus::new(&pwr_bus, &recorder)),
+        &FlashConf::with_bus(RecordingBus::new(&flash_bus, &recorder)),
+    );
+
+    assert_golden("setup_clock", &recorder.to_string());
+}
+
+// HPRE has to be written before SW, the wait states are sized for the divided HCLK
+#[test]
+fn freeze_divided_hclk_sequence() {
+    let recorder: Recorder<256> = Recorder::new();
+    let rcc_bus = rcc_bus();
+    let pwr_bus = PwrBus::new(pwr::BASE_ADDRESS);
+    pwr_bus.load_reset_values(&[pwr::CR, pwr::CSR]);
+    pwr_bus.preset(pwr::CSR, pwr::csr::VOSRDY.mask());
+    let flash_bus = FlashBus::new(flash::BASE_ADDRESS);
+    flash_bus.load_reset_values(&[flash::ACR]);
+
+    let clocks = ClockConfig::new()
+        .use_hse(8_000_000, true)
+        .sysclk(168_000_000)
+        .hclk(84_000_000)
+        .freeze(
+            &RccConf::with_bus(RecordingBus::new(&rcc_bus, &recorder)),
+            &PwrConf::with_bus(RecordingBus::new(&pwr_bus, &recorder)),
+            &FlashConf::with_bus(RecordingBus::new(&flash_bus, &recorder)),
+        )
+        .unwrap();
+
+    assert_eq!(clocks.hclk(), 84_000_000);
+    assert_golden("freeze_divided_hclk", &recorder.to_string());
+}
+
+#[test]
+fn over_drive_sequence() {
+    let recorder: Recorder<32> = Recorder::new();
+    let pwr_bus = PwrBus::with_register_file_hardware(pwr::BASE_ADDRESS, pwr_hardware);
+    pwr_bus.load_reset_values(&[pwr::CR, pwr::CSR]);
+    let pwr = PwrConf::with_bus(RecordingBus::new(&pwr_bus, &recorder));
+
+    pwr.enable_over_drive();
+    pwr.disable_over_drive();
+
+    assert_golden("over_drive", &recorder.to_string());
+}
+
+#[test]
+fn take_reset_cause_sequence() {
+    use avocado::registers::rcc::csr;
+
+    let recorder: Recorder<8> = Recorder::new();
+    let rcc_bus = rcc_bus();
+    // Watchdog reset, NRST is pulled by the internal reset as well
+    rcc_bus.preset(
+        rcc::CSR,
+        csr::IWDGRSTF.mask() | csr::PINRSTF.mask() | csr::LSION.mask(),
+    );
+
+    let cause = RccConf::with_bus(RecordingBus::new(&rcc_bus, &recorder)).take_reset_cause();
+
+    assert_eq!(cause, ResetCause::IndependentWatchdog);
+    assert_golden("take_reset_cause", &recorder.to_string());
+}
+
+#[test]
+fn peripheral_clock_gating_sequence() {
+    let recorder: Recorder<32> = Recorder::new();
+    let rcc_bus = rcc_bus();
+    rcc_bus.load_reset_values(&[rcc::APB2LPENR]);
+    let rcc = RccConf::with_bus(RecordingBus::new(&rcc_bus, &recorder));
+
+    rcc.enable_peripheral(Peripheral::Usart1);
+    rcc.reset_peripheral(Peripheral::Usart1);
+    rcc.set_low_power_clock(Peripheral::Usart1, false);
+    rcc.disable_peripheral(Peripheral::Usart1);
+    // No reset bit, nothing is recorded
+    rcc.reset_peripheral(Peripheral::BackupSram);
+
+    assert_golden("peripheral_clock_gating", &recorder.to_string());
+}
+
+#[test]
+fn i2s_and_sai_pll_sequence() {
+    let recorder: Recorder<64> = Recorder::new();
+    let rcc_bus = rcc_bus();
+    rcc_bus.load_reset_values(&[rcc::PLLI2SCFGR, rcc::PLLSAICFGR, rcc::DCKCFGR]);
+    let rcc = RccConf::with_bus(RecordingBus::new(&rcc_bus, &recorder));
+
+    // 2 MHz VCO input, PLLI2S VCO 384 MHz, PLLSAI VCO 336 MHz
+    rcc.configure_pll_input(PllClockSource::HSE, 4);
+    rcc.configure_i2s_pll(&I2sPll::new(192, 2, 4, 2).unwrap());
+    assert_eq!(rcc.enable_i2s_pll(), Ok(()));
+    rcc.configure_sai_pll(&SaiPll::new(168, 7, 6, 1, 4).unwrap());
+    assert_eq!(rcc.enable_sai_pll(), Ok(()));
+    rcc.disable_sai_pll();
+    rcc.disable_i2s_pll();
+
+    assert_golden("i2s_and_sai_pll", &recorder.to_string());
+}
+
+#[test]
+fn mco_output_sequence() {
+    let recorder: Recorder<64> = Recorder::new();
+    let rcc_bus = rcc_bus();
+    let rcc = RccConf::with_bus(RecordingBus::new(&rcc_bus, &recorder));
+    let port_a_bus = GpioBus::new(gpioa::BASE_ADDRESS);
+    port_a_bus.load_reset_values(&[gpioa::MODER, gpioa::OSPEEDR, gpioa::AFRH]);
+    let port_c_bus = GpioBus::new(gpioc::BASE_ADDRESS);
+    port_c_bus.load_reset_values(&[gpioc::MODER, gpioc::OSPEEDR, gpioc::AFRH]);
+
+    output_mco1(
+        &rcc,
+        &GpioConf::with_bus(RecordingBus::new(&port_a_bus, &recorder)),
+        Mco1Source::HSE,
+        McoPrescaler::Div1,
+    );
+    output_mco2(
+        &rcc,
+        &GpioConf::with_bus(RecordingBus::new(&port_c_bus, &recorder)),
+        Mco2Source::SYSCLK,
+        McoPrescaler::Div4,
+    );
+
+    assert_golden("mco_output", &recorder.to_string());
+}
+
+#[test]
+fn flash_erase_sector_sequence() {
+    let recorder: Recorder<32> = Recorder::new();
+    let flash_bus = FlashBus::new(flash::BASE_ADDRESS);
+    flash_bus.load_reset_values(&[flash::SR, flash::CR]);
+    let flash = FlashConf::with_bus(RecordingBus::new(&flash_bus, &recorder));
+
+    flash.unlock();
+    // Sector 17, the first 128 KB sector of bank 2
+    assert_eq!(flash.erase_sector(17), Ok(()));
+    flash.lock();
+
+    assert_golden("flash_erase_sector", &recorder.to_string());
+}
+
+#[test]
+fn usart_dma_flush_sequence() {
+    let recorder: Recorder<128> = Recorder::new();
+    let usart_bus = UsartBus::new(usart3::BASE_ADDRESS);
+    usart_bus.load_reset_values(&[usart3::SR]);
+    let dma_bus = DmaBus::new(dma1::BASE_ADDRESS);
+    let usart = UsartConf::with_bus(RecordingBus::new(&usart_bus, &recorder));
+    let streams = DmaConf::with_bus(RecordingBus::new(&dma_bus, &recorder)).split();
+    let mut driver: UsartDmaDriver<16, _, _> = UsartDmaDriver::new(usart, streams.stream3, 4);
+
+    driver.write_buffer(b"Hello\r\n");
+    driver.flush().unwrap();
+
+    // The buffer lives on the test stack, its address differs between runs
+    let buffer = dma_bus.peek(dma1::S3M0AR);
+    let recorded = recorder
+        .to_string()
+        .replace(&format!("{buffer:#010x}"), "<buffer>")
+        .replace(&format!("={buffer:#x}"), "=<buffer>");
+    assert_golden("usart_dma_flush", &recorded);
+}
+
+#[test]
+fn dma_set_stream_config_sequence() {
+    let recorder: Recorder<32> = Recorder::new();
+    let dma_bus = DmaBus::new(dma1::BASE_ADDRESS);
+    // Fields missing from the configuration keep their value
+    dma_bus.preset(
+        dma1::S6CR,
+        dma1::s6cr::PFCTRL.mask() | dma1::s6cr::TCIE.mask(),
+    );
+    let streams = DmaConf::with_bus(RecordingBus::new(&dma_bus, &recorder)).split();
+
+    streams.stream6.set_stream_config(StreamConf {
+        data_transfer_direction: Some(MemoryToPeripheral),
+        memory_increment_mode: Some(MemoryIncrementMode::AddressIncrement),
+        channel: Some(5),
+        priority_level: Some(PriorityLevel::VeryHigh),
+        ..Default::default()
+    });
+    streams.stream1.set_stream_config(StreamConf {
+        channel: Some(7),
+        ..Default::default()
+    });
+
+    assert_golden("dma_set_stream_config", &recorder.to_string());
+}
+
+#[test]
+fn dma_refuses_ccmram_buffer() {
+    let recorder: Recorder<8> = Recorder::new();
+    let dma_bus = DmaBus::new(dma1::BASE_ADDRESS);
+    let streams = DmaConf::with_bus(RecordingBus::new(&dma_bus, &recorder)).split();
+
+    assert_eq!(
+        streams
+            .stream3
+            .set_stream_memory0_address(CCMRAM_START + 0x100),
+        Err(DmaError::UnreachableMemory)
+    );
+    assert!(recorder.is_empty());
+}
@@ -0,0 +1,91 @@
+/*
+   Allocator tests on a buffer of the test, the MCU uses the same Heap behind the global
+   allocator.
+*/
+use avocado::heap::{Heap, HeapStatistics};
+use core::alloc::Layout;
+
+const HEAP_SIZE: usize = 1024;
+
+#[repr(align(64))]
+struct Region([u8; HEAP_SIZE]);
+
+fn heap(region: &mut Region) -> Heap {
+    let mut heap = Heap::empty();
+    unsafe {
+        heap.init(region.0.as_mut_ptr() as usize, HEAP_SIZE);
+    }
+    heap
+}
+
+#[test]
+fn allocations_are_aligned_and_inside_the_region() {
+    let mut region = Region([0; HEAP_SIZE]);
+    let bounds = region.0.as_ptr_range();
+    let (start, end) = (bounds.start as usize, bounds.end as usize);
+    let mut heap = heap(&mut region);
+
+    for align in [1, 4, 8, 32, 64] {
+        let layout = Layout::from_size_align(24, align).unwrap();
+        let pointer = heap.allocate(layout) as usize;
+        assert_ne!(pointer, 0);
+        assert_eq!(pointer % align, 0);
+        assert!(pointer >= start && pointer + layout.size() <= end);
+    }
+}
+
+#[test]
+fn freed_blocks_are_merged() {
+    let mut region = Region([0; HEAP_SIZE]);
+    let mut heap = heap(&mut region);
+    let quarter = Layout::from_size_align(HEAP_SIZE / 4, 8).unwrap();
+
+    let blocks = [(); 4].map(|_| heap.allocate(quarter));
+    assert!(blocks.iter().all(|block| !block.is_null()));
+    assert!(heap.allocate(Layout::new::<u64>()).is_null());
+
+    // Freed out of order, the whole region has to be one block again
+    for index in [1, 3, 0, 2] {
+        unsafe {
+            heap.deallocate(blocks[index], quarter);
+        }
+    }
+    let whole = Layout::from_size_align(HEAP_SIZE, 8).unwrap();
+    assert!(!heap.allocate(whole).is_null());
+}
+
+#[test]
+fn statistics_track_peak_usage() {
+    let mut region = Region([0; HEAP_SIZE]);
+    let mut heap = heap(&mut region);
+    let layout = Layout::from_size_align(100, 4).unwrap();
+
+    let first = heap.allocate(layout);
+    let second = heap.allocate(layout);
+    unsafe {
+        heap.deallocate(first, layout);
+    }
+
+    assert_eq!(
+        heap.statistics(),
+        HeapStatistics {
+            used: 104,
+            free: HEAP_SIZE - 104,
+            peak: 208,
+        }
+    );
+    unsafe {
+        heap.deallocate(second, layout);
+    }
+    assert_eq!(heap.statistics().used, 0);
+}
+
+#[test]
+fn exhausted_heap_returns_null() {
+    let mut region = Region([0; HEAP_SIZE]);
+    let mut heap = heap(&mut region);
+
+    let too_big = Layout::from_size_align(HEAP_SIZE + 8, 8).unwrap();
+    assert!(heap.allocate(too_big).is_null());
+    assert_eq!(heap.statistics().used, 0);
+}
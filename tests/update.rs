@@ -0,0 +1,264 @@
+/*
+   Firmware update over YMODEM on the host: a simulated sender answers the receiver byte by
+   byte and the image ends up in a SimulatedFlash with the sector layout of the MCU.
+*/
+use avocado::boot::{APPLICATION_ADDRESS, STAGING_ADDRESS, STAGING_SIZE};
+use avocado::crc::{crc16_xmodem, crc32};
+use avocado::flash::{FLASH_SIZE, FLASH_START};
+use avocado::simulated_flash::SimulatedFlash;
+use avocado::update::{apply_update, pending_update, receive_update, UpdateError, UpdateRecord};
+use avocado::ymodem::{SerialPort, YmodemError};
+use std::collections::VecDeque;
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+
+fn packet(number: u8, data: &[u8], size: usize) -> Vec<u8> {
+    let mut block = data.to_vec();
+    block.resize(size, if number == 0 { 0 } else { 0x1A });
+    let start = if size == 1024 { STX } else { SOH };
+    let mut packet = vec![start, number, !number];
+    packet.extend_from_slice(&block);
+    packet.extend_from_slice(&crc16_xmodem(&block).to_be_bytes());
+    packet
+}
+
+/*
+   Frames in the order they are sent: file header, data blocks, EOT and the empty header.
+   The file header, the first data block and the empty header wait for a C, the other frames
+   follow the ACK of the previous one. NAK repeats the current frame.
+*/
+struct SimulatedSender {
+    frames: Vec<Vec<u8>>,
+    current: usize,
+    queue: VecDeque<u8>,
+    corrupt: Vec<usize>,
+    received: Vec<u8>,
+}
+
+impl SimulatedSender {
+    fn new(name: &str, file: &[u8]) -> Self {
+        let mut header = format!("{name}\0{} 0 0", file.len()).into_bytes();
+        header.push(0);
+        let mut frames = vec![packet(0, &header, 128)];
+        let mut number = 1u8;
+        let mut remaining = file;
+        while !remaining.is_empty() {
+            let size = if remaining.len() > 128 { 1024 } else { 128 };
+            let (block, rest) = remaining.split_at(size.min(remaining.len()));
+            frames.push(packet(number, block, size));
+            number = number.wrapping_add(1);
+            remaining = rest;
+        }
+        frames.push(vec![EOT]);
+        frames.push(packet(0, &[0], 128));
+        SimulatedSender {
+            frames,
+            current: 0,
+            queue: VecDeque::new(),
+            corrupt: Vec::new(),
+            received: Vec::new(),
+        }
+    }
+
+    // The frame goes out with a flipped data bit the first time
+    fn corrupting(mut self, frame: usize) -> Self {
+        self.corrupt.push(frame);
+        self
+    }
+
+    fn is_done(&self) -> bool {
+        self.current == self.frames.len()
+    }
+
+    fn send_current(&mut self) {
+        let mut frame = self.frames[self.current].clone();
+        if let Some(position) = self.corrupt.iter().position(|&f| f == self.current) {
+            self.corrupt.remove(position);
+            frame[10] ^= 0x01;
+        }
+        self.queue.extend(frame);
+    }
+
+    fn waits_for_start(&self) -> bool {
+        self.current == 0 || self.current == 1 || self.current == self.frames.len() - 1
+    }
+}
+
+impl SerialPort for SimulatedSender {
+    fn write_byte(&mut self, byte: u8) {
+        self.received.push(byte);
+        if self.is_done() {
+            return;
+        }
+        match byte {
+            b'C' if self.waits_for_start() && self.queue.is_empty() => self.send_current(),
+            ACK => {
+                self.current += 1;
+                if !self.is_done() && !self.waits_for_start() {
+                    self.send_current();
+                }
+            }
+            NAK => self.send_current(),
+            _ => {}
+        }
+    }
+
+    fn read_byte(&mut self, _timeout: u32) -> Option<u8> {
+        self.queue.pop_front()
+    }
+}
+
+fn flash_memory() -> Vec<u8> {
+    vec![0xFF; FLASH_SIZE as usize]
+}
+
+fn image(binary: &[u8]) -> Vec<u8> {
+    let mut image = binary.to_vec();
+    image.extend_from_slice(&crc32(binary).to_le_bytes());
+    image
+}
+
+fn binary(length: usize) -> Vec<u8> {
+    (0..length).map(|i| (i * 7 + i / 256) as u8).collect()
+}
+
+#[test]
+fn crc_check_values() {
+    assert_eq!(crc16_xmodem(b"123456789"), 0x31C3);
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+}
+
+#[test]
+fn received_image_is_staged_and_applied_on_the_next_reset() {
+    let binary = binary(3001);
+    let mut memory = flash_memory();
+    let mut flash = SimulatedFlash::new(FLASH_START, &mut memory);
+    let mut sender = SimulatedSender::new("demo.img", &image(&binary)).corrupting(2);
+
+    let record = receive_update(&mut sender, &mut flash).unwrap();
+
+    let expected = UpdateRecord {
+        size: binary.len() as u32,
+        crc: crc32(&binary),
+    };
+    assert_eq!(record, expected);
+    assert!(sender.is_done());
+    assert!(sender.received.contains(&NAK));
+    assert_eq!(flash.contents(STAGING_ADDRESS, binary.len()), &binary[..]);
+    assert_eq!(pending_update(&flash), Some(expected));
+    assert_ne!(
+        flash.contents(APPLICATION_ADDRESS, binary.len()),
+        &binary[..]
+    );
+
+    apply_update(&mut flash, &record).unwrap();
+
+    assert_eq!(
+        flash.contents(APPLICATION_ADDRESS, binary.len()),
+        &binary[..]
+    );
+    assert_eq!(pending_update(&flash), None);
+}
+
+#[test]
+fn image_spanning_several_sectors_is_copied_completely() {
+    let binary = binary(300 * 1024 + 12);
+    let mut memory = flash_memory();
+    let mut flash = SimulatedFlash::new(FLASH_START, &mut memory);
+    let mut sender = SimulatedSender::new("demo.img", &image(&binary));
+
+    let record = receive_update(&mut sender, &mut flash).unwrap();
+    // Record sector and three staging sectors of 128 KB
+    assert_eq!(flash.erased_sectors(), 4);
+    apply_update(&mut flash, &record).unwrap();
+
+    assert_eq!(
+        flash.contents(APPLICATION_ADDRESS, binary.len()),
+        &binary[..]
+    );
+}
+
+#[test]
+fn image_with_wrong_crc_is_not_recorded() {
+    let binary = binary(2048);
+    let mut image = image(&binary);
+    *image.last_mut().unwrap() ^= 0xFF;
+    let mut memory = flash_memory();
+    let mut flash = SimulatedFlash::new(FLASH_START, &mut memory);
+    let mut sender = SimulatedSender::new("demo.img", &image);
+
+    assert_eq!(
+        receive_update(&mut sender, &mut flash),
+        Err(UpdateError::CrcMismatch)
+    );
+    assert_eq!(pending_update(&flash), None);
+}
+
+#[test]
+fn file_larger_than_the_staging_area_is_cancelled() {
+    let mut memory = flash_memory();
+    let mut flash = SimulatedFlash::new(FLASH_START, &mut memory);
+    let mut sender = SimulatedSender::new("demo.img", &vec![0; STAGING_SIZE as usize + 1]);
+
+    assert_eq!(
+        receive_update(&mut sender, &mut flash),
+        Err(UpdateError::Transfer(YmodemError::FileTooLarge))
+    );
+    assert!(sender.received.ends_with(&[CAN, CAN]));
+    assert_eq!(sender.current, 0);
+}
+
+#[test]
+fn silent_sender_times_out() {
+    let mut memory = flash_memory();
+    let mut flash = SimulatedFlash::new(FLASH_START, &mut memory);
+    let mut sender = SimulatedSender::new("demo.img", &[]);
+    sender.current = sender.frames.len();
+
+    assert_eq!(
+        receive_update(&mut sender, &mut flash),
+        Err(UpdateError::Transfer(YmodemError::Timeout))
+    );
+    assert!(sender.received.iter().all(|&byte| byte == b'C'));
+    // The bootloader retries every timeout, waiting must not wear out the record sector
+    assert_eq!(flash.erased_sectors(), 0);
+}
+
+#[test]
+fn corrupted_staging_area_drops_the_record() {
+    let binary = binary(1024);
+    let mut memory = flash_memory();
+    let record = {
+        let mut flash = SimulatedFlash::new(FLASH_START, &mut memory);
+        let mut sender = SimulatedSender::new("demo.img", &image(&binary));
+        receive_update(&mut sender, &mut flash).unwrap()
+    };
+    memory[(STAGING_ADDRESS - FLASH_START) as usize] ^= 0xFF;
+    let mut flash = SimulatedFlash::new(FLASH_START, &mut memory);
+
+    assert_eq!(
+        apply_update(&mut flash, &record),
+        Err(UpdateError::CrcMismatch)
+    );
+    assert_eq!(pending_update(&flash), None);
+    assert_eq!(flash.contents(APPLICATION_ADDRESS, 4), &[0xFF; 4]);
+}
+
+#[test]
+fn sender_cancel_stops_the_transfer() {
+    let mut memory = flash_memory();
+    let mut flash = SimulatedFlash::new(FLASH_START, &mut memory);
+    let mut sender = SimulatedSender::new("demo.img", &image(&binary(4096)));
+    sender.frames[2] = vec![CAN, CAN];
+
+    assert_eq!(
+        receive_update(&mut sender, &mut flash),
+        Err(UpdateError::Transfer(YmodemError::Cancelled))
+    );
+    assert_eq!(pending_update(&flash), None);
+}